@@ -3,22 +3,28 @@
 //! To support more CSS syntax, it would probably be easiest to replace this
 //! hand-rolled parser with one based on a library or parser generator.
 
-use std::ascii::OwnedAsciiExt; // for `into_ascii_lowercase`
+use std::ascii::{AsciiExt, OwnedAsciiExt}; // for `to_ascii_lowercase`, `into_ascii_lowercase`
 use std::iter::IteratorExt; // for `count`
 use std::str::FromStr;
-use std::num::FromStrRadix;
+use std::num::{Float, FromStrRadix};
+use style;
 
 // Data structures:
 
 #[derive(Show)]
 pub struct Stylesheet {
     pub rules: Vec<Rule>,
+    /// The names of the cascade layers declared by `@layer`, from lowest to highest precedence.
+    /// Anonymous layers have empty names.
+    pub layers: Vec<String>,
 }
 
 #[derive(Show)]
 pub struct Rule {
     pub selectors: Vec<Selector>,
     pub declarations: Vec<Declaration>,
+    /// The index in `Stylesheet::layers` of the cascade layer this rule is in, if any.
+    pub layer: Option<usize>,
 }
 
 #[derive(Show)]
@@ -31,24 +37,62 @@ pub struct SimpleSelector {
     pub tag_name: Option<String>,
     pub id: Option<String>,
     pub class: Vec<String>,
+    /// The pseudo-element this selector applies to, like `before` in `p::before`.
+    pub pseudo_element: Option<String>,
 }
 
 #[derive(Show)]
 pub struct Declaration {
     pub name: String,
     pub value: Value,
+    /// Set by `!important`.
+    pub important: bool,
 }
 
 #[derive(Show, Clone, PartialEq)]
 pub enum Value {
     Keyword(String),
     Length(f32, Unit),
+    /// A number without a unit.
+    Number(f32),
     ColorValue(Color),
+    /// Several space-separated values, like `2px 2px black`.
+    List(Vec<Value>),
+    /// A quoted string.
+    Str(String),
+    /// A function other than `rgb()`, like `var(--x, red)`, with one value per argument.
+    Function(String, Vec<Value>),
 }
 
 #[derive(Show, Clone, PartialEq)]
 pub enum Unit {
     Px,
+    Percent,
+    /// Multiples of the element's font size.
+    Em,
+    /// Multiples of the root element's font size.
+    Rem,
+    /// Percentages of the viewport's width.
+    Vw,
+    /// Percentages of the viewport's height.
+    Vh,
+    /// Inches. Like the other physical units, these are converted to px at
+    /// `LengthContext::px_per_inch`.
+    In,
+    /// Centimeters.
+    Cm,
+    /// Millimeters.
+    Mm,
+    /// Points, 1/72 of an inch.
+    Pt,
+    /// Picas, 12 points.
+    Pc,
+    /// Shares of the free space in a grid container, for track sizes.
+    Fr,
+    /// Degrees, for angles.
+    Deg,
+    /// Radians, for angles.
+    Rad,
 }
 
 #[derive(Show, Clone, PartialEq, Default)]
@@ -61,80 +105,385 @@ pub struct Color {
 
 impl Copy for Color {}
 
+impl Color {
+    /// Composite this color over `background`, using the "source-over" operator.
+    ///
+    /// http://www.w3.org/TR/compositing-1/#porterduffcompositingoperators_srcover
+    pub fn over(&self, background: &Color) -> Color {
+        let alpha = self.a as f32 / 255.0;
+        let background_alpha = background.a as f32 / 255.0 * (1.0 - alpha);
+        let result_alpha = alpha + background_alpha;
+        if result_alpha == 0.0 {
+            return Color { r: 0, g: 0, b: 0, a: 0 };
+        }
+        let channel = |&: c: u8, background_c: u8| {
+            let c = (c as f32 * alpha + background_c as f32 * background_alpha) / result_alpha;
+            c.round() as u8
+        };
+        Color {
+            r: channel(self.r, background.r),
+            g: channel(self.g, background.g),
+            b: channel(self.b, background.b),
+            a: (result_alpha * 255.0).round() as u8,
+        }
+    }
+
+    /// Interpolate between this color (at `t = 0`) and `other` (at `t = 1`).
+    ///
+    /// Each channel, including alpha, is interpolated separately. `t` is clamped to `[0, 1]`.
+    pub fn lerp(&self, other: &Color, t: f32) -> Color {
+        let t = t.max(0.0).min(1.0);
+        let channel = |&: a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+        Color {
+            r: channel(self.r, other.r),
+            g: channel(self.g, other.g),
+            b: channel(self.b, other.b),
+            a: channel(self.a, other.a),
+        }
+    }
+}
+
 pub type Specificity = (usize, usize, usize);
 
+impl Stylesheet {
+    /// Append the rules of `other` after this stylesheet's rules.
+    ///
+    /// Rules keep their relative order, so on equal specificity the appended rules win. Named
+    /// layers of `other` are merged with layers of the same name in this stylesheet, and any
+    /// others are added after this stylesheet's layers.
+    pub fn extend(&mut self, other: Stylesheet) {
+        let layers: Vec<usize> = other.layers.into_iter().map(|name| {
+            match self.layers.iter().position(|layer| !name.is_empty() && *layer == name) {
+                Some(i) => i,
+                None => {
+                    self.layers.push(name);
+                    self.layers.len() - 1
+                }
+            }
+        }).collect();
+        for mut rule in other.rules.into_iter() {
+            rule.layer = rule.layer.map(|i| layers[i]);
+            self.rules.push(rule);
+        }
+    }
+}
+
+/// Concatenate several stylesheets into one, in cascade order (e.g. UA, user, author).
+pub fn merge(sheets: Vec<Stylesheet>) -> Stylesheet {
+    let mut merged = Stylesheet { rules: Vec::new(), layers: Vec::new() };
+    for sheet in sheets.into_iter() {
+        merged.extend(sheet);
+    }
+    return merged;
+}
+
 impl Selector {
     pub fn specificity(&self) -> Specificity {
         // http://www.w3.org/TR/selectors/#specificity
         let Selector::Simple(ref simple) = *self;
         let a = simple.id.iter().count();
         let b = simple.class.len();
-        let c = simple.tag_name.iter().count();
+        let c = simple.tag_name.iter().count() + simple.pseudo_element.iter().count();
         (a, b, c)
     }
 }
 
 impl Value {
     /// Return the size of a length in px, or zero for non-lengths.
+    ///
+    /// `min()`, `max()`, and `clamp()` are evaluated if all of their arguments are lengths in px.
     pub fn to_px(&self) -> f32 {
+        if !self.is_px() {
+            return 0.0;
+        }
         match *self {
             Value::Length(f, Unit::Px) => f,
+            Value::Function(ref name, ref args) => {
+                let lengths = args.iter().map(|arg| arg.to_px());
+                match &**name {
+                    "min" => lengths.fold(Float::infinity(), |a: f32, b| a.min(b)),
+                    "max" => lengths.fold(Float::neg_infinity(), |a: f32, b| a.max(b)),
+                    // clamp(MIN, VAL, MAX) is max(MIN, min(VAL, MAX)).
+                    _ => args[0].to_px().max(args[1].to_px().min(args[2].to_px()))
+                }
+            }
             _ => 0.0
         }
     }
+
+    /// Is this a length in px, or a `min()`, `max()`, or `clamp()` of lengths in px?
+    pub fn is_px(&self) -> bool {
+        match *self {
+            Value::Length(_, Unit::Px) => true,
+            Value::Function(ref name, ref args) => {
+                let arity_ok = match &**name {
+                    "min" | "max" => !args.is_empty(),
+                    "clamp" => args.len() == 3,
+                    _ => false
+                };
+                arity_ok && args.iter().all(|arg| arg.is_px())
+            }
+            _ => false
+        }
+    }
 }
 
+/// A problem found while parsing a stylesheet.
+#[derive(Show, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    /// Line number where the problem was found, starting at 1.
+    pub line: usize,
+    /// Column number in characters, starting at 1.
+    pub column: usize,
+}
+
+pub type ParseResult<T> = Result<T, ParseError>;
+
 /// Parse a whole CSS stylesheet.
 pub fn parse(source: String) -> Stylesheet {
-    let mut parser = Parser { pos: 0, input: source };
-    Stylesheet { rules: parser.parse_rules() }
+    let (stylesheet, _) = parse_with_errors(source);
+    stylesheet
+}
+
+/// Parse a whole CSS stylesheet, and return any errors found along with it.
+///
+/// A malformed declaration is skipped up to the next `;`, and a malformed selector list skips
+/// its whole rule. Parsing then carries on with the rest of the stylesheet.
+pub fn parse_with_errors(source: String) -> (Stylesheet, Vec<ParseError>) {
+    let mut parser = Parser { pos: 0, input: source, errors: Vec::new(), layers: Vec::new() };
+    let rules = parser.parse_rules(None, false);
+    (Stylesheet { rules: rules, layers: parser.layers }, parser.errors)
 }
 
 struct Parser {
     pos: usize,
     input: String,
+    errors: Vec<ParseError>,
+    /// The cascade layers declared so far, in order.
+    layers: Vec<String>,
 }
 
 impl Parser {
-    /// Parse a list of rule sets, separated by optional whitespace.
-    fn parse_rules(&mut self) -> Vec<Rule> {
+    /// Parse a list of rule sets and at-rules, separated by optional whitespace. Rule sets are
+    /// put in cascade layer `layer`.
+    ///
+    /// If `in_block` is set, this stops before the `}` that ends the enclosing block.
+    fn parse_rules(&mut self, layer: Option<usize>, in_block: bool) -> Vec<Rule> {
         let mut rules = Vec::new();
         loop {
             self.consume_whitespace();
             if self.eof() { break }
-            rules.push(self.parse_rule());
+            let result = match self.next_char() {
+                '}' if in_block => break,
+                '}' => {
+                    let error = self.unexpected("outside of a block");
+                    self.errors.push(error);
+                    self.consume_char();
+                    continue;
+                }
+                '@' => self.parse_at_rule(layer).map(|at_rules| rules.extend(at_rules.into_iter())),
+                _ => self.parse_rule().map(|rule| rules.push(Rule { layer: layer, ..rule }))
+            };
+            if let Err(error) = result {
+                self.errors.push(error);
+                self.skip_rule();
+            }
         }
         return rules;
     }
 
+    /// Parse an at-rule inside cascade layer `layer`, and return the rule sets inside it that
+    /// apply.
+    ///
+    /// Only `@layer` and `@supports` are supported. Other at-rules are errors.
+    fn parse_at_rule(&mut self, layer: Option<usize>) -> ParseResult<Vec<Rule>> {
+        let start = self.pos;
+        assert!(self.consume_char() == '@');
+        let name = self.parse_identifier().into_ascii_lowercase();
+        self.consume_whitespace();
+        match &*name {
+            "layer" => self.parse_layer_rule(),
+            "supports" => self.parse_supports_rule(layer),
+            _ => Err(self.error_at(start, format!("unsupported at-rule @{}", name)))
+        }
+    }
+
+    /// Parse the rest of a `@supports` rule, after the name. The rules in its block are returned
+    /// only if this engine supports its condition; see `style::supports`.
+    ///
+    /// http://www.w3.org/TR/css3-conditional/#at-supports
+    fn parse_supports_rule(&mut self, layer: Option<usize>) -> ParseResult<Vec<Rule>> {
+        let supported = try!(self.parse_supports_condition());
+        self.consume_whitespace();
+        if self.eof() || self.next_char() != '{' {
+            return Err(self.unexpected("in @supports rule"));
+        }
+        self.consume_char();
+        let rules = self.parse_rules(layer, true);
+        try!(self.expect('}', "at end of @supports block"));
+        Ok(if supported { rules } else { Vec::new() })
+    }
+
+    /// Parse and evaluate a `@supports` condition, like `(display: grid) and (float: left)`.
+    ///
+    /// A mix of `and` and `or` without parentheses is invalid CSS, but is accepted here and
+    /// evaluated from left to right.
+    fn parse_supports_condition(&mut self) -> ParseResult<bool> {
+        if self.parse_keyword("not") {
+            return self.parse_supports_in_parens().map(|supported| !supported);
+        }
+        let mut supported = try!(self.parse_supports_in_parens());
+        loop {
+            self.consume_whitespace();
+            if self.parse_keyword("and") {
+                let other = try!(self.parse_supports_in_parens());
+                supported = supported && other;
+            } else if self.parse_keyword("or") {
+                let other = try!(self.parse_supports_in_parens());
+                supported = supported || other;
+            } else {
+                return Ok(supported);
+            }
+        }
+    }
+
+    /// Parse and evaluate a parenthesized `@supports` condition or declaration, like
+    /// `(display: grid)`.
+    fn parse_supports_in_parens(&mut self) -> ParseResult<bool> {
+        try!(self.expect('(', "in @supports condition"));
+        self.consume_whitespace();
+        let supported = if !self.eof() && self.next_char() == '(' {
+            try!(self.parse_supports_condition())
+        } else if self.parse_keyword("not") {
+            !try!(self.parse_supports_in_parens())
+        } else {
+            let name = self.parse_identifier().into_ascii_lowercase();
+            self.consume_whitespace();
+            try!(self.expect(':', "in @supports condition"));
+            self.consume_whitespace();
+            let value = try!(self.parse_value_until(&[')']));
+            style::supports(&*name, &value)
+        };
+        self.consume_whitespace();
+        try!(self.expect(')', "in @supports condition"));
+        Ok(supported)
+    }
+
+    /// If the next identifier is `keyword` (in any case), consume it and any whitespace after it
+    /// and return true. Otherwise consume nothing.
+    fn parse_keyword(&mut self, keyword: &str) -> bool {
+        let start = self.pos;
+        if self.parse_identifier().into_ascii_lowercase() == keyword {
+            self.consume_whitespace();
+            true
+        } else {
+            self.pos = start;
+            false
+        }
+    }
+
+    /// Parse the rest of a `@layer` rule, after the name: either a statement like `@layer a, b;`
+    /// that declares layers in order, or a block like `@layer a { ... }` of rules in a layer. A
+    /// block with no layer name gets a new anonymous layer.
+    ///
+    /// http://www.w3.org/TR/css-cascade-5/#layering
+    ///
+    /// Layers are ordered by where they are first declared, and later layers win.
+    ///
+    /// TODO: Nested layers. Layers declared inside a layer block are treated as top-level.
+    fn parse_layer_rule(&mut self) -> ParseResult<Vec<Rule>> {
+        let mut names = Vec::new();
+        loop {
+            let name = self.parse_identifier();
+            if name.is_empty() {
+                break;
+            }
+            names.push(name);
+            self.consume_whitespace();
+            if self.eof() || self.next_char() != ',' {
+                break;
+            }
+            self.consume_char();
+            self.consume_whitespace();
+        }
+        if self.eof() {
+            return Err(self.unexpected("in @layer rule"));
+        }
+        match self.next_char() {
+            ';' if !names.is_empty() => {
+                self.consume_char();
+                for name in names.into_iter() {
+                    self.layer_index(name);
+                }
+                Ok(Vec::new())
+            }
+            '{' if names.len() <= 1 => {
+                let layer = match names.pop() {
+                    Some(name) => self.layer_index(name),
+                    None => {
+                        self.layers.push(String::new());
+                        self.layers.len() - 1
+                    }
+                };
+                self.consume_char();
+                let rules = self.parse_rules(Some(layer), true);
+                try!(self.expect('}', "at end of @layer block"));
+                Ok(rules)
+            }
+            _ => Err(self.unexpected("in @layer rule"))
+        }
+    }
+
+    /// The index of the layer called `name`, declaring it if this is the first mention.
+    fn layer_index(&mut self, name: String) -> usize {
+        match self.layers.iter().position(|layer| *layer == name) {
+            Some(i) => i,
+            None => {
+                self.layers.push(name);
+                self.layers.len() - 1
+            }
+        }
+    }
+
     /// Parse a rule set: `<selectors> { <declarations> }`.
-    fn parse_rule(&mut self) -> Rule {
-        Rule {
-            selectors: self.parse_selectors(),
+    fn parse_rule(&mut self) -> ParseResult<Rule> {
+        let selectors = try!(self.parse_selectors());
+        Ok(Rule {
+            selectors: selectors,
             declarations: self.parse_declarations(),
-        }
+            layer: None,
+        })
     }
 
     /// Parse a comma-separated list of selectors.
-    fn parse_selectors(&mut self) -> Vec<Selector> {
+    fn parse_selectors(&mut self) -> ParseResult<Vec<Selector>> {
         let mut selectors = Vec::new();
         loop {
             selectors.push(Selector::Simple(self.parse_simple_selector()));
             self.consume_whitespace();
+            if self.eof() {
+                return Err(self.unexpected("in selector list"));
+            }
             match self.next_char() {
                 ',' => { self.consume_char(); self.consume_whitespace(); }
                 '{' => break,
-                c   => panic!("Unexpected character {} in selector list", c)
+                _   => return Err(self.unexpected("in selector list"))
             }
         }
         // Return selectors with highest specificity first, for use in matching.
         selectors.sort_by(|a,b| b.specificity().cmp(&a.specificity()));
-        return selectors;
+        return Ok(selectors);
     }
 
     /// Parse one simple selector, e.g.: `type#id.class1.class2.class3`
     fn parse_simple_selector(&mut self) -> SimpleSelector {
-        let mut selector = SimpleSelector { tag_name: None, id: None, class: Vec::new() };
+        let mut selector = SimpleSelector {
+            tag_name: None,
+            id: None,
+            class: Vec::new(),
+            pseudo_element: None,
+        };
         while !self.eof() {
             match self.next_char() {
                 '#' => {
@@ -149,8 +498,19 @@ impl Parser {
                     // universal selector
                     self.consume_char();
                 }
+                ':' => {
+                    // Pseudo-elements may be written with one colon or two, for compatibility
+                    // with CSS 2. Pseudo-classes aren't supported, and are parsed the same way as
+                    // an unknown pseudo-element that never matches.
+                    self.consume_char();
+                    if !self.eof() && self.next_char() == ':' {
+                        self.consume_char();
+                    }
+                    selector.pseudo_element = Some(self.parse_identifier().into_ascii_lowercase());
+                }
                 c if valid_identifier_char(c) => {
-                    selector.tag_name = Some(self.parse_identifier());
+                    // HTML tag names are case-insensitive, and stored in lowercase.
+                    selector.tag_name = Some(self.parse_identifier().into_ascii_lowercase());
                 }
                 _ => break
             }
@@ -159,80 +519,283 @@ impl Parser {
     }
 
     /// Parse a list of declarations enclosed in `{ ... }`.
+    ///
+    /// Malformed declarations are recorded as errors and skipped. Each property appears at most
+    /// once in the result; see `add_declaration`.
     fn parse_declarations(&mut self) -> Vec<Declaration> {
         assert!(self.consume_char() == '{');
         let mut declarations = Vec::new();
         loop {
             self.consume_whitespace();
+            if self.eof() {
+                let error = self.unexpected("in declaration block");
+                self.errors.push(error);
+                break;
+            }
             if self.next_char() == '}' {
                 self.consume_char();
                 break;
             }
-            declarations.push(self.parse_declaration());
+            match self.parse_declaration() {
+                Ok(declaration) => add_declaration(&mut declarations, declaration),
+                Err(error) => {
+                    self.errors.push(error);
+                    self.skip_declaration();
+                }
+            }
         }
         return declarations;
     }
 
-    /// Parse one `<property>: <value>;` declaration.
-    fn parse_declaration(&mut self) -> Declaration {
+    /// Parse one `<property>: <value> [!important];` declaration.
+    ///
+    /// The semicolon may be left out after the last declaration in a block.
+    fn parse_declaration(&mut self) -> ParseResult<Declaration> {
         let property_name = self.parse_identifier();
+        if property_name.is_empty() {
+            return Err(self.unexpected("in property name"));
+        }
+        self.consume_whitespace();
+        try!(self.expect(':', "after property name"));
         self.consume_whitespace();
-        assert!(self.consume_char() == ':');
+        let value = try!(self.parse_value());
         self.consume_whitespace();
-        let value = self.parse_value();
+        let important = try!(self.parse_important());
         self.consume_whitespace();
-        assert!(self.consume_char() == ';');
+        if self.eof() || self.next_char() != '}' {
+            try!(self.expect(';', "after value"));
+        }
 
-        Declaration {
+        Ok(Declaration {
             name: property_name,
             value: value,
+            important: important,
+        })
+    }
+
+    /// Parse an optional `!important` annotation.
+    fn parse_important(&mut self) -> ParseResult<bool> {
+        if self.eof() || self.next_char() != '!' {
+            return Ok(false);
+        }
+        self.consume_char();
+        self.consume_whitespace();
+        let start = self.pos;
+        match &*self.parse_identifier().into_ascii_lowercase() {
+            "important" => Ok(true),
+            _ => Err(self.error_at(start, "expected 'important' after '!'".to_string()))
         }
     }
 
     // Methods for parsing values:
 
-    fn parse_value(&mut self) -> Value {
+    /// Parse a declaration's value: one or more component values separated by whitespace.
+    fn parse_value(&mut self) -> ParseResult<Value> {
+        self.parse_value_until(&[';', '}', '!'])
+    }
+
+    /// Parse one or more component values separated by whitespace, up to one of `terminators`.
+    fn parse_value_until(&mut self, terminators: &[char]) -> ParseResult<Value> {
+        let mut values = vec![try!(self.parse_component_value())];
+        loop {
+            self.consume_whitespace();
+            if self.eof() { break }
+            let c = self.next_char();
+            if terminators.contains(&c) {
+                break;
+            }
+            values.push(try!(self.parse_component_value()));
+        }
+        if values.len() == 1 {
+            Ok(values.pop().unwrap())
+        } else {
+            Ok(Value::List(values))
+        }
+    }
+
+    /// Parse a single value, like `10px`, `#ff0000`, `red`, or `rgb(255, 0, 0)`.
+    fn parse_component_value(&mut self) -> ParseResult<Value> {
+        if self.eof() {
+            return Err(self.unexpected("in value"));
+        }
+        if self.starts_number() {
+            return self.parse_length();
+        }
         match self.next_char() {
-            '0'...'9' => self.parse_length(),
             '#' => self.parse_color(),
-            _ => Value::Keyword(self.parse_identifier())
+            '"' | '\'' => self.parse_string(),
+            // A separator, as in `aspect-ratio: 16 / 9`.
+            '/' => { self.consume_char(); Ok(Value::Keyword("/".to_string())) }
+            c if valid_identifier_char(c) => {
+                let start = self.pos;
+                let name = self.parse_identifier();
+                if !self.eof() && self.next_char() == '(' {
+                    if name.to_ascii_lowercase() == "url" {
+                        self.parse_url()
+                    } else {
+                        self.parse_function(start, name)
+                    }
+                } else {
+                    Ok(match named_color(&*name) {
+                        Some(color) => Value::ColorValue(color),
+                        None => Value::Keyword(name)
+                    })
+                }
+            }
+            _ => Err(self.unexpected("in value"))
         }
     }
 
-    fn parse_length(&mut self) -> Value {
-        Value::Length(self.parse_float(), self.parse_unit())
+    /// Parse a number, with or without a unit.
+    fn parse_length(&mut self) -> ParseResult<Value> {
+        let f = try!(self.parse_float());
+        if self.eof() || !(self.next_char() == '%' || valid_identifier_char(self.next_char())) {
+            return Ok(Value::Number(f));
+        }
+        let unit = try!(self.parse_unit());
+        Ok(Value::Length(f, unit))
     }
 
-    fn parse_float(&mut self) -> f32 {
-        let s = self.consume_while(|c| match c {
+    /// Is the next thing in the input a number, possibly negative?
+    fn starts_number(&self) -> bool {
+        let mut chars = self.input[self.pos ..].chars();
+        match chars.next() {
+            Some('0'...'9') | Some('.') => true,
+            Some('-') => match chars.next() {
+                Some('0'...'9') | Some('.') => true,
+                _ => false
+            },
+            _ => false
+        }
+    }
+
+    fn parse_float(&mut self) -> ParseResult<f32> {
+        let start = self.pos;
+        let mut s = String::new();
+        if self.next_char() == '-' {
+            s.push(self.consume_char());
+        }
+        s.push_str(&*self.consume_while(|c| match c {
             '0'...'9' | '.' => true,
             _ => false
-        });
+        }));
         let f: Option<f32> = FromStr::from_str(&*s);
-        f.unwrap()
+        match f {
+            Some(f) => Ok(f),
+            None => Err(self.error_at(start, format!("invalid number '{}'", s)))
+        }
     }
 
-    fn parse_unit(&mut self) -> Unit {
-        match &*self.parse_identifier().into_ascii_lowercase() {
-            "px" => Unit::Px,
-            _ => panic!("unrecognized unit")
+    fn parse_unit(&mut self) -> ParseResult<Unit> {
+        if !self.eof() && self.next_char() == '%' {
+            self.consume_char();
+            return Ok(Unit::Percent);
+        }
+        let start = self.pos;
+        let unit = self.parse_identifier().into_ascii_lowercase();
+        match &*unit {
+            "px" => Ok(Unit::Px),
+            "em" => Ok(Unit::Em),
+            "rem" => Ok(Unit::Rem),
+            "vw" => Ok(Unit::Vw),
+            "vh" => Ok(Unit::Vh),
+            "in" => Ok(Unit::In),
+            "cm" => Ok(Unit::Cm),
+            "mm" => Ok(Unit::Mm),
+            "pt" => Ok(Unit::Pt),
+            "pc" => Ok(Unit::Pc),
+            "fr" => Ok(Unit::Fr),
+            "deg" => Ok(Unit::Deg),
+            "rad" => Ok(Unit::Rad),
+            _ => Err(self.error_at(start, format!("unrecognized unit '{}'", unit)))
         }
     }
 
-    fn parse_color(&mut self) -> Value {
+    fn parse_color(&mut self) -> ParseResult<Value> {
         assert!(self.consume_char() == '#');
-        Value::ColorValue(Color {
-            r: self.parse_hex_pair(),
-            g: self.parse_hex_pair(),
-            b: self.parse_hex_pair(),
-            a: 255 })
+        Ok(Value::ColorValue(Color {
+            r: try!(self.parse_hex_pair()),
+            g: try!(self.parse_hex_pair()),
+            b: try!(self.parse_hex_pair()),
+            a: 255 }))
+    }
+
+    /// Parse the arguments of a function like `rgb(255, 0, 0)`, after its name. `start` is where
+    /// the name began.
+    ///
+    /// Each argument may be a space-separated list of values.
+    fn parse_function(&mut self, start: usize, name: String) -> ParseResult<Value> {
+        assert!(self.consume_char() == '(');
+        let mut args = Vec::new();
+        loop {
+            self.consume_whitespace();
+            args.push(try!(self.parse_value_until(&[',', ')'])));
+            self.consume_whitespace();
+            if self.eof() {
+                return Err(self.unexpected("in function arguments"));
+            }
+            match self.next_char() {
+                ',' => { self.consume_char(); }
+                ')' => { self.consume_char(); break; }
+                _ => return Err(self.unexpected("in function arguments"))
+            }
+        }
+
+        match &*name.into_ascii_lowercase() {
+            "rgb" | "rgba" => match rgb_function(&*args) {
+                Some(color) => Ok(Value::ColorValue(color)),
+                None => Err(self.error_at(start, "invalid arguments to rgb()".to_string()))
+            },
+            name if FUNCTIONS.contains(&name) => Ok(Value::Function(name.to_string(), args)),
+            name => Err(self.error_at(start, format!("unknown function {}()", name)))
+        }
+    }
+
+    /// Parse the argument of `url(...)`, which may be quoted or not, after the name. The URL is
+    /// returned as `url("...")`.
+    fn parse_url(&mut self) -> ParseResult<Value> {
+        assert!(self.consume_char() == '(');
+        self.consume_whitespace();
+        let url = if !self.eof() && (self.next_char() == '"' || self.next_char() == '\'') {
+            try!(self.parse_string())
+        } else {
+            Value::Str(self.consume_while(|c| c != ')' && !c.is_whitespace()))
+        };
+        self.consume_whitespace();
+        try!(self.expect(')', "after URL"));
+        Ok(Value::Function("url".to_string(), vec![url]))
+    }
+
+    /// Parse a string in single or double quotes. A backslash escapes the next character.
+    fn parse_string(&mut self) -> ParseResult<Value> {
+        let start = self.pos;
+        let quote = self.consume_char();
+        let mut s = String::new();
+        loop {
+            if self.eof() {
+                return Err(self.error_at(start, "unterminated string".to_string()));
+            }
+            match self.consume_char() {
+                c if c == quote => break,
+                '\\' if !self.eof() => s.push(self.consume_char()),
+                c => s.push(c)
+            }
+        }
+        Ok(Value::Str(s))
     }
 
     /// Parse two hexadecimal digits.
-    fn parse_hex_pair(&mut self) -> u8 {
-        let s = &self.input[self.pos .. self.pos + 2];
-        self.pos = self.pos + 2;
-        FromStrRadix::from_str_radix(s, 0x10).unwrap()
+    fn parse_hex_pair(&mut self) -> ParseResult<u8> {
+        let start = self.pos;
+        let mut s = String::new();
+        while s.len() < 2 && !self.eof() && self.next_char().is_digit(16) {
+            s.push(self.consume_char());
+        }
+        if s.len() < 2 {
+            return Err(self.error_at(start,
+                                     "expected two hexadecimal digits in color".to_string()));
+        }
+        Ok(FromStrRadix::from_str_radix(&*s, 0x10).unwrap())
     }
 
     /// Parse a property name or keyword.
@@ -240,6 +803,60 @@ impl Parser {
         self.consume_while(valid_identifier_char)
     }
 
+    // Methods for error handling and recovery:
+
+    /// Consume `expected` if it is the next character, or return an error.
+    fn expect(&mut self, expected: char, context: &str) -> ParseResult<()> {
+        if !self.eof() && self.next_char() == expected {
+            self.consume_char();
+            return Ok(());
+        }
+        Err(self.error_at(self.pos, format!("expected '{}' {}", expected, context)))
+    }
+
+    /// An error about whatever is at the current position.
+    fn unexpected(&self, context: &str) -> ParseError {
+        let message = if self.eof() {
+            format!("unexpected end of input {}", context)
+        } else {
+            format!("unexpected character '{}' {}", self.next_char(), context)
+        };
+        self.error_at(self.pos, message)
+    }
+
+    /// An error at byte offset `pos` in the input.
+    fn error_at(&self, pos: usize, message: String) -> ParseError {
+        let before = &self.input[.. pos];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        ParseError {
+            message: message,
+            line: before.chars().filter(|&c| c == '\n').count() + 1,
+            column: before[line_start ..].chars().count() + 1,
+        }
+    }
+
+    /// Skip the rest of a malformed declaration, up to and including its `;`.
+    ///
+    /// Stops before a `}`, so the end of the block is still seen.
+    fn skip_declaration(&mut self) {
+        while !self.eof() {
+            match self.next_char() {
+                '}' => break,
+                ';' => { self.consume_char(); break; }
+                _ => { self.consume_char(); }
+            }
+        }
+    }
+
+    /// Skip the rest of a malformed rule, up to and including its `}`.
+    fn skip_rule(&mut self) {
+        while !self.eof() {
+            if self.consume_char() == '}' {
+                break;
+            }
+        }
+    }
+
     /// Consume and discard zero or more whitespace characters.
     fn consume_whitespace(&mut self) {
         self.consume_while(CharExt::is_whitespace);
@@ -273,9 +890,233 @@ impl Parser {
     }
 }
 
+/// The functions other than `rgb()` that values may use. Any other function is a parse error.
+static FUNCTIONS: &'static [&'static str] = &[
+    "var", "counter", "attr", "translate", "translatex", "translatey", "scale", "scalex", "scaley",
+    "rotate", "min", "max", "clamp",
+];
+
+/// The color with the given name, for the basic CSS color keywords.
+///
+/// http://www.w3.org/TR/css3-color/#html4
+fn named_color(name: &str) -> Option<Color> {
+    let (r, g, b) = match &*name.to_ascii_lowercase() {
+        "black" => (0, 0, 0),
+        "silver" => (192, 192, 192),
+        "gray" => (128, 128, 128),
+        "white" => (255, 255, 255),
+        "maroon" => (128, 0, 0),
+        "red" => (255, 0, 0),
+        "purple" => (128, 0, 128),
+        "fuchsia" => (255, 0, 255),
+        "green" => (0, 128, 0),
+        "lime" => (0, 255, 0),
+        "olive" => (128, 128, 0),
+        "yellow" => (255, 255, 0),
+        "navy" => (0, 0, 128),
+        "blue" => (0, 0, 255),
+        "teal" => (0, 128, 128),
+        "aqua" => (0, 255, 255),
+        _ => return None
+    };
+    Some(Color { r: r, g: g, b: b, a: 255 })
+}
+
+/// The color given by the arguments of `rgb()` or `rgba()`: three channels from 0 to 255, and an
+/// optional alpha from 0 to 1.
+fn rgb_function(args: &[Value]) -> Option<Color> {
+    let mut numbers = Vec::new();
+    for arg in args.iter() {
+        match *arg {
+            Value::Number(n) => numbers.push(n),
+            _ => return None
+        }
+    }
+    let channel = |&: n: f32| n.max(0.0).min(255.0).round() as u8;
+    let alpha = match numbers.len() {
+        3 => 1.0,
+        4 => numbers[3],
+        _ => return None
+    };
+    Some(Color {
+        r: channel(numbers[0]),
+        g: channel(numbers[1]),
+        b: channel(numbers[2]),
+        a: channel(alpha * 255.0),
+    })
+}
+
+/// Add a declaration to a block, replacing any earlier declaration of the same property.
+///
+/// The later declaration wins, unless only the earlier one is `!important`.
+fn add_declaration(declarations: &mut Vec<Declaration>, declaration: Declaration) {
+    let earlier = declarations.iter().position(|d| d.name == declaration.name);
+    if let Some(index) = earlier {
+        if declarations[index].important && !declaration.important {
+            return;
+        }
+        declarations.remove(index);
+    }
+    declarations.push(declaration);
+}
+
 fn valid_identifier_char(c: char) -> bool {
     match c {
         'a'...'z' | 'A'...'Z' | '0'...'9' | '-' | '_' => true, // TODO: Include U+00A0 and higher.
         _ => false,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn declared_value(rule: &Rule) -> Value {
+        rule.declarations[0].value.clone()
+    }
+
+    #[test]
+    fn merge_keeps_cascade_order() {
+        let ua = parse("p { display: block; }".to_string());
+        let user = parse("p { display: inline; }".to_string());
+        let author = parse("p { display: none; } div { display: block; }".to_string());
+        let merged = merge(vec![ua, user, author]);
+
+        let values: Vec<Value> = merged.rules.iter().map(declared_value).collect();
+        assert_eq!(values, vec![Value::Keyword("block".to_string()),
+                                Value::Keyword("inline".to_string()),
+                                Value::Keyword("none".to_string()),
+                                Value::Keyword("block".to_string())]);
+    }
+
+    #[test]
+    fn extend_appends_after_existing_rules() {
+        let mut sheet = parse("p { display: block; }".to_string());
+        sheet.extend(parse("p { display: none; }".to_string()));
+        assert_eq!(sheet.rules.len(), 2);
+        assert_eq!(declared_value(&sheet.rules[1]), Value::Keyword("none".to_string()));
+    }
+
+    #[test]
+    fn layers_are_ordered_by_first_declaration() {
+        let (sheet, errors) = parse_with_errors("@layer base, theme; \
+                                                 @layer theme { p { display: none; } } \
+                                                 @layer { p { display: inline; } } \
+                                                 @layer base { p { display: block; } } \
+                                                 p { display: flex; }".to_string());
+        assert!(errors.is_empty());
+        assert_eq!(sheet.layers, vec!["base", "theme", ""]);
+        let layers: Vec<Option<usize>> = sheet.rules.iter().map(|rule| rule.layer).collect();
+        assert_eq!(layers, vec![Some(1), Some(2), Some(0), None]);
+    }
+
+    /// The number of rules kept from a `@supports` block with the given condition.
+    fn supported_rules(condition: &str) -> usize {
+        let (sheet, errors) = parse_with_errors(format!("@supports {} {{ p {{ display: none; }} }}",
+                                                        condition));
+        assert!(errors.is_empty());
+        sheet.rules.len()
+    }
+
+    #[test]
+    fn supports_keeps_rules_for_implemented_features() {
+        assert_eq!(supported_rules("(display: grid)"), 1);
+        assert_eq!(supported_rules("(display: ruby)"), 0);
+        assert_eq!(supported_rules("(overflow: hidden)"), 1);
+        assert_eq!(supported_rules("(border: 1px solid)"), 1);
+        assert_eq!(supported_rules("(margin: 0 auto)"), 1);
+        assert_eq!(supported_rules("(z-index: 2)"), 1);
+        assert_eq!(supported_rules("(mask: none)"), 0);
+        assert_eq!(supported_rules("(--anything: 1px)"), 1);
+        assert_eq!(supported_rules("not (display: ruby)"), 1);
+        assert_eq!(supported_rules("(display: grid) and (float: center)"), 0);
+        assert_eq!(supported_rules("(display: ruby) or ((display: flex) and (gap: 1px))"), 1);
+    }
+
+    #[test]
+    fn extend_merges_layers_with_the_same_name() {
+        let mut sheet = parse("@layer a { p { display: block; } }".to_string());
+        sheet.extend(parse("@layer b, a; @layer a { p { display: none; } }".to_string()));
+        assert_eq!(sheet.layers, vec!["a", "b"]);
+        assert_eq!(sheet.rules[1].layer, Some(0));
+    }
+
+    #[test]
+    fn errors_report_line_and_column() {
+        let (sheet, errors) = parse_with_errors("p {\n  width: 10qq;\n  height: 5px;\n}"
+                                                .to_string());
+        assert_eq!(errors, vec![ParseError {
+            message: "unrecognized unit 'qq'".to_string(),
+            line: 2,
+            column: 12,
+        }]);
+        // The rest of the block is still parsed.
+        assert_eq!(sheet.rules[0].declarations.len(), 1);
+        assert_eq!(&*sheet.rules[0].declarations[0].name, "height");
+    }
+
+    #[test]
+    fn malformed_selector_skips_its_rule() {
+        let (sheet, errors) = parse_with_errors("p! { display: none; }\ndiv { display: block; }"
+                                                .to_string());
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].line, errors[0].column), (1, 2));
+        assert_eq!(sheet.rules.len(), 1);
+        assert_eq!(declared_value(&sheet.rules[0]), Value::Keyword("block".to_string()));
+    }
+
+    #[test]
+    fn unterminated_block_is_an_error() {
+        let (sheet, errors) = parse_with_errors("p { display: none;".to_string());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(declared_value(&sheet.rules[0]), Value::Keyword("none".to_string()));
+    }
+
+    #[test]
+    fn important_declaration_wins_within_a_block() {
+        let sheet = parse("p { display: none ! IMPORTANT; display: block; width: 1px; \
+                           width: 2px; }".to_string());
+        let declarations = &sheet.rules[0].declarations;
+        assert_eq!(declarations.len(), 2);
+        assert_eq!(&*declarations[0].name, "display");
+        assert_eq!(declarations[0].value, Value::Keyword("none".to_string()));
+        assert!(declarations[0].important);
+        assert_eq!(declarations[1].value, Value::Length(2.0, Unit::Px));
+        assert!(!declarations[1].important);
+    }
+
+    #[test]
+    fn bang_without_important_is_an_error() {
+        let (sheet, errors) = parse_with_errors("p { display: none !urgent; }".to_string());
+        assert_eq!(errors.len(), 1);
+        assert!(sheet.rules[0].declarations.is_empty());
+    }
+
+    fn rgba(r: u8, g: u8, b: u8, a: u8) -> Color {
+        Color { r: r, g: g, b: b, a: a }
+    }
+
+    #[test]
+    fn half_transparent_red_over_white() {
+        let white = rgba(255, 255, 255, 255);
+        assert_eq!(rgba(255, 0, 0, 128).over(&white), rgba(255, 127, 127, 255));
+    }
+
+    #[test]
+    fn over_opaque_and_transparent_colors() {
+        let white = rgba(255, 255, 255, 255);
+        let blue = rgba(0, 0, 255, 255);
+        assert_eq!(blue.over(&white), blue);
+        assert_eq!(rgba(0, 0, 0, 0).over(&blue), blue);
+        assert_eq!(rgba(0, 0, 0, 0).over(&rgba(0, 0, 0, 0)), rgba(0, 0, 0, 0));
+    }
+
+    #[test]
+    fn lerp_clamps_t() {
+        let black = rgba(0, 0, 0, 255);
+        let white = rgba(255, 255, 255, 255);
+        assert_eq!(black.lerp(&white, 0.5), rgba(128, 128, 128, 255));
+        assert_eq!(black.lerp(&white, 2.0), white);
+        assert_eq!(black.lerp(&white, -1.0), black);
+    }
+}
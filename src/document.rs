@@ -0,0 +1,235 @@
+//! A parsed document, for laying out more than once.
+//!
+//! Parsing doesn't depend on the viewport, and styling only depends on its size through `vw` and
+//! `vh` lengths. So an embedder can parse and style once and then call `StyledDocument::layout`
+//! again whenever the viewport is resized, restyling first only if the stylesheet uses them.
+//!
+//! To change the document, mutate `Document::root` (see `Node::append_child` and friends) and then
+//! call `Document::style` again. A `StyledDocument` borrows its `Document`, so no styled tree can
+//! outlive a change to the DOM and go stale.
+//!
+//! There is no `Document::layout`, because the styled tree can't be cached inside the `Document`.
+//! Its `StyledNode`s borrow the DOM and the stylesheet, and a struct can't hold a reference into
+//! itself. A `LayoutBox` in turn borrows the `StyledNode`s, so the `StyledDocument` has to outlive
+//! it somewhere the embedder can see.
+//!
+//! The document keeps the geometry of each element's block from the last layout, and layout
+//! reuses it for blocks whose subtree hasn't changed. The mutation methods mark the nodes they
+//! change, and the setters for the stylesheet, root font size, and resolution forget every block.
+
+use css::{self, Stylesheet};
+use dom::Node;
+use html;
+use images::ImageCache;
+use layout::{self, Dimensions, FontMetrics, LayoutBox, LayoutCache, LayoutRecorder, Rect};
+use style::{self, StyledNode};
+
+/// A DOM tree and the stylesheet that applies to it.
+pub struct Document {
+    pub root: Node,
+    stylesheet: Stylesheet,
+    /// The font size in px of the root element if the stylesheet doesn't set one. This is the
+    /// base for `em` and `rem` lengths. Defaults to 16px.
+    root_font_size: f32,
+    /// The resolution used to convert physical units like `in` and `pt` to px. Defaults to 96.
+    px_per_inch: f32,
+    /// The images the document uses, with relative URLs resolved against its directory.
+    images: ImageCache,
+    /// The geometry of each block from the last layout.
+    layout_cache: LayoutCache,
+}
+
+/// A document with styles applied to it.
+///
+/// This borrows from its `Document`, so they are kept separate.
+pub struct StyledDocument<'a> {
+    pub style_root: StyledNode<'a>,
+    images: &'a ImageCache,
+    layout_cache: &'a LayoutCache,
+}
+
+impl Document {
+    pub fn new(root: Node, stylesheet: Stylesheet) -> Document {
+        Document {
+            root: root,
+            stylesheet: stylesheet,
+            root_font_size: style::DEFAULT_FONT_SIZE,
+            px_per_inch: style::DEFAULT_PX_PER_INCH,
+            images: ImageCache::new(Path::new(".")),
+            layout_cache: LayoutCache::new(),
+        }
+    }
+
+    pub fn stylesheet(&self) -> &Stylesheet {
+        &self.stylesheet
+    }
+
+    /// Replace the stylesheet. The change shows up the next time the document is styled, and
+    /// every block is laid out again from scratch.
+    pub fn set_stylesheet(&mut self, stylesheet: Stylesheet) {
+        self.stylesheet = stylesheet;
+        self.layout_cache.clear();
+    }
+
+    pub fn root_font_size(&self) -> f32 {
+        self.root_font_size
+    }
+
+    /// Set the font size in px of the root element if the stylesheet doesn't set one. The change
+    /// shows up the next time the document is styled, and every block is laid out again from
+    /// scratch.
+    pub fn set_root_font_size(&mut self, size: f32) {
+        self.root_font_size = size;
+        self.layout_cache.clear();
+    }
+
+    pub fn px_per_inch(&self) -> f32 {
+        self.px_per_inch
+    }
+
+    /// Set the resolution used to convert physical units like `in` and `pt` to px. The change
+    /// shows up the next time the document is styled, and every block is laid out again from
+    /// scratch.
+    pub fn set_px_per_inch(&mut self, px_per_inch: f32) {
+        self.px_per_inch = px_per_inch;
+        self.layout_cache.clear();
+    }
+
+    /// The number of blocks that have been laid out from scratch instead of reused from an
+    /// earlier layout, over every layout of this document so far.
+    pub fn recompute_count(&self) -> usize {
+        self.layout_cache.recompute_count()
+    }
+
+    /// Resolve relative URLs in the document, like `<img src>` and `url()`, against the directory
+    /// `base` instead of the working directory.
+    pub fn set_base(&mut self, base: Path) {
+        self.images = ImageCache::new(base);
+    }
+
+    /// Parse an HTML document and a CSS stylesheet.
+    pub fn parse(html: String, css: String) -> Document {
+        Document::new(html::parse(html), css::parse(css))
+    }
+
+    /// Apply the stylesheet to the DOM, for a viewport of the given size.
+    pub fn style(&self, viewport: Rect) -> StyledDocument {
+        StyledDocument {
+            style_root: style::style_tree(&self.root, &self.stylesheet, self.root_font_size,
+                                          (viewport.width, viewport.height), self.px_per_inch),
+            images: &self.images,
+            layout_cache: &self.layout_cache,
+        }
+    }
+}
+
+impl<'a> StyledDocument<'a> {
+    /// Lay out the document in the given viewport. This can be called any number of times.
+    pub fn layout(&self, viewport: Dimensions) -> LayoutBox {
+        self.layout_with_metrics(viewport, &layout::BUILTIN_FONT)
+    }
+
+    /// Lay out the document like `layout`, measuring text with `metrics` instead of the built-in
+    /// font. Blocks laid out with other metrics are laid out again.
+    pub fn layout_with_metrics<'b>(&'b self, viewport: Dimensions,
+                                   metrics: &'b (FontMetrics + 'b)) -> LayoutBox<'b> {
+        self.layout_with_recorder(viewport, metrics, None)
+    }
+
+    /// Lay out the document like `layout_with_metrics`, reporting the time taken by each phase
+    /// of block layout to `recorder`.
+    pub fn layout_with_recorder<'b>(&'b self, viewport: Dimensions,
+                                    metrics: &'b (FontMetrics + 'b),
+                                    recorder: Option<&'b (LayoutRecorder + 'b)>)
+                                    -> LayoutBox<'b> {
+        // Mark the ancestors of changed nodes, so their blocks aren't reused either.
+        self.style_root.node.propagate_dirty();
+        let layout_root = layout::layout_document(&self.style_root, viewport, metrics, recorder,
+                                                  self.images, self.layout_cache);
+        // Every change is now reflected in the cached layouts.
+        self.style_root.node.clear_dirty();
+        layout_root
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::default::Default;
+    use css;
+    use html;
+    use layout::Dimensions;
+
+    fn viewport(width: f32) -> Dimensions {
+        let mut viewport: Dimensions = Default::default();
+        viewport.content.width = width;
+        viewport.content.height = 600.0;
+        viewport
+    }
+
+    #[test]
+    fn styled_document_lays_out_in_each_viewport() {
+        let document = Document::parse("<div></div>".to_string(),
+                                       "div { display: block; padding: 10px; }".to_string());
+        let styled = document.style(viewport(800.0).content);
+        assert_eq!(styled.layout(viewport(800.0)).dimensions.content.width, 780.0);
+        assert_eq!(styled.layout(viewport(400.0)).dimensions.content.width, 380.0);
+    }
+
+    #[test]
+    fn set_stylesheet_applies_on_the_next_style() {
+        let mut document = Document::parse("<div></div>".to_string(),
+                                           "div { display: block; }".to_string());
+        document.set_stylesheet(css::parse("div { display: block; width: 100px; }".to_string()));
+        assert_eq!(document.stylesheet().rules.len(), 1);
+        let styled = document.style(viewport(800.0).content);
+        assert_eq!(styled.layout(viewport(800.0)).dimensions.content.width, 100.0);
+    }
+
+    #[test]
+    fn set_px_per_inch_applies_on_the_next_style() {
+        let mut document = Document::parse("<div></div>".to_string(),
+                                           "div { display: block; width: 1in; }".to_string());
+        assert_eq!(document.px_per_inch(), 96.0);
+        document.set_px_per_inch(150.0);
+        let styled = document.style(viewport(800.0).content);
+        assert_eq!(styled.layout(viewport(800.0)).dimensions.content.width, 150.0);
+    }
+
+    #[test]
+    fn dom_changes_apply_on_the_next_style() {
+        let mut document = Document::parse("<div><p></p></div>".to_string(),
+                                           "div, p { display: block; } \
+                                            .tall { height: 30px; }".to_string());
+        {
+            let styled = document.style(viewport(800.0).content);
+            assert_eq!(styled.layout(viewport(800.0)).dimensions.content.height, 0.0);
+        }
+
+        document.root.children[0].set_attribute("class", "tall");
+        document.root.append_child(html::parse("<p class=\"tall\"></p>".to_string()));
+        let styled = document.style(viewport(800.0).content);
+        let layout = styled.layout(viewport(800.0));
+        assert_eq!(layout.children.len(), 2);
+        assert_eq!(layout.dimensions.content.height, 60.0);
+    }
+
+    #[test]
+    fn layout_reuses_unchanged_blocks() {
+        let mut document = Document::parse(
+            "<div><div id=\"a\"><p></p></div><div id=\"b\"><p></p></div></div>".to_string(),
+            "div, p { display: block; height: 10px; }".to_string());
+        {
+            let styled = document.style(viewport(800.0).content);
+            styled.layout(viewport(800.0));
+            assert_eq!(document.recompute_count(), 4);
+            styled.layout(viewport(800.0));
+            assert_eq!(document.recompute_count(), 4);
+        }
+
+        document.root.children[1].children[0].set_attribute("class", "x");
+        let styled = document.style(viewport(800.0).content);
+        styled.layout(viewport(800.0));
+        assert_eq!(document.recompute_count(), 6);
+    }
+}
@@ -1,6 +1,9 @@
 //! Basic DOM data structures.
 
+use std::ascii::AsciiExt; // for `to_ascii_lowercase`
+use std::cell::Cell;
 use std::collections::{HashMap,HashSet};
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
 
 pub type AttrMap = HashMap<String, String>;
 
@@ -9,8 +12,21 @@ pub struct Node {
     // data common to all nodes:
     pub children: Vec<Node>,
 
+    /// Where the node came from in the HTML source, if the parser was asked to record it.
+    pub source: Option<SourceRange>,
+
     // data specific to each node type:
     pub node_type: NodeType,
+
+    /// A number that identifies this node for as long as the program runs, even as it is moved
+    /// around the tree.
+    id: usize,
+
+    /// Set when this node or one of its descendants has changed since the last layout.
+    ///
+    /// The mutation methods only set it on the nodes they change, because nodes don't point to
+    /// their parents. `propagate_dirty` sets it on their ancestors before each layout.
+    dirty: Cell<bool>,
 }
 
 #[derive(Show)]
@@ -25,19 +41,194 @@ pub struct ElementData {
     pub attributes: AttrMap,
 }
 
-// Constructor functions for convenience:
+/// A location in source text.
+#[derive(Show, PartialEq)]
+pub struct SourcePosition {
+    /// Offset in bytes from the start of the source.
+    pub offset: usize,
+    /// Line number, starting at 1.
+    pub line: usize,
+    /// Column number in characters, starting at 1.
+    pub column: usize,
+}
+
+/// The span of source text from `start` up to (but not including) `end`.
+#[derive(Show, PartialEq)]
+pub struct SourceRange {
+    pub start: SourcePosition,
+    pub end: SourcePosition,
+}
+
+impl Copy for SourcePosition {}
+impl Copy for SourceRange {}
+
+impl SourcePosition {
+    /// The position of the start of the source.
+    pub fn start() -> SourcePosition {
+        SourcePosition { offset: 0, line: 1, column: 1 }
+    }
+
+    /// Move past one character of source.
+    pub fn advance(&mut self, c: char) {
+        self.offset = self.offset + c.len_utf8();
+        if c == '\n' {
+            self.line = self.line + 1;
+            self.column = 1;
+        } else {
+            self.column = self.column + 1;
+        }
+    }
+}
+
+// Constructor functions for convenience, e.g. for building a DOM tree without parsing HTML:
+//
+//     elem("p".to_string(), vec![("class".to_string(), "note".to_string())].into_iter(),
+//          vec![text("Hello".to_string())])
+
+/// The source of `Node::id`s.
+static NEXT_NODE_ID: AtomicUsize = ATOMIC_USIZE_INIT;
 
 pub fn text(data: String) -> Node {
-    Node { children: vec![], node_type: NodeType::Text(data) }
+    Node {
+        children: vec![],
+        source: None,
+        node_type: NodeType::Text(data),
+        id: NEXT_NODE_ID.fetch_add(1, Ordering::SeqCst),
+        dirty: Cell::new(false),
+    }
 }
 
-pub fn elem(name: String, attrs: AttrMap, children: Vec<Node>) -> Node {
+pub fn elem<I>(name: String, attrs: I, children: Vec<Node>) -> Node
+        where I: Iterator<Item=(String, String)> {
     Node {
         children: children,
+        source: None,
         node_type: NodeType::Element(ElementData {
             tag_name: name,
-            attributes: attrs,
-        })
+            attributes: attrs.collect(),
+        }),
+        id: NEXT_NODE_ID.fetch_add(1, Ordering::SeqCst),
+        dirty: Cell::new(false),
+    }
+}
+
+// Node methods
+
+impl Node {
+    /// A number that is different for every node, and doesn't change when the node is moved.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// The text of all descendant text nodes, concatenated in document order, like the DOM's
+    /// `textContent`. Whitespace is kept as it is in the source.
+    pub fn text_content(&self) -> String {
+        let mut result = String::new();
+        self.collect_text(&mut result);
+        result
+    }
+
+    /// All of the descendants of this node (not including itself), in document order.
+    pub fn descendants(&self) -> Descendants {
+        Descendants { stack: self.children.iter().rev().collect() }
+    }
+
+    /// The descendants of this node that are elements, in document order.
+    pub fn elements(&self) -> Elements {
+        Elements { descendants: self.descendants() }
+    }
+
+    /// Add `child` after the existing children of this node.
+    pub fn append_child(&mut self, child: Node) {
+        // The child may inherit different styles here than where it came from.
+        child.invalidate_subtree();
+        self.invalidate();
+        self.children.push(child);
+    }
+
+    /// Remove and return the child at `index`, shifting the later children down.
+    ///
+    /// Panics if there is no child at `index`.
+    pub fn remove_child(&mut self, index: usize) -> Node {
+        self.invalidate();
+        self.children.remove(index)
+    }
+
+    /// Set an attribute of this element, replacing any earlier value. Names are ASCII
+    /// case-insensitive, like those from the HTML parser.
+    ///
+    /// Text nodes have no attributes, so this does nothing on them.
+    pub fn set_attribute(&mut self, name: &str, value: &str) {
+        match self.node_type {
+            NodeType::Element(ref mut elem) => {
+                elem.attributes.insert(name.to_ascii_lowercase(), value.to_string());
+            }
+            NodeType::Text(_) => return
+        }
+        // New attributes can change the styles of this element and its descendants.
+        self.invalidate_subtree();
+    }
+
+    /// Remove an attribute of this element, returning its value if it was set. Always returns
+    /// `None` on a text node.
+    pub fn remove_attribute(&mut self, name: &str) -> Option<String> {
+        let removed = match self.node_type {
+            NodeType::Element(ref mut elem) => elem.attributes.remove(&name.to_ascii_lowercase()),
+            NodeType::Text(_) => return None
+        };
+        self.invalidate_subtree();
+        removed
+    }
+
+    /// Mark this node as changed, so that its cached layout isn't reused.
+    fn invalidate(&self) {
+        self.dirty.set(true);
+    }
+
+    /// Mark this node and all of its descendants as changed, so that none of their cached
+    /// layouts are reused.
+    ///
+    /// The mutation methods do this as needed. Call it on the root after changing anything else
+    /// that affects layout.
+    pub fn invalidate_subtree(&self) {
+        self.invalidate();
+        for child in self.children.iter() {
+            child.invalidate_subtree();
+        }
+    }
+
+    /// Has this node or any of its descendants changed since the last layout? This is only
+    /// accurate for ancestors of changed nodes after `propagate_dirty`.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.get()
+    }
+
+    /// Mark every node that has a changed descendant as changed itself, in a single pass over the
+    /// tree. Returns whether this node is now marked.
+    pub fn propagate_dirty(&self) -> bool {
+        let mut dirty = self.dirty.get();
+        for child in self.children.iter() {
+            dirty = child.propagate_dirty() || dirty;
+        }
+        self.dirty.set(dirty);
+        dirty
+    }
+
+    /// Clear the dirty flags of this node and its descendants, once they have been laid out.
+    pub fn clear_dirty(&self) {
+        self.dirty.set(false);
+        for child in self.children.iter() {
+            child.clear_dirty();
+        }
+    }
+
+    fn collect_text(&self, result: &mut String) {
+        match self.node_type {
+            NodeType::Text(ref text) => result.push_str(&**text),
+            NodeType::Element(_) => for child in self.children.iter() {
+                child.collect_text(result);
+            }
+        }
     }
 }
 
@@ -55,3 +246,135 @@ impl ElementData {
         }
     }
 }
+
+// Iterators
+
+/// An iterator over the descendants of a node, in document order. See `Node::descendants`.
+pub struct Descendants<'a> {
+    /// The nodes still to visit, next one last.
+    stack: Vec<&'a Node>,
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = &'a Node;
+
+    fn next(&mut self) -> Option<&'a Node> {
+        let node = match self.stack.pop() {
+            Some(node) => node,
+            None => return None
+        };
+        self.stack.extend(node.children.iter().rev());
+        Some(node)
+    }
+}
+
+/// An iterator over the descendants of a node that are elements. See `Node::elements`.
+pub struct Elements<'a> {
+    descendants: Descendants<'a>,
+}
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = &'a Node;
+
+    fn next(&mut self) -> Option<&'a Node> {
+        self.descendants.find(|node| match node.node_type {
+            NodeType::Element(_) => true,
+            NodeType::Text(_) => false
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use html;
+
+    /// The types and contents of a node and its descendants, leaving out their ids.
+    fn tree_contents(node: &Node) -> String {
+        let children: Vec<String> = node.children.iter().map(tree_contents).collect();
+        format!("{:?} {:?}", node.node_type, children)
+    }
+
+    #[test]
+    fn built_tree_matches_parsed_html() {
+        let built = elem("p".to_string(),
+                         vec![("class".to_string(), "note".to_string())].into_iter(),
+                         vec![text("Hello ".to_string()),
+                              elem("em".to_string(), Vec::new().into_iter(),
+                                   vec![text("world".to_string())])]);
+        let parsed = html::parse("<p class=\"note\">Hello <em>world</em></p>".to_string());
+        assert_eq!(tree_contents(&built), tree_contents(&parsed));
+    }
+
+    #[test]
+    fn elem_accepts_an_attribute_map() {
+        let mut attrs = AttrMap::new();
+        attrs.insert("id".to_string(), "main".to_string());
+        let node = elem("div".to_string(), attrs.into_iter(), Vec::new());
+        match node.node_type {
+            NodeType::Element(ref data) => assert_eq!(data.id(), Some(&"main".to_string())),
+            NodeType::Text(_) => panic!("expected an element")
+        }
+    }
+
+    #[test]
+    fn text_content_concatenates_descendant_text() {
+        let root = html::parse("<div>Hello <em>big <b>wide</b></em>  world</div>".to_string());
+        assert_eq!(&*root.text_content(), "Hello big wide  world");
+        assert_eq!(&*text("plain".to_string()).text_content(), "plain");
+        assert_eq!(&*elem("br".to_string(), Vec::new().into_iter(), Vec::new()).text_content(),
+                   "");
+    }
+
+    fn attribute(node: &Node, name: &str) -> Option<String> {
+        match node.node_type {
+            NodeType::Element(ref data) => data.attributes.get(name).map(|value| value.clone()),
+            NodeType::Text(_) => None
+        }
+    }
+
+    #[test]
+    fn attributes_can_be_set_and_removed() {
+        let mut node = html::parse("<div id=\"a\"></div>".to_string());
+        node.set_attribute("CLASS", "note");
+        node.set_attribute("id", "b");
+        assert_eq!(attribute(&node, "class"), Some("note".to_string()));
+        assert_eq!(attribute(&node, "id"), Some("b".to_string()));
+        assert_eq!(node.remove_attribute("Id"), Some("b".to_string()));
+        assert_eq!(node.remove_attribute("id"), None);
+    }
+
+    #[test]
+    fn text_nodes_ignore_attribute_changes() {
+        let mut node = text("hi".to_string());
+        node.set_attribute("class", "note");
+        assert_eq!(node.remove_attribute("class"), None);
+        assert_eq!(tree_contents(&node), tree_contents(&text("hi".to_string())));
+        assert!(!node.is_dirty());
+    }
+
+    #[test]
+    fn children_can_be_appended_and_removed() {
+        let mut node = html::parse("<p>a</p>".to_string());
+        node.append_child(text("b".to_string()));
+        assert_eq!(&*node.text_content(), "ab");
+        let removed = node.remove_child(0);
+        assert_eq!(&*removed.text_content(), "a");
+        assert_eq!(&*node.text_content(), "b");
+    }
+
+    #[test]
+    fn descendants_are_visited_in_document_order() {
+        let root = html::parse("<div><p>a<em>b</em></p><br></div>".to_string());
+        let contents: Vec<String> = root.descendants().map(|node| match node.node_type {
+            NodeType::Element(ref data) => data.tag_name.clone(),
+            NodeType::Text(ref text) => text.clone()
+        }).collect();
+        assert_eq!(contents, vec!["p", "a", "em", "b", "br"]);
+        let tags: Vec<String> = root.elements().map(|node| match node.node_type {
+            NodeType::Element(ref data) => data.tag_name.clone(),
+            NodeType::Text(_) => panic!("expected an element")
+        }).collect();
+        assert_eq!(tags, vec!["p", "em", "br"]);
+    }
+}
@@ -0,0 +1,119 @@
+//! Layout regression tests, driven by a directory of fixtures.
+//!
+//! Each subdirectory of the fixture directory contains an `input.html` and `input.css`, and the
+//! expected layout tree (as produced by `json::layout_to_json`) in `expected.json`.
+//!
+//! Set the `ROBINSON_UPDATE_GOLDENS` environment variable to overwrite each `expected.json` with
+//! the current output instead of checking it.
+//!
+//! The unit tests at the bottom also check a table of lengths in each supported unit, so that
+//! each new unit gets the same coverage.
+
+use std::old_io::fs::{self, File, PathExtensions};
+use std::os;
+use document::Document;
+use layout::Dimensions;
+use json;
+
+/// Run every fixture in `dir`, laid out in the given viewport. Returns true if they all pass.
+pub fn check(dir: &Path, viewport: Dimensions) -> bool {
+    let update = os::getenv("ROBINSON_UPDATE_GOLDENS").is_some();
+    let mut fixtures = fs::readdir(dir).unwrap();
+    fixtures.sort_by(|a, b| a.as_vec().cmp(b.as_vec()));
+
+    let mut all_passed = true;
+    for fixture in fixtures.iter().filter(|path| path.is_dir()) {
+        let read = |&: name: &str| File::open(&fixture.join(name)).read_to_string().unwrap();
+        let actual = layout_json(read("input.html"), read("input.css"), viewport);
+        let expected_path = fixture.join("expected.json");
+
+        if update {
+            File::create(&expected_path).write_str(&*actual).unwrap();
+            println!("updated {}", expected_path.display());
+        } else if actual == read("expected.json") {
+            println!("ok      {}", fixture.display());
+        } else {
+            all_passed = false;
+            println!("FAILED  {}", fixture.display());
+            println!("{}", actual);
+        }
+    }
+    return all_passed;
+}
+
+/// Parse, style, and lay out a document, and serialize the layout tree.
+fn layout_json(html: String, css: String, viewport: Dimensions) -> String {
+    let document = Document::parse(html, css);
+    let styled_document = document.style(viewport.content);
+    let layout_root = styled_document.layout(viewport);
+    json::layout_to_json(&layout_root)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::Float;
+    use css::{self, Value};
+    use style::LengthContext;
+
+    /// A length as written in a stylesheet, the context to resolve it in, and the expected result
+    /// in px.
+    type LengthCase = (&'static str, LengthContext, f32);
+
+    /// The lengths to check, covering every unit that resolves to px.
+    fn length_cases() -> Vec<LengthCase> {
+        let context = LengthContext {
+            root_font_size: 16.0,
+            font_size: 20.0,
+            viewport_width: 800.0,
+            viewport_height: 600.0,
+            px_per_inch: 96.0,
+        };
+        vec![
+            ("10px", context, 10.0),
+            ("2em", context, 40.0),
+            ("0.5em", context, 10.0),
+            ("2rem", context, 32.0),
+            ("50%", context, 10.0),
+            ("10vw", context, 80.0),
+            ("10vh", context, 60.0),
+            ("1in", context, 96.0),
+            ("2.54cm", context, 96.0),
+            ("25.4mm", context, 96.0),
+            ("72pt", context, 96.0),
+            ("6pc", context, 96.0),
+            ("min(10px, 1em)", context, 10.0),
+            ("max(1rem, 5vh)", context, 30.0),
+            ("clamp(1rem, 10vw, 50px)", context, 50.0),
+        ]
+    }
+
+    /// Parse a single property value, like the right-hand side of a declaration.
+    fn parse_value(source: &str) -> Option<Value> {
+        let stylesheet = css::parse(format!("* {{ x: {} }}", source));
+        stylesheet.rules.into_iter().next()
+                  .and_then(|rule| rule.declarations.into_iter().next())
+                  .map(|declaration| declaration.value)
+    }
+
+    /// Each length is resolved as a `font-size` would be, so percentages are relative to the
+    /// context's font size. A length that fails to parse or resolve fails its case.
+    #[test]
+    fn lengths_resolve_to_px() {
+        for &(source, context, expected) in length_cases().iter() {
+            let value = match parse_value(source) {
+                Some(value) => value,
+                None => panic!("{} failed to parse", source)
+            };
+            match context.resolve_font_size(&value) {
+                Some(px) => assert!((px - expected).abs() < 0.001,
+                                    "{}: expected {}px, got {}px", source, expected, px),
+                None => panic!("{} is not a length", source)
+            }
+        }
+    }
+
+    #[test]
+    fn unparseable_lengths_are_caught() {
+        assert!(parse_value("10qq").is_none());
+    }
+}
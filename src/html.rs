@@ -1,83 +1,309 @@
 //! A simple parser for a tiny subset of HTML.
 //!
-//! Can parse basic opening and closing tags, and text nodes.
+//! Can parse basic opening and closing tags, void elements like `<br>`, and text nodes.
+//!
+//! The input is split into tokens (start tags, end tags, and text), which are built into a tree
+//! using a stack of open elements. This lets the parser recover from markup that isn't
+//! well-formed: stray end tags are ignored, and an end tag closes any elements still open inside
+//! the element it ends.
 //!
 //! Not yet supported:
 //!
 //! * Comments
 //! * Doctypes and processing instructions
-//! * Self-closing tags
-//! * Non-well-formed markup
+//! * Self-closing tags, except on void elements
+//! * Most of the HTML tree construction rules
 //! * Character entities
 
 use dom;
+use dom::{SourcePosition, SourceRange};
+use std::ascii::OwnedAsciiExt; // for `into_ascii_lowercase`
 use std::collections::HashMap;
+use std::default::Default;
+
+/// Options for the HTML parser.
+#[derive(Default)]
+pub struct ParseOptions {
+    /// Record where each node came from in the source, in `Node::source`. This makes parsing
+    /// slower, so it is off by default.
+    pub track_positions: bool,
+}
 
 /// Parse an HTML document and return the root element.
 pub fn parse(source: String) -> dom::Node {
-    let mut nodes = Parser { pos: 0, input: source }.parse_nodes();
+    parse_with_options(source, Default::default())
+}
+
+/// Parse an HTML document with the given options, and return the root element.
+pub fn parse_with_options(source: String, options: ParseOptions) -> dom::Node {
+    let mut parser = Parser::new(source, options);
+    let mut builder = TreeBuilder::new();
+    while let Some(token) = parser.next_token() {
+        builder.process(token, parser.token_range());
+    }
+    builder.finish(parser.position)
+}
+
+/// An HTML parser that is given the source a chunk at a time, for example as it arrives from the
+/// network.
+///
+/// The tree is built as soon as each token is complete, and source that has been parsed is
+/// discarded.
+pub struct StreamParser {
+    parser: Parser,
+    builder: TreeBuilder,
+}
+
+impl StreamParser {
+    pub fn new() -> StreamParser {
+        StreamParser::with_options(Default::default())
+    }
+
+    pub fn with_options(options: ParseOptions) -> StreamParser {
+        StreamParser {
+            parser: Parser::new(String::new(), options),
+            builder: TreeBuilder::new(),
+        }
+    }
+
+    /// Add a chunk of source, and parse any tokens that are now complete.
+    pub fn feed(&mut self, chunk: &str) {
+        self.parser.input.push_str(chunk);
+        loop {
+            self.parser.consume_whitespace();
+            if !self.parser.has_complete_token() {
+                break;
+            }
+            let token = self.parser.next_token().unwrap();
+            self.builder.process(token, self.parser.token_range());
+        }
+        self.parser.discard_consumed();
+    }
 
-    // If the document contains a root element, just return it. Otherwise, create one.
-    if nodes.len() == 1 {
-        nodes.swap_remove(0)
-    } else {
-        dom::elem("html".to_string(), HashMap::new(), nodes)
+    /// Parse the rest of the source, and return the root element of the document.
+    pub fn finish(mut self) -> dom::Node {
+        while let Some(token) = self.parser.next_token() {
+            self.builder.process(token, self.parser.token_range());
+        }
+        self.builder.finish(self.parser.position)
     }
 }
 
+/// A piece of HTML source.
+enum Token {
+    StartTag(String, dom::AttrMap),
+    EndTag(String),
+    Text(String),
+}
+
+/// An element whose end tag hasn't been seen yet.
+struct OpenElement {
+    tag_name: String,
+    attributes: dom::AttrMap,
+    children: Vec<dom::Node>,
+    /// Where the start tag began, if positions are being tracked.
+    start: Option<SourcePosition>,
+}
+
+/// Builds a DOM tree from a sequence of tokens.
+struct TreeBuilder {
+    /// Nodes at the top level of the document.
+    document: Vec<dom::Node>,
+    /// Elements that are still open, innermost last.
+    open_elements: Vec<OpenElement>,
+}
+
+impl TreeBuilder {
+    fn new() -> TreeBuilder {
+        TreeBuilder { document: Vec::new(), open_elements: Vec::new() }
+    }
+
+    /// Add one token to the tree. `range` is where the token is in the source, if known.
+    fn process(&mut self, token: Token, range: Option<SourceRange>) {
+        let start = range.map(|range| range.start);
+        let end = range.map(|range| range.end);
+        match token {
+            Token::StartTag(tag_name, attributes) => {
+                // Some start tags imply the end of the current element, like `<li>` in an `<li>`.
+                while self.open_elements.last()
+                          .map_or(false, |open| implies_end_tag(&*open.tag_name, &*tag_name)) {
+                    self.close_element(start);
+                }
+                if is_void_element(&*tag_name) {
+                    let mut node = dom::elem(tag_name, attributes.into_iter(), vec!());
+                    node.source = range;
+                    self.append(node);
+                } else {
+                    self.open_elements.push(OpenElement {
+                        tag_name: tag_name,
+                        attributes: attributes,
+                        children: vec!(),
+                        start: start,
+                    });
+                }
+            }
+            Token::EndTag(tag_name) => {
+                // Close the matching element, and any elements still open inside it.
+                // An end tag that doesn't match any open element is ignored.
+                let matching = self.open_elements.iter()
+                                                 .rposition(|open| open.tag_name == tag_name);
+                if let Some(index) = matching {
+                    while self.open_elements.len() > index + 1 {
+                        self.close_element(start);
+                    }
+                    self.close_element(end);
+                }
+            }
+            Token::Text(text) => {
+                let mut node = dom::text(text);
+                node.source = range;
+                self.append(node);
+            }
+        }
+    }
+
+    /// Close the innermost open element, which ends at `end` in the source, and add it to its
+    /// parent.
+    fn close_element(&mut self, end: Option<SourcePosition>) {
+        let open = self.open_elements.pop().unwrap();
+        let mut node = dom::elem(open.tag_name, open.attributes.into_iter(), open.children);
+        node.source = match (open.start, end) {
+            (Some(start), Some(end)) => Some(SourceRange { start: start, end: end }),
+            _ => None
+        };
+        self.append(node);
+    }
+
+    /// Add a node to the innermost open element.
+    fn append(&mut self, node: dom::Node) {
+        match self.open_elements.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => self.document.push(node)
+        }
+    }
+
+    /// Close any elements that are still open at the end of the input, which is at `end` in the
+    /// source, and return the root.
+    fn finish(mut self, end: Option<SourcePosition>) -> dom::Node {
+        while !self.open_elements.is_empty() {
+            self.close_element(end);
+        }
+
+        // If the document contains a root element, just return it. Otherwise, create one.
+        if self.document.len() == 1 {
+            self.document.swap_remove(0)
+        } else {
+            dom::elem("html".to_string(), Vec::new().into_iter(), self.document)
+        }
+    }
+}
+
+/// Splits HTML source into tokens.
 struct Parser {
     pos: usize,
     input: String,
+    /// The source position of `pos`, if positions are being tracked.
+    position: Option<SourcePosition>,
+    /// The source position where the last token started.
+    token_start: Option<SourcePosition>,
 }
 
 impl Parser {
-    /// Parse a sequence of sibling nodes.
-    fn parse_nodes(&mut self) -> Vec<dom::Node> {
-        let mut nodes = vec!();
-        loop {
-            self.consume_whitespace();
-            if self.eof() || self.starts_with("</") {
-                break;
+    fn new(input: String, options: ParseOptions) -> Parser {
+        Parser {
+            pos: 0,
+            input: input,
+            position: if options.track_positions { Some(SourcePosition::start()) } else { None },
+            token_start: None,
+        }
+    }
+
+    /// Parse the next token, or return `None` at the end of the input.
+    ///
+    /// Whitespace before a token is skipped.
+    fn next_token(&mut self) -> Option<Token> {
+        self.consume_whitespace();
+        if self.eof() {
+            return None;
+        }
+        self.token_start = self.position;
+        Some(if self.starts_with("</") {
+            self.parse_end_tag()
+        } else if self.next_char() == '<' {
+            self.parse_start_tag()
+        } else {
+            self.parse_text()
+        })
+    }
+
+    /// Does the input contain a whole token at the current position?
+    ///
+    /// A tag is complete once its closing `>` has been seen. A text token is only known to be
+    /// complete once the next tag starts.
+    fn has_complete_token(&self) -> bool {
+        let rest = &self.input[self.pos ..];
+        if rest.is_empty() {
+            return false;
+        }
+        if !rest.starts_with("<") {
+            return rest.contains("<");
+        }
+        // Look for a `>` that isn't inside a quoted attribute value.
+        let mut quote = None;
+        for c in rest.chars() {
+            match (quote, c) {
+                (None, '>') => return true,
+                (None, '"') | (None, '\'') => quote = Some(c),
+                (Some(open_quote), c) if c == open_quote => quote = None,
+                _ => {}
             }
-            nodes.push(self.parse_node());
         }
-        return nodes;
+        return false;
     }
 
-    /// Parse a single node.
-    fn parse_node(&mut self) -> dom::Node {
-        match self.next_char() {
-            '<' => self.parse_element(),
-            _   => self.parse_text()
+    /// Where the last token was in the source, if positions are being tracked.
+    fn token_range(&self) -> Option<SourceRange> {
+        match (self.token_start, self.position) {
+            (Some(start), Some(end)) => Some(SourceRange { start: start, end: end }),
+            _ => None
         }
     }
 
-    /// Parse a single element, including its open tag, contents, and closing tag.
-    fn parse_element(&mut self) -> dom::Node {
-        // Opening tag.
+    /// Drop the part of the input that has already been parsed.
+    fn discard_consumed(&mut self) {
+        self.input = self.input[self.pos ..].to_string();
+        self.pos = 0;
+    }
+
+    /// Parse an opening tag, e.g. `<div class="note">`.
+    fn parse_start_tag(&mut self) -> Token {
         assert!(self.consume_char() == '<');
         let tag_name = self.parse_tag_name();
         let attrs = self.parse_attributes();
-        assert!(self.consume_char() == '>');
 
-        // Contents.
-        let children = self.parse_nodes();
+        // Void elements may be written as `<br/>`.
+        if self.next_char() == '/' {
+            self.consume_char();
+        }
+        assert!(self.consume_char() == '>');
+        Token::StartTag(tag_name, attrs)
+    }
 
-        // Closing tag.
+    /// Parse a closing tag, e.g. `</div>`.
+    fn parse_end_tag(&mut self) -> Token {
         assert!(self.consume_char() == '<');
         assert!(self.consume_char() == '/');
-        assert!(self.parse_tag_name() == tag_name);
+        let tag_name = self.parse_tag_name();
+        self.consume_whitespace();
         assert!(self.consume_char() == '>');
-
-        return dom::elem(tag_name, attrs, children);
+        Token::EndTag(tag_name)
     }
 
     /// Parse a tag or attribute name.
+    ///
+    /// Names are ASCII case-insensitive, so they are converted to lowercase.
     fn parse_tag_name(&mut self) -> String {
-        self.consume_while(|c| match c {
-            'a'...'z' | 'A'...'Z' | '0'...'9' => true,
-            _ => false
-        })
+        self.consume_while(valid_tag_name_char).into_ascii_lowercase()
     }
 
     /// Parse a list of name="value" pairs, separated by whitespace.
@@ -85,7 +311,7 @@ impl Parser {
         let mut attributes = HashMap::new();
         loop {
             self.consume_whitespace();
-            if self.next_char() == '>' {
+            if self.next_char() == '>' || self.starts_with("/>") {
                 break;
             }
             let (name, value) = self.parse_attr();
@@ -111,9 +337,9 @@ impl Parser {
         return value;
     }
 
-    /// Parse a text node.
-    fn parse_text(&mut self) -> dom::Node {
-        dom::text(self.consume_while(|c| c != '<'))
+    /// Parse the text before the next tag.
+    fn parse_text(&mut self) -> Token {
+        Token::Text(self.consume_while(|c| c != '<'))
     }
 
     /// Consume and discard zero or more whitespace characters.
@@ -135,6 +361,9 @@ impl Parser {
     fn consume_char(&mut self) -> char {
         let range = self.input.char_range_at(self.pos);
         self.pos = range.next;
+        if let Some(ref mut position) = self.position {
+            position.advance(range.ch);
+        }
         return range.ch;
     }
 
@@ -153,3 +382,35 @@ impl Parser {
         self.pos >= self.input.len()
     }
 }
+
+fn valid_tag_name_char(c: char) -> bool {
+    match c {
+        'a'...'z' | 'A'...'Z' | '0'...'9' => true,
+        _ => false
+    }
+}
+
+/// Does a start tag for `next` implicitly close an open `open` element?
+///
+/// https://html.spec.whatwg.org/multipage/syntax.html#optional-tags
+fn implies_end_tag(open: &str, next: &str) -> bool {
+    match open {
+        "li" => next == "li",
+        "p" => match next {
+            "address" | "article" | "aside" | "blockquote" | "div" | "dl" | "fieldset" |
+            "footer" | "form" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "header" | "hr" |
+            "main" | "nav" | "ol" | "p" | "pre" | "section" | "table" | "ul" => true,
+            _ => false
+        },
+        _ => false
+    }
+}
+
+/// Elements that can't have any contents, and so have no closing tag.
+fn is_void_element(tag_name: &str) -> bool {
+    match tag_name {
+        "area" | "base" | "br" | "col" | "embed" | "hr" | "img" | "input" | "link" | "meta" |
+        "param" | "source" | "track" | "wbr" => true,
+        _ => false
+    }
+}
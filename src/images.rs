@@ -0,0 +1,68 @@
+//! Loading images from the filesystem.
+
+use css::Color;
+use image;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// A decoded image.
+#[derive(Show)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    /// Pixels in row-major order, starting at the top left.
+    pub pixels: Vec<Color>,
+}
+
+impl Image {
+    /// The color of the pixel at (`x`, `y`).
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+}
+
+/// The images used by a document, each read and decoded the first time it is needed.
+///
+/// Layout measures replaced elements and the painter draws them and background images, so the
+/// same image is often loaded several times.
+pub struct ImageCache {
+    /// The directory that relative URLs are resolved against.
+    base: Path,
+    /// Each image by its resolved path, or `None` if it couldn't be loaded.
+    images: RefCell<HashMap<Path, Option<Rc<Image>>>>,
+}
+
+impl ImageCache {
+    /// An empty cache for a document whose relative URLs are relative to the directory `base`.
+    pub fn new(base: Path) -> ImageCache {
+        ImageCache { base: base, images: RefCell::new(HashMap::new()) }
+    }
+
+    /// The image at `url`, loading it if this is the first time it's been asked for.
+    pub fn load(&self, url: &str) -> Option<Rc<Image>> {
+        let path = self.base.join(url);
+        if let Some(image) = self.images.borrow().get(&path) {
+            return image.clone();
+        }
+        let image = load(&path).map(Rc::new);
+        self.images.borrow_mut().insert(path, image.clone());
+        image
+    }
+}
+
+/// Load an image file, or return `None` if it can't be read or decoded.
+pub fn load(path: &Path) -> Option<Image> {
+    let buffer = match image::open(path) {
+        Ok(img) => img.to_rgba(),
+        Err(_) => return None
+    };
+    let (width, height) = buffer.dimensions();
+    let pixels = buffer.pixels().map(|p| Color {
+        r: p.data[0],
+        g: p.data[1],
+        b: p.data[2],
+        a: p.data[3],
+    }).collect();
+    Some(Image { width: width as usize, height: height as usize, pixels: pixels })
+}
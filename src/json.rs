@@ -0,0 +1,182 @@
+//! Serialize layout trees as JSON, for debugging and regression tests.
+
+use layout::{AnonymousBlock, BlockNode, InlineNode, LayoutBox};
+use css::{Stylesheet, Selector, Value, Unit};
+use style::{self, StyledNode};
+
+/// Serialize a layout tree as indented JSON.
+///
+/// Each box records its type, the DOM node it was generated for, its content rect, and its
+/// children.
+pub fn layout_to_json(layout_root: &LayoutBox) -> String {
+    let mut output = String::new();
+    write_layout_box(&mut output, layout_root, 0);
+    output.push('\n');
+    return output;
+}
+
+fn write_layout_box(output: &mut String, layout_box: &LayoutBox, depth: usize) {
+    let (box_type, style) = match layout_box.box_type {
+        BlockNode(style) => ("block", Some(style)),
+        InlineNode(style) => ("inline", Some(style)),
+        AnonymousBlock => ("anonymous", None)
+    };
+    let node = match style {
+        Some(style) => match (style.text(), style.element(), style.pseudo_element) {
+            (Some(_), _, _) => "#text".to_string(),
+            (None, Some(elem), _) => elem.tag_name.clone(),
+            (None, None, Some(pseudo)) => format!("::{}", pseudo.name()),
+            (None, None, None) => "".to_string(),
+        },
+        None => "".to_string()
+    };
+    let rect = layout_box.dimensions.content;
+
+    let indent = indentation(depth);
+    output.push_str("{\n");
+    output.push_str(&*format!("{}  \"box\": {},\n", indent, escape(box_type)));
+    output.push_str(&*format!("{}  \"node\": {},\n", indent, escape(&*node)));
+    output.push_str(&*format!("{}  \"x\": {},\n", indent, rect.x));
+    output.push_str(&*format!("{}  \"y\": {},\n", indent, rect.y));
+    output.push_str(&*format!("{}  \"width\": {},\n", indent, rect.width));
+    output.push_str(&*format!("{}  \"height\": {},\n", indent, rect.height));
+    output.push_str(&*format!("{}  \"children\": [", indent));
+    for (i, child) in layout_box.children.iter().enumerate() {
+        if i > 0 {
+            output.push(',');
+        }
+        output.push_str(&*format!("\n{}    ", indent));
+        write_layout_box(output, child, depth + 2);
+    }
+    if !layout_box.children.is_empty() {
+        output.push_str(&*format!("\n{}  ", indent));
+    }
+    output.push_str("]\n");
+    output.push_str(&*format!("{}}}", indent));
+}
+
+/// Serialize the computed styles of every element in a style tree as a JSON array.
+///
+/// Each element records its index in document order, its tag name, the selectors in
+/// `stylesheet` that match it, and its computed values (see `StyledNode::computed_values`),
+/// sorted by property name. Text nodes and pseudo-elements are left out.
+pub fn computed_styles_to_json(style_root: &StyledNode, stylesheet: &Stylesheet) -> String {
+    let mut entries = Vec::new();
+    collect_computed_styles(&mut entries, style_root, stylesheet);
+    let mut output = String::from_str("[");
+    for (i, entry) in entries.iter().enumerate() {
+        if i > 0 {
+            output.push(',');
+        }
+        output.push_str(&*format!("\n  {{\n    \"index\": {},\n{}  }}", i, entry));
+    }
+    if !entries.is_empty() {
+        output.push('\n');
+    }
+    output.push_str("]\n");
+    return output;
+}
+
+/// Append the JSON fields for each element in a style tree to `entries`, in document order.
+fn collect_computed_styles(entries: &mut Vec<String>, node: &StyledNode, stylesheet: &Stylesheet) {
+    if let Some(elem) = node.element() {
+        let selectors: Vec<String> = style::matched_selectors(elem, stylesheet).iter()
+            .map(|selector| escape(&*selector_text(*selector)))
+            .collect();
+        let values = node.computed_values();
+        let mut values: Vec<(&String, &Value)> = values.iter().collect();
+        values.sort_by(|&(a, _), &(b, _)| a.cmp(b));
+        let properties: Vec<String> = values.iter().map(|&(name, value)| {
+            format!("\n      {}: {}", escape(&**name), escape(&*css_text(value)))
+        }).collect();
+
+        let mut entry = String::new();
+        entry.push_str(&*format!("    \"tag\": {},\n", escape(&*elem.tag_name)));
+        entry.push_str(&*format!("    \"selectors\": [{}],\n", selectors.connect(", ")));
+        entry.push_str(&*format!("    \"style\": {{{}\n    }}\n", properties.connect(",")));
+        entries.push(entry);
+    }
+    for child in node.children.iter() {
+        collect_computed_styles(entries, child, stylesheet);
+    }
+}
+
+/// Write a selector as it would appear in a stylesheet.
+fn selector_text(selector: &Selector) -> String {
+    let Selector::Simple(ref simple) = *selector;
+    let mut text = String::new();
+    if let Some(ref tag_name) = simple.tag_name {
+        text.push_str(&**tag_name);
+    }
+    if let Some(ref id) = simple.id {
+        text.push_str(&*format!("#{}", id));
+    }
+    for class in simple.class.iter() {
+        text.push_str(&*format!(".{}", class));
+    }
+    if let Some(ref pseudo) = simple.pseudo_element {
+        text.push_str(&*format!("::{}", pseudo));
+    }
+    if text.is_empty() {
+        text.push('*');
+    }
+    return text;
+}
+
+/// Write a value as it would appear in a stylesheet.
+fn css_text(value: &Value) -> String {
+    match *value {
+        Value::Keyword(ref keyword) => keyword.clone(),
+        Value::Length(n, ref unit) => format!("{}{}", n, match *unit {
+            Unit::Px => "px",
+            Unit::Percent => "%",
+            Unit::Em => "em",
+            Unit::Rem => "rem",
+            Unit::Vw => "vw",
+            Unit::Vh => "vh",
+            Unit::In => "in",
+            Unit::Cm => "cm",
+            Unit::Mm => "mm",
+            Unit::Pt => "pt",
+            Unit::Pc => "pc",
+            Unit::Fr => "fr",
+            Unit::Deg => "deg",
+            Unit::Rad => "rad",
+        }),
+        Value::Number(n) => format!("{}", n),
+        Value::ColorValue(c) if c.a == 255 => format!("#{:02x}{:02x}{:02x}", c.r, c.g, c.b),
+        Value::ColorValue(c) =>
+            format!("rgba({}, {}, {}, {})", c.r, c.g, c.b, c.a as f32 / 255.0),
+        Value::List(ref values) =>
+            values.iter().map(|v| css_text(v)).collect::<Vec<String>>().connect(" "),
+        Value::Str(ref s) =>
+            format!("\"{}\"", s.replace("\\", "\\\\").replace("\"", "\\\"")),
+        Value::Function(ref name, ref args) => format!("{}({})", name,
+            args.iter().map(|v| css_text(v)).collect::<Vec<String>>().connect(", ")),
+    }
+}
+
+fn indentation(depth: usize) -> String {
+    let mut indent = String::new();
+    for _ in (0 .. depth) {
+        indent.push_str("  ");
+    }
+    return indent;
+}
+
+/// Quote a string as a JSON string literal.
+pub fn escape(s: &str) -> String {
+    let mut result = String::from_str("\"");
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&*format!("\\u{:04x}", c as u32)),
+            c => result.push(c)
+        }
+    }
+    result.push('"');
+    return result;
+}
@@ -1,10 +1,24 @@
-///! Basic CSS block layout.
+///! Basic CSS block and inline layout.
 
-use style::{StyledNode, Display};
+use style::{StyledNode, Font, Display, Position, Direction, VerticalAlign, Overflow,
+            JustifyContent, AlignItems, FlexDirection, FlexWrap, TrackSize, PointerEvents,
+            WhiteSpace, WordBreak, OverflowWrap, Visibility, BackgroundClip,
+            ContentVisibility};
+use style::Float as CssFloat;
+use style::NORMAL_LINE_HEIGHT;
+use css::Value;
 use css::Value::{Keyword, Length};
-use css::Unit::Px;
+use css::Unit::{Px, Percent};
+use images::{self, Image, ImageCache};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::default::Default;
-use std::iter::AdditiveIterator; // for `sum`
+use std::iter::{repeat, AdditiveIterator}; // for `sum`
+use std::mem;
+use std::num::Float;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::Duration;
 
 pub use self::BoxType::{AnonymousBlock, InlineNode, BlockNode};
 
@@ -40,11 +54,115 @@ impl Copy for Rect {}
 impl Copy for Dimensions {}
 impl Copy for EdgeSizes {}
 
+/// Which intrinsic width of a box's content to measure.
+#[derive(PartialEq)]
+enum IntrinsicSize {
+    /// The narrowest the content can be without overflowing.
+    MinContent,
+    /// The width of the content with no line breaks.
+    MaxContent,
+}
+
+impl Copy for IntrinsicSize {}
+
+/// The part of an inline box that lies on a single line.
+#[derive(Show, Clone)]
+pub struct Fragment {
+    /// Position of the fragment relative to the document origin.
+    pub rect: Rect,
+    /// The text in this fragment, if it belongs to a text node.
+    pub text: String,
+    /// Index of the line box containing this fragment.
+    pub line: usize,
+}
+
+/// The geometry of blocks from earlier layouts of a document, to reuse for blocks whose elements
+/// haven't changed. See `LayoutBox::layout_cached`.
+///
+/// Blocks are found by the `id` of their DOM node, so they stay with their elements as the DOM is
+/// changed. After each layout, the blocks of elements that no longer have a box (because they
+/// were removed from the DOM, or are now `display: none`) are forgotten.
+pub struct LayoutCache {
+    blocks: RefCell<HashMap<usize, CachedBlock>>,
+    /// The number of blocks laid out from scratch instead of reused, so far.
+    recomputed: Cell<usize>,
+}
+
+impl LayoutCache {
+    pub fn new() -> LayoutCache {
+        LayoutCache { blocks: RefCell::new(HashMap::new()), recomputed: Cell::new(0) }
+    }
+
+    /// The number of blocks that have been laid out from scratch instead of reused, in all of the
+    /// layouts so far. Only blocks that could have been reused are counted: those of elements in
+    /// normal flow.
+    pub fn recompute_count(&self) -> usize {
+        self.recomputed.get()
+    }
+
+    /// Forget the geometry of every block, so the next layout starts from scratch.
+    pub fn clear(&self) {
+        self.blocks.borrow_mut().clear();
+    }
+
+    /// Forget the geometry of every block whose element has no box in `layout_root`.
+    fn forget_missing_blocks(&self, layout_root: &LayoutBox) {
+        let mut ids = HashSet::new();
+        layout_root.walk(|layout_box, _| {
+            if let BlockNode(style) = layout_box.box_type {
+                ids.insert(style.node.id());
+            }
+        });
+        let mut blocks = self.blocks.borrow_mut();
+        let missing: Vec<usize> = blocks.keys().filter(|id| !ids.contains(*id))
+                                                .map(|&id| id).collect();
+        for id in missing.iter() {
+            blocks.remove(id);
+        }
+    }
+
+    /// The number of blocks whose geometry is kept.
+    pub fn len(&self) -> usize {
+        self.blocks.borrow().len()
+    }
+}
+
+/// The geometry of a block and its descendants from an earlier layout.
+#[derive(Show)]
+struct CachedBlock {
+    /// The width of the containing block the block was laid out in.
+    containing_width: f32,
+    /// The width and height of the viewport.
+    viewport: (f32, f32),
+    /// The font metrics the text was measured with; see `metrics_id`.
+    metrics: (usize, usize),
+    /// The left edge of the containing block, and the top of the space the block was placed in.
+    origin: (f32, f32),
+    /// The dimensions and fragments of each box in the subtree, in pre-order.
+    boxes: Vec<(Dimensions, Vec<Fragment>)>,
+}
+
 /// A node in the layout tree.
 pub struct LayoutBox<'a> {
     pub dimensions: Dimensions,
     pub box_type: BoxType<'a>,
     pub children: Vec<LayoutBox<'a>>,
+    /// For inline boxes, one fragment for each line the box appears on.
+    pub fragments: Vec<Fragment>,
+    /// Measures the text in this box.
+    metrics: &'a (FontMetrics + 'a),
+    /// Receives the timings of this box's layout, if anything is profiling it.
+    recorder: Option<&'a (LayoutRecorder + 'a)>,
+    /// The number of boxes in this box's subtree, including itself. Only counted if there is a
+    /// recorder; see `count_boxes`.
+    box_count: usize,
+    /// The images of the document this box belongs to, if it was laid out as part of one.
+    images: Option<&'a ImageCache>,
+    /// The geometry of blocks from earlier layouts of the same document, if there were any.
+    cache: Option<&'a LayoutCache>,
+    /// The `direction` of the containing block, which decides which margin absorbs any leftover
+    /// width.
+    containing_direction: Direction,
 }
 
 pub enum BoxType<'a> {
@@ -54,11 +172,40 @@ pub enum BoxType<'a> {
 }
 
 impl<'a> LayoutBox<'a> {
-    fn new(box_type: BoxType) -> LayoutBox {
+    fn new(box_type: BoxType<'a>, metrics: &'a (FontMetrics + 'a)) -> LayoutBox<'a> {
         LayoutBox {
             box_type: box_type,
             dimensions: Default::default(),
             children: Vec::new(),
+            fragments: Vec::new(),
+            metrics: metrics,
+            recorder: None,
+            box_count: 1,
+            images: None,
+            cache: None,
+            containing_direction: Direction::Ltr,
+        }
+    }
+
+    /// The image at `url`, from the document's `ImageCache` if there is one. Otherwise it is
+    /// loaded again each time, relative to the working directory.
+    pub fn load_image(&self, url: &str) -> Option<Rc<Image>> {
+        match self.images {
+            Some(cache) => cache.load(url),
+            None => images::load(&Path::new(url)).map(Rc::new)
+        }
+    }
+
+    /// Record `direction` as the direction of this box's containing block, and this box's own
+    /// direction as that of its children's.
+    fn set_containing_direction(&mut self, direction: Direction) {
+        self.containing_direction = direction;
+        let own_direction = match self.box_type {
+            BlockNode(style) | InlineNode(style) => style.direction(),
+            AnonymousBlock => direction
+        };
+        for child in self.children.iter_mut() {
+            child.set_containing_direction(own_direction);
         }
     }
 
@@ -69,66 +216,1985 @@ impl<'a> LayoutBox<'a> {
             AnonymousBlock => panic!("Anonymous block box has no style node")
         }
     }
+
+    /// The bottom edge of the lowest margin box or line fragment in this tree.
+    ///
+    /// This includes floated and absolutely positioned descendants, so after laying out the root
+    /// it is the height needed to show the whole document, which may be more than the viewport.
+    pub fn document_height(&self) -> f32 {
+        let margin_box = self.dimensions.margin_box();
+        let mut bottom = margin_box.y + margin_box.height;
+        for fragment in self.fragments.iter() {
+            bottom = bottom.max(fragment.rect.y + fragment.rect.height);
+        }
+        for child in self.children.iter() {
+            bottom = bottom.max(child.document_height());
+        }
+        return bottom;
+    }
+
+    /// How far the descendants of this box extend past the right and bottom edges of its content
+    /// area, as measured by their margin boxes and line fragments. Both are zero if the content
+    /// fits.
+    ///
+    /// This is how far a scroll container would have to scroll to show all of its content.
+    pub fn overflow_amount(&self) -> (f32, f32) {
+        let content = self.dimensions.content;
+        let (mut right, mut bottom) = (content.x + content.width, content.y + content.height);
+        for child in self.children.iter() {
+            child.walk(|descendant, _| {
+                let margin_box = descendant.dimensions.margin_box();
+                right = right.max(margin_box.x + margin_box.width);
+                bottom = bottom.max(margin_box.y + margin_box.height);
+                for fragment in descendant.fragments.iter() {
+                    right = right.max(fragment.rect.x + fragment.rect.width);
+                    bottom = bottom.max(fragment.rect.y + fragment.rect.height);
+                }
+            });
+        }
+        (right - (content.x + content.width), bottom - (content.y + content.height))
+    }
+
+    /// The area outside of which this box's content is clipped: its padding box, if it is a
+    /// scroll container or has paint containment.
+    pub fn overflow_clip(&self) -> Option<Rect> {
+        let paint_contained = match self.box_type {
+            BlockNode(style) | InlineNode(style) => style.contain().paint,
+            AnonymousBlock => false
+        };
+        if self.is_scroll_container() || paint_contained {
+            Some(self.dimensions.padding_box())
+        } else {
+            None
+        }
+    }
+
+    /// The rectangle painted with this box's background: its border, padding, or content box,
+    /// as `background-clip` says.
+    pub fn background_rect(&self) -> Rect {
+        let clip = match self.box_type {
+            BlockNode(style) | InlineNode(style) => style.background_clip(),
+            AnonymousBlock => BackgroundClip::BorderBox
+        };
+        match clip {
+            BackgroundClip::BorderBox => self.dimensions.border_box(),
+            BackgroundClip::PaddingBox => self.dimensions.padding_box(),
+            BackgroundClip::ContentBox => self.dimensions.content,
+        }
+    }
+
+    /// The rectangles painted with this box's background color. This is just `background_rect`,
+    /// except for an inline box, which has one rectangle on each line it appears on.
+    ///
+    /// The background is sliced at each line break, as with `box-decoration-break: slice`.
+    /// TODO: `box-decoration-break: clone`, once inline boxes have padding and borders.
+    pub fn background_rects(&self) -> Vec<Rect> {
+        match self.box_type {
+            InlineNode(_) if !self.is_atomic_inline() && !self.fragments.is_empty() =>
+                self.fragments.iter().map(|fragment| fragment.rect).collect(),
+            _ => vec![self.background_rect()]
+        }
+    }
+
+    /// How far the user can scroll this box's content horizontally and vertically. Each is zero
+    /// unless the overflow on that axis is `scroll` or `auto`.
+    ///
+    /// Content on an axis with `overflow: hidden` is clipped but can't be scrolled to.
+    pub fn scroll_range(&self) -> (f32, f32) {
+        let style = match self.box_type {
+            BlockNode(style) => style,
+            _ => return (0.0, 0.0)
+        };
+        let (x, y) = self.overflow_amount();
+        let scrolls = |&: overflow: Overflow|
+            overflow == Overflow::Scroll || overflow == Overflow::Auto;
+        (if scrolls(style.overflow_x()) { x } else { 0.0 },
+         if scrolls(style.overflow_y()) { y } else { 0.0 })
+    }
+
+    /// Call `f` on this box and each of its descendants in pre-order, with the depth of each box
+    /// below this one.
+    pub fn walk<F: FnMut(&LayoutBox<'a>, usize)>(&self, mut f: F) {
+        self.walk_from(0, &mut f);
+    }
+
+    fn walk_from<F: FnMut(&LayoutBox<'a>, usize)>(&self, depth: usize, f: &mut F) {
+        (*f)(self, depth);
+        for child in self.children.iter() {
+            child.walk_from(depth + 1, f);
+        }
+    }
+
+    /// Like `walk`, but `f` may modify the boxes. Each box is visited before its children.
+    pub fn walk_mut<F: FnMut(&mut LayoutBox<'a>, usize)>(&mut self, mut f: F) {
+        self.walk_mut_from(0, &mut f);
+    }
+
+    fn walk_mut_from<F: FnMut(&mut LayoutBox<'a>, usize)>(&mut self, depth: usize, f: &mut F) {
+        (*f)(self, depth);
+        for child in self.children.iter_mut() {
+            child.walk_mut_from(depth + 1, f);
+        }
+    }
+
+    /// The frontmost box in this tree under the point (`x`, `y`), or `None` if there isn't one.
+    ///
+    /// Block boxes are hit inside their border box, and inline boxes inside one of their
+    /// fragments. Boxes with `pointer-events: none` are skipped, so the box beneath them is found
+    /// instead. `pointer-events` is inherited, so this applies to their descendants too unless
+    /// they set it back to `auto`.
+    ///
+    /// Boxes are tried from front to back, in the reverse of `paint_order`, so stacking contexts
+    /// and `z-index` are taken into account.
+    pub fn hit_test(&self, x: f32, y: f32) -> Option<&LayoutBox<'a>> {
+        self.paint_order().into_iter().rev().find(|layout_box| layout_box.is_hit(x, y))
+    }
+
+    /// Is the point (`x`, `y`) inside this box, and can it be hit there?
+    fn is_hit(&self, x: f32, y: f32) -> bool {
+        let style = match self.box_type {
+            BlockNode(style) | InlineNode(style) => style,
+            AnonymousBlock => return false
+        };
+        if style.pointer_events() == PointerEvents::None {
+            return false;
+        }
+        match self.box_type {
+            InlineNode(_) if !self.is_atomic_inline() =>
+                self.fragments.iter().any(|fragment| fragment.rect.contains(x, y)),
+            _ => self.dimensions.border_box().contains(x, y)
+        }
+    }
+
+    /// Every box in this tree that overlaps `rect`, in paint order (back to front). See
+    /// `paint_order`.
+    ///
+    /// As in `hit_test`, block boxes are measured by their border box and inline boxes by their
+    /// fragments.
+    pub fn boxes_in_rect(&self, rect: Rect) -> Vec<&LayoutBox<'a>> {
+        self.paint_order().into_iter().filter(|layout_box| layout_box.overlaps(rect)).collect()
+    }
+
+    /// Does this box overlap `rect`? Block boxes are measured by their border box and inline
+    /// boxes by their fragments.
+    fn overlaps(&self, rect: Rect) -> bool {
+        match self.box_type {
+            InlineNode(_) if !self.is_atomic_inline() =>
+                self.fragments.iter().any(|fragment| fragment.rect.intersects(rect)),
+            _ => self.dimensions.border_box().intersects(rect)
+        }
+    }
+
+    /// Every box in this tree, in the order the painter draws them (back to front).
+    ///
+    /// This box is painted as a stacking context, in the order of CSS2 Appendix E:
+    ///
+    /// 1. the box itself;
+    /// 2. the layers with a negative `z-index` (see `stacking_layers`);
+    /// 3. its in-flow, block-level descendants;
+    /// 4. its floats;
+    /// 5. its inline-level descendants;
+    /// 6. the rest of the layers.
+    ///
+    /// Each group is in tree order. Floats and atomic inlines are painted as a whole, each one
+    /// followed by its own contents in the same order, but any layers inside them belong to the
+    /// stacking context. Each layer that forms a stacking context is painted the same way; a
+    /// positioned box with `z-index: auto` is painted like a float.
+    ///
+    /// http://www.w3.org/TR/CSS2/zindex.html
+    pub fn paint_order(&self) -> Vec<&LayoutBox<'a>> {
+        let mut boxes = Vec::new();
+        self.collect_paint_order(&mut boxes);
+        boxes
+    }
+
+    fn collect_paint_order<'b>(&'b self, boxes: &mut Vec<&'b LayoutBox<'a>>) {
+        let layers = self.stacking_layers();
+        boxes.push(self);
+        for &(_, layer) in layers.iter().filter(|&&(z, _)| z < 0) {
+            layer.collect_layer_paint_order(boxes);
+        }
+        self.collect_in_flow_paint_order(boxes);
+        for &(_, layer) in layers.iter().filter(|&&(z, _)| z >= 0) {
+            layer.collect_layer_paint_order(boxes);
+        }
+    }
+
+    /// Collect a layer of a stacking context. The layers inside a box with `z-index: auto` were
+    /// already collected with the stacking context's own.
+    fn collect_layer_paint_order<'b>(&'b self, boxes: &mut Vec<&'b LayoutBox<'a>>) {
+        match self.layer_type() {
+            LayerType::Auto => {
+                boxes.push(self);
+                self.collect_in_flow_paint_order(boxes);
+            }
+            _ => self.collect_paint_order(boxes)
+        }
+    }
+
+    /// Collect the descendants of this box that aren't layers, in the order of steps 3 to 5 of
+    /// `paint_order`.
+    fn collect_in_flow_paint_order<'b>(&'b self, boxes: &mut Vec<&'b LayoutBox<'a>>) {
+        let mut blocks = Vec::new();
+        let mut floats = Vec::new();
+        let mut inlines = Vec::new();
+        self.sort_in_flow_descendants(&mut blocks, &mut floats, &mut inlines);
+
+        boxes.extend(blocks.into_iter());
+        for &float in floats.iter() {
+            boxes.push(float);
+            float.collect_in_flow_paint_order(boxes);
+        }
+        for &inline in inlines.iter() {
+            boxes.push(inline);
+            if inline.is_atomic_inline() {
+                inline.collect_in_flow_paint_order(boxes);
+            }
+        }
+    }
+
+    /// Sort the descendants of this box that aren't layers into block-level boxes, floats, and
+    /// inline-level boxes, in tree order. The contents of floats and atomic inlines are left out.
+    fn sort_in_flow_descendants<'b>(&'b self, blocks: &mut Vec<&'b LayoutBox<'a>>,
+                                    floats: &mut Vec<&'b LayoutBox<'a>>,
+                                    inlines: &mut Vec<&'b LayoutBox<'a>>) {
+        if self.skips_contents() {
+            return;
+        }
+        for child in self.children.iter().filter(|&child| child.layer_type() == LayerType::InFlow) {
+            if child.is_float() {
+                floats.push(child);
+                continue;
+            }
+            match child.box_type {
+                InlineNode(_) => {
+                    inlines.push(child);
+                    if child.is_atomic_inline() {
+                        continue;
+                    }
+                }
+                BlockNode(_) | AnonymousBlock => blocks.push(child)
+            }
+            child.sort_in_flow_descendants(blocks, floats, inlines);
+        }
+    }
+
+    /// The layers of the stacking context formed by this box: the positioned or transformed
+    /// descendants that aren't inside a nested stacking context, with their z-indexes. They are
+    /// sorted from lowest to highest `z-index` and then in tree order.
+    ///
+    /// A layer that forms its own stacking context is painted as a whole, so the z-indexes of its
+    /// descendants only order them within it. Layers with `z-index: auto` are painted at
+    /// `z-index: 0`, but the layers inside them belong to this stacking context.
+    fn stacking_layers(&self) -> Vec<(i32, &LayoutBox<'a>)> {
+        let mut layers = Vec::new();
+        if self.skips_contents() {
+            return layers;
+        }
+        for child in self.children.iter() {
+            child.collect_layers(&mut layers);
+        }
+        layers.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
+        layers
+    }
+
+    fn collect_layers<'b>(&'b self, layers: &mut Vec<(i32, &'b LayoutBox<'a>)>) {
+        match self.layer_type() {
+            LayerType::StackingContext(z) => {
+                layers.push((z, self));
+                return;
+            }
+            LayerType::Auto => layers.push((0, self)),
+            LayerType::InFlow => {}
+        }
+        if self.skips_contents() {
+            return;
+        }
+        for child in self.children.iter() {
+            child.collect_layers(layers);
+        }
+    }
+
+    /// Is this box sized as if it were empty, because of `contain: size` or
+    /// `content-visibility: hidden`?
+    fn has_size_containment(&self) -> bool {
+        let style = match self.box_type {
+            BlockNode(style) => style,
+            InlineNode(style) if self.is_atomic_inline() => style,
+            _ => return false
+        };
+        self.skips_contents() || style.contain().size
+    }
+
+    /// The width and height of a box with size containment, from `contain-intrinsic-size`.
+    fn contained_size(&self) -> (f32, f32) {
+        self.get_style_node().contain_intrinsic_size().unwrap_or((0.0, 0.0))
+    }
+
+    /// Does this box skip laying out and painting its contents, because of
+    /// `content-visibility: hidden`? The box itself is still laid out and painted, as if it were
+    /// empty.
+    pub fn skips_contents(&self) -> bool {
+        match self.box_type {
+            BlockNode(style) => style.content_visibility() == ContentVisibility::Hidden,
+            InlineNode(style) if self.is_atomic_inline() =>
+                style.content_visibility() == ContentVisibility::Hidden,
+            _ => false
+        }
+    }
+
+    /// How this box is painted within its stacking context.
+    fn layer_type(&self) -> LayerType {
+        let style = match self.box_type {
+            BlockNode(style) | InlineNode(style) => style,
+            AnonymousBlock => return LayerType::InFlow
+        };
+        let positioned = style.position() != Position::Static;
+        match style.z_index() {
+            Some(z) if positioned => LayerType::StackingContext(z),
+            _ if !style.transform().is_empty() => LayerType::StackingContext(0),
+            _ if positioned => LayerType::Auto,
+            _ => LayerType::InFlow
+        }
+    }
+
+    /// The nearest ancestor of `descendant` in this tree whose `overflow` is not `visible`, which
+    /// is the box that scrolls to reveal it.
+    ///
+    /// Boxes don't point to their parents, so this searches down from `self`, typically the root.
+    /// Returns `None` if no ancestor is a scroll container (so the viewport scrolls), or if
+    /// `descendant` is not in this tree.
+    pub fn scroll_parent(&self, descendant: &LayoutBox) -> Option<&LayoutBox<'a>> {
+        let mut ancestors = Vec::new();
+        if !self.find_ancestors(descendant, &mut ancestors) {
+            return None;
+        }
+        ancestors.into_iter().rev().find(|ancestor| ancestor.is_scroll_container())
+    }
+
+    /// If `target` is this box or one of its descendants, push the boxes from `self` down to the
+    /// parent of `target` onto `ancestors` and return true.
+    fn find_ancestors<'b>(&'b self, target: &LayoutBox, ancestors: &mut Vec<&'b LayoutBox<'a>>)
+                          -> bool {
+        if self as *const _ as usize == target as *const _ as usize {
+            return true;
+        }
+        ancestors.push(self);
+        for child in self.children.iter() {
+            if child.find_ancestors(target, ancestors) {
+                return true;
+            }
+        }
+        ancestors.pop();
+        false
+    }
+}
+
+/// Transform a style tree into a layout tree, measuring text in the built-in font.
+pub fn layout_tree<'a>(node: &'a StyledNode<'a>, containing_block: Dimensions) -> LayoutBox<'a> {
+    layout_tree_with_metrics(node, containing_block, &BUILTIN_FONT)
+}
+
+/// Transform a style tree into a layout tree, measuring text with `metrics`.
+pub fn layout_tree_with_metrics<'a>(node: &'a StyledNode<'a>, containing_block: Dimensions,
+                                    metrics: &'a (FontMetrics + 'a)) -> LayoutBox<'a> {
+    layout_tree_with_recorder(node, containing_block, metrics, None)
+}
+
+/// Transform a style tree into a layout tree like `layout_tree_with_metrics`, reporting the time
+/// taken by each phase of block layout to `recorder`.
+pub fn layout_tree_with_recorder<'a>(node: &'a StyledNode<'a>, containing_block: Dimensions,
+                                     metrics: &'a (FontMetrics + 'a),
+                                     recorder: Option<&'a (LayoutRecorder + 'a)>)
+                                     -> LayoutBox<'a> {
+    layout_tree_in(node, containing_block, metrics, recorder, None, None)
 }
 
-/// Transform a style tree into a layout tree.
-pub fn layout_tree<'a>(node: &'a StyledNode<'a>, mut containing_block: Dimensions) -> LayoutBox<'a> {
+/// Transform the style tree of a document into a layout tree like `layout_tree_with_recorder`,
+/// loading its images from `images`.
+///
+/// Blocks whose elements haven't changed since they were laid out in the same containing block
+/// width and viewport are copied from `cache` instead of being laid out again, so they aren't
+/// reported to `recorder`. Call `Node::propagate_dirty` on the root first.
+pub fn layout_document<'a>(node: &'a StyledNode<'a>, containing_block: Dimensions,
+                           metrics: &'a (FontMetrics + 'a),
+                           recorder: Option<&'a (LayoutRecorder + 'a)>,
+                           images: &'a ImageCache, cache: &'a LayoutCache) -> LayoutBox<'a> {
+    layout_tree_in(node, containing_block, metrics, recorder, Some(images), Some(cache))
+}
+
+fn layout_tree_in<'a>(node: &'a StyledNode<'a>, mut containing_block: Dimensions,
+                      metrics: &'a (FontMetrics + 'a),
+                      recorder: Option<&'a (LayoutRecorder + 'a)>,
+                      images: Option<&'a ImageCache>, cache: Option<&'a LayoutCache>)
+                      -> LayoutBox<'a> {
+    // Fixed-position boxes are laid out against the full initial containing block.
+    // TODO: Also use its height for calculating percent heights.
+    let viewport = containing_block;
+
     // The layout algorithm expects the container height to start at 0.
-    // TODO: Save the initial containing block height, for calculating percent heights.
     containing_block.content.height = 0.0;
 
-    let mut root_box = build_layout_tree(node);
-    root_box.layout(containing_block);
+    let mut root_box = build_layout_tree(node, metrics);
+    root_box.walk_mut(|b, _| {
+        b.recorder = recorder;
+        b.images = images;
+        b.cache = cache;
+    });
+    if recorder.is_some() {
+        root_box.count_boxes();
+    }
+    root_box.layout(containing_block, viewport);
+
+    // Absolutely positioned boxes are laid out once the boxes they are positioned against are.
+    root_box.layout_absolute_descendants(viewport, viewport);
+
+    if let Some(cache) = cache {
+        cache.forget_missing_blocks(&root_box);
+    }
     return root_box;
 }
 
+/// Lay out several independent style trees in the same viewport, for rendering many small
+/// fragments in one go. The results are in the same order as `nodes`.
+///
+/// All of the trees measure text with `metrics` and load their images from `images`, so an
+/// image used by several of them is only decoded once.
+pub fn layout_many<'a>(nodes: &'a [StyledNode<'a>], viewport: Dimensions,
+                       metrics: &'a (FontMetrics + 'a), images: &'a ImageCache)
+                       -> Vec<LayoutBox<'a>> {
+    let mut results = Vec::with_capacity(nodes.len());
+    for node in nodes.iter() {
+        results.push(layout_tree_in(node, viewport, metrics, Some(images), None));
+    }
+    return results;
+}
+
 /// Build the tree of LayoutBoxes, but don't perform any layout calculations yet.
-fn build_layout_tree<'a>(style_node: &'a StyledNode<'a>) -> LayoutBox<'a> {
-    // Create the root box.
-    let mut root = LayoutBox::new(match style_node.display() {
-        Display::Block => BlockNode(style_node),
-        Display::Inline => InlineNode(style_node),
+fn build_layout_tree<'a>(style_node: &'a StyledNode<'a>, metrics: &'a (FontMetrics + 'a))
+                         -> LayoutBox<'a> {
+    let mut root = build_box(style_node, style_node.display(), metrics);
+    // The initial containing block has the direction of the root element.
+    root.set_containing_direction(style_node.direction());
+    root
+}
+
+/// Build the box for `style_node` and its descendants, as if it had `display`.
+fn build_box<'a>(style_node: &'a StyledNode<'a>, display: Display,
+                 metrics: &'a (FontMetrics + 'a)) -> LayoutBox<'a> {
+    // Create the root box. The root element always generates a box, so `display: contents`
+    // acts like `block` there.
+    let mut root = LayoutBox::new(match display {
+        Display::Block | Display::Table | Display::TableRow | Display::TableCell |
+        Display::Flex | Display::Grid | Display::Contents => BlockNode(style_node),
+        Display::Inline | Display::InlineBlock | Display::InlineFlex => InlineNode(style_node),
         Display::None => panic!("Root node has display: none.")
-    });
+    }, metrics);
 
     // Create the descendant boxes.
+    build_children(&mut root, style_node, display);
+    return root;
+}
+
+/// Build the boxes for the children of `style_node`, and add them to `parent`, whose own
+/// `display` is `display`.
+///
+/// The children of a `display: contents` element are added to `parent` directly, as if they
+/// were children of `style_node`. They still inherit their styles from the element.
+fn build_children<'a>(parent: &mut LayoutBox<'a>, style_node: &'a StyledNode<'a>,
+                      display: Display) {
     for child in style_node.children.iter() {
-        match child.display() {
-            Display::Block => root.children.push(build_layout_tree(child)),
-            Display::Inline => root.get_inline_container().children.push(build_layout_tree(child)),
+        let child_display = match (display, child.text()) {
+            // The non-text children of a flex or grid container are "blockified" into items.
+            (Display::Flex, None) | (Display::InlineFlex, None) | (Display::Grid, None)
+                => blockify(child.display()),
+            _ => child.display()
+        };
+        let metrics = parent.metrics;
+        match child_display {
+            Display::Block | Display::Table | Display::TableRow | Display::TableCell |
+            Display::Flex | Display::Grid
+                => parent.children.push(build_box(child, child_display, metrics)),
+            Display::Inline | Display::InlineBlock | Display::InlineFlex
+                => parent.get_inline_container().children.push(build_box(child, child_display,
+                                                                         metrics)),
+            Display::Contents => build_children(parent, child, display),
             Display::None => {} // Don't lay out nodes with `display: none;`
         }
     }
-    return root;
+}
+
+/// The block-level equivalent of `display`.
+///
+/// http://www.w3.org/TR/css-display-3/#blockify
+fn blockify(display: Display) -> Display {
+    match display {
+        Display::Inline | Display::InlineBlock => Display::Block,
+        Display::InlineFlex => Display::Flex,
+        _ => display
+    }
 }
 
 impl<'a> LayoutBox<'a> {
     /// Lay out a box and its descendants.
-    fn layout(&mut self, containing_block: Dimensions) {
+    ///
+    /// `viewport` is the initial containing block, used by fixed-position descendants.
+    fn layout(&mut self, containing_block: Dimensions, viewport: Dimensions) {
+        match self.box_type {
+            // A box that skips its contents is sized like an empty block.
+            BlockNode(_) if self.skips_contents() => self.layout_block(containing_block, viewport),
+            BlockNode(style) => match style.display() {
+                Display::Table => self.layout_table(containing_block, viewport),
+                // A blockified `inline-flex` item is still a flex container.
+                Display::Flex | Display::InlineFlex => self.layout_flex(containing_block, viewport),
+                Display::Grid => self.layout_grid(containing_block, viewport),
+                _ => self.layout_block(containing_block, viewport)
+            },
+            InlineNode(_) => {} // Laid out by the containing anonymous block.
+            AnonymousBlock => self.layout_anonymous_block(containing_block, 0.0, viewport),
+        }
+    }
+
+    /// Lay out a block in normal flow like `layout`, but reuse the geometry from the last layout
+    /// of its element if it and its descendants haven't changed and it is in a containing block
+    /// of the same width, in the same viewport, with the same font metrics. The reused boxes are
+    /// moved to the new position.
+    ///
+    /// This relies on `Node::propagate_dirty` having marked the ancestors of every changed node.
+    /// Subtrees with positioned boxes aren't cached, because those depend on boxes outside.
+    ///
+    /// TODO: Generated content using counters can change when earlier elements change, which
+    /// doesn't invalidate the cache.
+    fn layout_cached(&mut self, containing_block: Dimensions, viewport: Dimensions) {
+        let node = match self.box_type {
+            BlockNode(style) if style.element().is_some() => style.node,
+            _ => return self.layout(containing_block, viewport)
+        };
+        let cache = match self.cache {
+            Some(cache) => cache,
+            None => return self.layout(containing_block, viewport)
+        };
+        let cb = containing_block.content;
+        let origin = (cb.x, cb.y + cb.height);
+        let viewport_size = (viewport.content.width, viewport.content.height);
+        let metrics = metrics_id(self.metrics);
+
+        if !node.is_dirty() {
+            if let Some(block) = cache.blocks.borrow().get(&node.id()) {
+                if block.containing_width == cb.width && block.viewport == viewport_size &&
+                   block.metrics == metrics {
+                    let mut boxes = block.boxes.iter();
+                    self.walk_mut(|layout_box, _| {
+                        let &(dimensions, ref fragments) = boxes.next().unwrap();
+                        layout_box.dimensions = dimensions;
+                        layout_box.fragments = fragments.clone();
+                    });
+                    self.translate(origin.0 - block.origin.0, origin.1 - block.origin.1);
+                    return;
+                }
+            }
+        }
+
+        self.layout(containing_block, viewport);
+        cache.recomputed.set(cache.recomputed.get() + 1);
+        let mut positioned = false;
+        let mut boxes = Vec::new();
+        self.walk(|layout_box, _| {
+            positioned = positioned || layout_box.is_fixed() || layout_box.is_absolute();
+            boxes.push((layout_box.dimensions, layout_box.fragments.clone()));
+        });
+        if positioned {
+            cache.blocks.borrow_mut().remove(&node.id());
+        } else {
+            cache.blocks.borrow_mut().insert(node.id(), CachedBlock {
+                containing_width: cb.width,
+                viewport: viewport_size,
+                metrics: metrics,
+                origin: origin,
+                boxes: boxes,
+            });
+        }
+    }
+
+    /// Is this an inline-level box that is laid out as a single unit, like `inline-block` or an
+    /// inline replaced element?
+    fn is_atomic_inline(&self) -> bool {
+        match self.box_type {
+            InlineNode(style) => style.display() == Display::InlineBlock ||
+                                 style.display() == Display::InlineFlex || self.is_replaced(),
+            _ => false
+        }
+    }
+
+    /// Is this box for a replaced element, whose content is an image rather than its children?
+    /// Only `<img>` elements are replaced.
+    fn is_replaced(&self) -> bool {
+        match self.box_type {
+            BlockNode(style) | InlineNode(style) =>
+                style.element().map_or(false, |elem| &*elem.tag_name == "img"),
+            AnonymousBlock => false
+        }
+    }
+
+    /// The intrinsic width and height of a replaced element, or `None` if this box isn't one.
+    ///
+    /// They come from the `width` and `height` attributes, or else from the image itself. If
+    /// only one attribute is set, the other follows from the image's aspect ratio.
+    fn replaced_intrinsic_size(&self) -> Option<(f32, f32)> {
+        if !self.is_replaced() {
+            return None;
+        }
+        let elem = self.get_style_node().element().unwrap();
+        let attr = |&: name: &str| -> Option<f32> {
+            elem.attributes.get(name).and_then(|value| FromStr::from_str(value.trim()))
+        };
+        let (width, height) = (attr("width"), attr("height"));
+        if let (Some(w), Some(h)) = (width, height) {
+            return Some((w, h));
+        }
+        let natural = elem.attributes.get("src").and_then(|src| self.load_image(&**src))
+                                                .map(|image| (image.width as f32,
+                                                              image.height as f32));
+        match (width, height, natural) {
+            (Some(w), None, Some((iw, ih))) if iw > 0.0 => Some((w, w * ih / iw)),
+            (None, Some(h), Some((iw, ih))) if ih > 0.0 => Some((h * iw / ih, h)),
+            (Some(w), None, _) => Some((w, 0.0)),
+            (None, Some(h), _) => Some((0.0, h)),
+            _ => natural
+        }
+    }
+
+    /// Is this a box with `visibility: collapse`, like a collapsed table row?
+    fn is_collapsed(&self) -> bool {
+        match self.box_type {
+            BlockNode(style) => style.visibility() == Visibility::Collapse,
+            _ => false
+        }
+    }
+
+    /// Is this box taken out of normal flow and positioned relative to the viewport?
+    fn is_fixed(&self) -> bool {
+        match self.box_type {
+            BlockNode(style) => style.position() == Position::Fixed,
+            _ => false
+        }
+    }
+
+    /// Is this box taken out of normal flow and positioned relative to its nearest positioned
+    /// ancestor?
+    fn is_absolute(&self) -> bool {
+        match self.box_type {
+            BlockNode(style) => style.position() == Position::Absolute,
+            _ => false
+        }
+    }
+
+    /// Does this box clip its content, because its `overflow` is not `visible`?
+    ///
+    /// Overflow is clipped on both axes or neither, since `visible` computes to `auto` when the
+    /// other axis isn't `visible`.
+    fn is_scroll_container(&self) -> bool {
+        match self.box_type {
+            BlockNode(style) => style.overflow_x() != Overflow::Visible,
+            _ => false
+        }
+    }
+
+    /// Is this box floated to the left or right, out of normal flow?
+    fn is_float(&self) -> bool {
+        match self.box_type {
+            BlockNode(style) => style.float() != CssFloat::None,
+            _ => false
+        }
+    }
+
+    /// Is this box's auto height big enough to contain its floated children? This is true of
+    /// boxes that establish a new block formatting context, including boxes with layout or paint
+    /// containment.
+    ///
+    /// http://www.w3.org/TR/CSS2/visuren.html#block-formatting
+    fn contains_floats(&self) -> bool {
         match self.box_type {
-            BlockNode(_) => self.layout_block(containing_block),
-            InlineNode(_) => {} // TODO
-            AnonymousBlock => {} // TODO
+            BlockNode(style) => self.is_scroll_container() ||
+                                style.contain().layout || style.contain().paint ||
+                                style.display() == Display::TableCell ||
+                                style.display() == Display::Flex ||
+                                style.display() == Display::InlineFlex ||
+                                style.display() == Display::Grid ||
+                                self.is_float() || self.is_fixed() || self.is_absolute(),
+            InlineNode(_) => self.is_atomic_inline(),
+            AnonymousBlock => false
         }
     }
 
     /// Lay out a block-level element and its descendants.
-    fn layout_block(&mut self, containing_block: Dimensions) {
+    fn layout_block(&mut self, containing_block: Dimensions, viewport: Dimensions) {
         // Child width can depend on parent width, so we need to calculate this box's width before
         // laying out its children.
-        self.calculate_block_width(containing_block);
+        self.timed(LayoutPhase::Width, |b| {
+            b.calculate_block_width(containing_block);
 
-        // Determine where the box is located within its container.
-        self.calculate_block_position(containing_block);
+            // Determine where the box is located within its container.
+            b.calculate_block_position(containing_block);
+        });
 
         // Recursively lay out the children of this box.
-        self.layout_block_children();
+        self.timed(LayoutPhase::Content, |b| b.layout_block_children(viewport));
 
         // Parent height can depend on child height, so `calculate_height` must be called after the
         // children are laid out.
+        self.timed(LayoutPhase::Height, |b| b.calculate_block_height());
+    }
+
+    /// Run one phase of laying out this box, and report how long it took to the recorder if
+    /// there is one.
+    fn timed<F: FnOnce(&mut LayoutBox<'a>)>(&mut self, phase: LayoutPhase, f: F) {
+        let recorder = match self.recorder {
+            Some(recorder) => recorder,
+            None => return f(self)
+        };
+        let duration = {
+            let this = &mut *self;
+            Duration::span(move || f(this))
+        };
+        recorder.record(phase, self, duration, self.box_count);
+    }
+
+    /// Set `box_count` on this box and its descendants, in a single pass from the leaves up.
+    /// Returns the count for this box.
+    fn count_boxes(&mut self) -> usize {
+        let mut count = 1;
+        for child in self.children.iter_mut() {
+            count = count + child.count_boxes();
+        }
+        self.box_count = count;
+        count
+    }
+
+    /// Lay out a `display: table` box. Its children are rows, stacked vertically, and their
+    /// children are cells, arranged side by side in columns.
+    ///
+    /// This is a simplified version of the fixed table layout algorithm:
+    /// http://www.w3.org/TR/CSS2/tables.html#fixed-table-layout
+    ///
+    /// Rows with `visibility: collapse` take up no space, so the rows after them move up.
+    fn layout_table(&mut self, containing_block: Dimensions, viewport: Dimensions) {
+        // The table box itself is sized and positioned like a block.
+        self.calculate_block_width(containing_block);
+        self.calculate_block_position(containing_block);
+
+        let column_widths = self.table_column_widths();
+        if !self.skips_contents() {
+            let d = &mut self.dimensions;
+            d.content.height = 0.0;
+            for row in self.children.iter_mut() {
+                if row.is_collapsed() {
+                    // The row and its cells are left out of layout altogether.
+                    row.walk_mut(|b, _| {
+                        b.dimensions = Default::default();
+                        b.fragments.clear();
+                    });
+                    row.dimensions.content = Rect { x: d.content.x,
+                                                    y: d.content.y + d.content.height,
+                                                    width: d.content.width, height: 0.0 };
+                    continue;
+                }
+                row.layout_table_row(*d, &*column_widths, viewport);
+                // Increment the height so each row is laid out below the previous one.
+                d.content.height = d.content.height + row.dimensions.margin_box().height;
+            }
+        }
+
+        self.calculate_block_height();
+    }
+
+    /// Lay out a `display: flex` container and its items.
+    ///
+    /// http://www.w3.org/TR/css-flexbox-1/#layout-algorithm
+    ///
+    /// Items are laid out side by side in `order`, separated by `column-gap`. With
+    /// `flex-wrap: wrap`, they are broken into several lines that are stacked vertically and
+    /// separated by `row-gap`. Free space in each line is shared out according to `flex-grow`,
+    /// but items never shrink. Any space that is still left over is distributed by
+    /// `justify-content`. Items are aligned vertically within their line by `align-items`.
+    fn layout_flex(&mut self, containing_block: Dimensions, viewport: Dimensions) {
+        // The container itself is sized and positioned like a block.
+        self.calculate_block_width(containing_block);
+        self.calculate_block_position(containing_block);
+        self.layout_flex_items(None, viewport);
         self.calculate_block_height();
     }
 
+    /// Lay out the items of a flex container in rows, starting from the top of its content area.
+    ///
+    /// `stretched_height` is the height this container was stretched to as an item of another
+    /// flex container, if any. Like a specified `height`, a single line is aligned within it.
+    ///
+    /// Sets `self.dimensions.height` to the total height of the flex lines.
+    fn layout_flex_items(&mut self, stretched_height: Option<f32>, viewport: Dimensions) {
+        if self.skips_contents() {
+            // The items keep their empty dimensions, and take up no space.
+            self.dimensions.content.height = self.contained_size().1;
+            return;
+        }
+        let style = self.get_style_node();
+        let container = self.dimensions;
+        let zero = Length(0.0, Px);
+        let gap = resolve_percentage(style.value("column-gap").unwrap_or(zero.clone()),
+                                     container.content.width).to_px();
+        let row_gap = style.value("row-gap").unwrap_or(zero).to_px();
+        if style.flex_direction() == FlexDirection::Column {
+            return self.layout_flex_column(row_gap, stretched_height, viewport);
+        }
+
+        // Find the size of each item along the main axis: its own width if it has one, and
+        // otherwise the width of its content. `edges` holds the width of its margins, borders,
+        // and padding.
+        let items = self.flex_items_in_order(viewport);
+        let mut sizes = Vec::new();
+        let mut edges = Vec::new();
+        let mut grow_factors = Vec::new();
+        for &i in items.iter() {
+            let child = &mut self.children[i];
+            let size = child.flex_base_size(container);
+            let d = child.dimensions;
+            sizes.push(size);
+            edges.push(d.margin_box().width - d.content.width);
+            grow_factors.push(match child.box_type {
+                BlockNode(style) => style.flex_grow(),
+                _ => 0.0
+            });
+        }
+
+        // Break the items into lines, as the range of `items` on each line. Without
+        // `flex-wrap: wrap`, they all go on a single line.
+        let wrap = style.flex_wrap() == FlexWrap::Wrap;
+        let mut lines = Vec::new();
+        let mut start = 0;
+        let mut line_width: f32 = 0.0;
+        for j in (0 .. items.len()) {
+            let outer_size = sizes[j] + edges[j];
+            if wrap && j > start && line_width + gap + outer_size > container.content.width {
+                lines.push((start, j));
+                start = j;
+            }
+            line_width = if j > start { line_width + gap + outer_size } else { outer_size };
+        }
+        if start < items.len() {
+            lines.push((start, items.len()));
+        }
+
+        // A single line fills the height of the container, if it has one.
+        let definite_height = match style.value("height") {
+            _ if lines.len() > 1 => None,
+            Some(Length(h, Px)) => Some(h),
+            _ => stretched_height
+        };
+
+        let mut y = container.content.y;
+        for (line, &(start, end)) in lines.iter().enumerate() {
+            if line > 0 {
+                y = y + row_gap;
+            }
+
+            // Share out any free space in proportion to the grow factors.
+            let n = (end - start) as f32;
+            let used_width = (start .. end).map(|j| sizes[j] + edges[j]).sum() + gap * (n - 1.0);
+            let mut free_space = (container.content.width - used_width).max(0.0);
+            let total_grow: f32 = (start .. end).map(|j| grow_factors[j]).sum();
+            if free_space > 0.0 && total_grow > 0.0 {
+                for j in (start .. end) {
+                    sizes[j] = sizes[j] + free_space * grow_factors[j] / total_grow;
+                }
+                free_space = 0.0;
+            }
+
+            let (leading_space, extra_gap) = justify_offsets(style.justify_content(), free_space,
+                                                             n);
+
+            // Place the items side by side.
+            let mut x = container.content.x + leading_space;
+            let mut line_height: f32 = 0.0;
+            for j in (start .. end) {
+                let child = &mut self.children[items[j]];
+                child.layout_flex_item(container, x, y, sizes[j], None, viewport);
+                let margin_box = child.dimensions.margin_box();
+                x = x + margin_box.width + gap + extra_gap;
+                line_height = line_height.max(margin_box.height);
+            }
+
+            // Align the items within the height of the line.
+            let cross_size = definite_height.unwrap_or(line_height);
+            let align = style.align_items();
+            for j in (start .. end) {
+                let child = &mut self.children[items[j]];
+                let space = cross_size - child.dimensions.margin_box().height;
+                if align == AlignItems::Stretch {
+                    // Only items with an auto height are stretched.
+                    if !child.has_specified_size("height") && space > 0.0 {
+                        // Lay the item out again at its new height, so that its own contents
+                        // are laid out within it.
+                        let height = child.dimensions.content.height + space;
+                        let x = child.dimensions.margin_box().x;
+                        let width = child.dimensions.content.width;
+                        child.layout_flex_item(container, x, y, width, Some(height), viewport);
+                    }
+                } else {
+                    child.translate(0.0, align_offset(align, space));
+                }
+            }
+            y = y + line_height;
+        }
+        self.dimensions.content.height = y - container.content.y;
+    }
+
+    /// Lay out a `display: grid` container and its items.
+    ///
+    /// http://www.w3.org/TR/css-grid-1/#layout-algorithm
+    ///
+    /// This supports column widths in px and `fr` from `grid-template-columns`. Items are placed
+    /// into the cells in order, filling each row before starting the next, and separated by
+    /// `column-gap` and `row-gap`. Each row is as tall as its tallest item.
+    ///
+    /// TODO: Spanning, explicit placement, and `grid-template-rows`.
+    fn layout_grid(&mut self, containing_block: Dimensions, viewport: Dimensions) {
+        // The container itself is sized and positioned like a block.
+        self.calculate_block_width(containing_block);
+        self.calculate_block_position(containing_block);
+        self.layout_grid_items(viewport);
+        self.calculate_block_height();
+    }
+
+    /// Place the items of a grid container into rows of cells, from the top of its content area.
+    ///
+    /// Sets `self.dimensions.height` to the total height of the rows.
+    fn layout_grid_items(&mut self, viewport: Dimensions) {
+        if self.skips_contents() {
+            // The items keep their empty dimensions, and take up no space.
+            self.dimensions.content.height = self.contained_size().1;
+            return;
+        }
+        let style = self.get_style_node();
+        let container = self.dimensions;
+        let zero = Length(0.0, Px);
+        let column_gap = resolve_percentage(style.value("column-gap").unwrap_or(zero.clone()),
+                                            container.content.width).to_px();
+        let row_gap = style.value("row-gap").unwrap_or(zero).to_px();
+
+        // Without a template, there is a single column as wide as the container.
+        let mut tracks = style.grid_template_columns();
+        if tracks.is_empty() {
+            tracks.push(TrackSize::Fraction(1.0));
+        }
+        let columns = grid_track_widths(&*tracks, container.content.width, column_gap);
+
+        let mut column = 0;
+        let mut x = container.content.x;
+        let mut y = container.content.y;
+        let mut row_height: f32 = 0.0;
+        for child in self.children.iter_mut() {
+            if child.is_fixed() {
+                child.layout_fixed(container, viewport);
+                continue;
+            }
+            if child.is_absolute() {
+                // The static position of an absolute child is the start of the container.
+                child.dimensions = Default::default();
+                child.dimensions.content.x = container.content.x;
+                child.dimensions.content.y = container.content.y;
+                continue;
+            }
+            if column == columns.len() {
+                // Start a new row.
+                column = 0;
+                x = container.content.x;
+                y = y + row_height + row_gap;
+                row_height = 0.0;
+            }
+
+            // Lay out the item as a block in its cell.
+            let mut cell = container;
+            cell.content = Rect { x: x, y: y, width: columns[column], height: 0.0 };
+            child.layout(cell, viewport);
+            row_height = row_height.max(child.dimensions.margin_box().height);
+
+            x = x + columns[column] + column_gap;
+            column = column + 1;
+        }
+        self.dimensions.content.height = y + row_height - container.content.y;
+    }
+
+    /// The width of this flex item's content box before any flexing. Also sets its horizontal
+    /// margin, border, and padding widths.
+    ///
+    /// http://www.w3.org/TR/css-flexbox-1/#flex-base-size
+    fn flex_base_size(&mut self, container: Dimensions) -> f32 {
+        let style = match self.box_type {
+            BlockNode(style) => style,
+            _ => return self.max_content_width()
+        };
+        self.calculate_block_width(container);
+        self.use_specified_horizontal_margins(container);
+        match style.value("width") {
+            Some(Length(..)) => self.dimensions.content.width,
+            _ => self.max_content_width()
+        }
+    }
+
+    /// Lay out the items of a `flex-direction: column` container, stacked from the top of its
+    /// content area and separated by `gap`.
+    ///
+    /// This mirrors the row layout in `layout_flex_items`, with the axes swapped. Each item is
+    /// laid out as a block to find its height. If the container has a definite height (or was
+    /// stretched to `stretched_height`), free space is shared out by `flex-grow` and then
+    /// `justify-content`. Items are aligned horizontally by `align-items`.
+    ///
+    /// Sets `self.dimensions.height` to the total height of the items.
+    ///
+    /// TODO: `flex-wrap` in columns.
+    fn layout_flex_column(&mut self, gap: f32, stretched_height: Option<f32>,
+                          viewport: Dimensions) {
+        let style = self.get_style_node();
+        let container = self.dimensions;
+        let align = style.align_items();
+        let items = self.flex_items_in_order(viewport);
+
+        // Lay out the items in a stack. Stretched items fill the width of the container, and
+        // others are as wide as their content.
+        let mut y = container.content.y;
+        let mut grow_factors = Vec::new();
+        for &i in items.iter() {
+            let child = &mut self.children[i];
+            let mut width = child.flex_base_size(container);
+            if align == AlignItems::Stretch && !child.has_specified_size("width") {
+                width = child.dimensions.content_within(container.content).width.max(0.0);
+            }
+            child.layout_flex_item(container, container.content.x, y, width, None, viewport);
+            y = y + child.dimensions.margin_box().height + gap;
+            grow_factors.push(match child.box_type {
+                BlockNode(style) => style.flex_grow(),
+                _ => 0.0
+            });
+        }
+        let n = items.len() as f32;
+        let used_height = if items.is_empty() { 0.0 } else { y - gap - container.content.y };
+
+        // Share out the free space in a container with a definite height.
+        let definite_height = match style.value("height") {
+            Some(Length(h, Px)) => Some(h),
+            _ => stretched_height
+        };
+        let mut free_space = match definite_height {
+            Some(h) => (h - used_height).max(0.0),
+            None => 0.0
+        };
+        let total_grow: f32 = grow_factors.iter().map(|&g| g).sum();
+        let grow_space = if total_grow > 0.0 { free_space } else { 0.0 };
+        if total_grow > 0.0 {
+            free_space = 0.0;
+        }
+        let (leading_space, extra_gap) = justify_offsets(style.justify_content(), free_space, n);
+
+        // Move each item down by the space added before it, and align it horizontally.
+        let mut offset = leading_space;
+        for (&i, &grow) in items.iter().zip(grow_factors.iter()) {
+            let child = &mut self.children[i];
+            let space = container.content.width - child.dimensions.margin_box().width;
+            let dx = if align == AlignItems::Stretch { 0.0 } else { align_offset(align, space) };
+            child.translate(dx, offset);
+            let growth = if total_grow > 0.0 { grow_space * grow / total_grow } else { 0.0 };
+            child.dimensions.content.height = child.dimensions.content.height + growth;
+            offset = offset + growth + extra_gap;
+        }
+        // The space after the last item isn't part of the container's height.
+        let added_height = if items.is_empty() { 0.0 } else { offset - extra_gap };
+        self.dimensions.content.height = used_height + added_height;
+    }
+
+    /// Lay out any fixed-position children of a flex container, and give absolutely positioned
+    /// children their static position. Return the indices of the remaining children, the flex
+    /// items, sorted by `order` and then by document order.
+    fn flex_items_in_order(&mut self, viewport: Dimensions) -> Vec<usize> {
+        let container = self.dimensions;
+        let mut items = Vec::new();
+        for (i, child) in self.children.iter_mut().enumerate() {
+            if child.is_fixed() {
+                child.layout_fixed(container, viewport);
+            } else if child.is_absolute() {
+                // The static position of an absolute child is the start of the container.
+                child.dimensions = Default::default();
+                child.dimensions.content.x = container.content.x;
+                child.dimensions.content.y = container.content.y;
+            } else {
+                items.push(i);
+            }
+        }
+        let children = &self.children;
+        items.sort_by(|&a, &b| children[a].flex_order().cmp(&children[b].flex_order()));
+        items
+    }
+
+    /// Does this box have a `width` or `height` (named by `property`) that is not auto?
+    fn has_specified_size(&self, property: &str) -> bool {
+        match self.box_type {
+            BlockNode(style) => match style.value(property) {
+                Some(Length(..)) => true,
+                _ => false
+            },
+            _ => false
+        }
+    }
+
+    /// The `order` of a flex item. Anonymous items have the default order, 0.
+    fn flex_order(&self) -> i32 {
+        match self.box_type {
+            BlockNode(style) => style.order(),
+            _ => 0
+        }
+    }
+
+    /// Lay out a flex item with a content box `width` px wide, with its top left margin edge at
+    /// (`x`, `y`).
+    ///
+    /// If `height` is given, the item's content box is that tall, as if its `height` were set.
+    /// This is used for items stretched by `align-items: stretch`.
+    fn layout_flex_item(&mut self, container: Dimensions, x: f32, y: f32, width: f32,
+                        height: Option<f32>, viewport: Dimensions) {
+        let mut containing_block = container;
+        containing_block.content.x = x;
+        containing_block.content.y = y;
+        containing_block.content.height = 0.0;
+        match self.box_type {
+            BlockNode(style) => {
+                self.dimensions.content.width = width;
+                self.calculate_block_position(containing_block);
+                match style.display() {
+                    Display::Flex | Display::InlineFlex => self.layout_flex_items(height, viewport),
+                    Display::Grid => self.layout_grid_items(viewport),
+                    _ => self.layout_block_children(viewport)
+                }
+                self.calculate_block_height();
+            }
+            _ => {
+                // An anonymous item holds text that is directly inside the flex container.
+                containing_block.content.width = width;
+                self.layout(containing_block, viewport);
+            }
+        }
+        if let Some(height) = height {
+            self.dimensions.content.height = height;
+        }
+    }
+
+    /// Set the horizontal margins to their specified values, without stretching them to fill the
+    /// containing block. Auto margins are treated as zero.
+    fn use_specified_horizontal_margins(&mut self, containing_block: Dimensions) {
+        let style = self.get_style_node();
+        let zero = Length(0.0, Px);
+        let cb_width = containing_block.content.width;
+        let d = &mut self.dimensions;
+        d.margin.left = resolve_percentage(style.lookup("margin-left", "margin", &zero),
+                                           cb_width).to_px();
+        d.margin.right = resolve_percentage(style.lookup("margin-right", "margin", &zero),
+                                            cb_width).to_px();
+    }
+
+    /// The width this box's content would have if no lines were broken, not counting its own
+    /// margin, border, and padding.
+    fn max_content_width(&self) -> f32 {
+        self.intrinsic_width(IntrinsicSize::MaxContent)
+    }
+
+    /// The width this box's content would have if every possible line break were taken, not
+    /// counting its own margin, border, and padding. This is the width of the widest word or
+    /// atomic inline.
+    fn min_content_width(&self) -> f32 {
+        self.intrinsic_width(IntrinsicSize::MinContent)
+    }
+
+    /// The min-content or max-content width of this box's content.
+    ///
+    /// TODO: This doesn't account for tables or floats.
+    fn intrinsic_width(&self, size: IntrinsicSize) -> f32 {
+        let style = match self.box_type {
+            AnonymousBlock => {
+                // The inline children, all on one line or each broken as much as possible.
+                let contributions = self.children.iter()
+                                        .map(|child| child.intrinsic_contribution(size));
+                return match size {
+                    IntrinsicSize::MaxContent => contributions.sum(),
+                    IntrinsicSize::MinContent => contributions.fold(0.0, |a: f32, b| a.max(b))
+                };
+            }
+            BlockNode(style) | InlineNode(style) => style
+        };
+        let is_inline = match self.box_type {
+            InlineNode(_) => !self.is_atomic_inline(),
+            _ => false
+        };
+        if let (false, Some(Length(w, Px))) = (is_inline, style.value("width")) {
+            return w;
+        }
+        if let Some((w, _)) = self.replaced_intrinsic_size() {
+            return w;
+        }
+        if self.has_size_containment() {
+            return self.contained_size().0;
+        }
+        match style.text() {
+            Some(text) if style.white_space() == WhiteSpace::Pre => {
+                // Preformatted text only breaks at newlines, so both sizes are its longest line.
+                let font = style.font();
+                let metrics = self.metrics;
+                text.split('\n').map(|line| {
+                    text_width(&*expand_tabs(line, 0, style.tab_size()), &font, metrics)
+                }).fold(0.0, |a: f32, b| a.max(b))
+            }
+            Some(text) => {
+                // Unless whitespace is collapsed, each line between newlines is measured
+                // separately.
+                let font = style.font();
+                let metrics = self.metrics;
+                let white_space = style.white_space();
+                let lines: Vec<&str> = match white_space {
+                    WhiteSpace::Normal => vec![text],
+                    _ => text.split('\n').collect()
+                };
+                lines.iter().map(|line| {
+                    let words = match white_space {
+                        WhiteSpace::PreWrap =>
+                            split_preserved_words(&*expand_tabs(*line, 0, style.tab_size())),
+                        _ => split_words(*line)
+                    };
+                    match size {
+                        IntrinsicSize::MaxContent => {
+                            let mut line = String::new();
+                            for word in words.iter() {
+                                line.push_str(&**word);
+                            }
+                            text_width(line.trim_right(), &font, metrics)
+                        }
+                        IntrinsicSize::MinContent if style.word_break() == WordBreak::BreakAll ||
+                                style.overflow_wrap() == OverflowWrap::Anywhere => {
+                            // A line can break after any character.
+                            line.chars().filter(|c| !c.is_whitespace()).map(|c| {
+                                text_width(&*c.to_string(), &font, metrics)
+                            }).fold(0.0, |a: f32, b| a.max(b))
+                        }
+                        IntrinsicSize::MinContent => words.iter().map(|word| {
+                            text_width(word.trim_right(), &font, metrics)
+                        }).fold(0.0, |a: f32, b| a.max(b))
+                    }
+                }).fold(0.0, |a: f32, b| a.max(b))
+            }
+            None if is_inline && size == IntrinsicSize::MaxContent =>
+                self.children.iter().map(|child| child.intrinsic_contribution(size)).sum(),
+            None =>
+                self.children.iter().map(|child| child.intrinsic_contribution(size))
+                                    .fold(0.0, |a: f32, b| a.max(b))
+        }
+    }
+
+    /// The min-content or max-content width of this box including its horizontal margin,
+    /// border, and padding.
+    fn intrinsic_contribution(&self, size: IntrinsicSize) -> f32 {
+        let style = match self.box_type {
+            BlockNode(style) => style,
+            InlineNode(style) if self.is_atomic_inline() => style,
+            _ => return self.intrinsic_width(size)
+        };
+        let zero = Length(0.0, Px);
+        let edges = style.lookup("margin-left", "margin", &zero).to_px() +
+                    style.lookup("margin-right", "margin", &zero).to_px() +
+                    style.lookup("border-left-width", "border-width", &zero).to_px() +
+                    style.lookup("border-right-width", "border-width", &zero).to_px() +
+                    style.lookup("padding-left", "padding", &zero).to_px() +
+                    style.lookup("padding-right", "padding", &zero).to_px();
+        self.intrinsic_width(size) + edges
+    }
+
+    /// The width of this box's content if its `width` is auto and it shrinks to fit, with
+    /// `available` px available for it: its max-content width, but no wider than the available
+    /// width unless its min-content width is.
+    ///
+    /// http://www.w3.org/TR/CSS2/visudet.html#shrink-to-fit-float
+    fn shrink_to_fit_width(&self, available: f32) -> f32 {
+        self.min_content_width().max(available).min(self.max_content_width())
+    }
+
+    /// Does an auto width shrink to fit this box's content, instead of filling its containing
+    /// block? This is true of floats and inline-blocks. (Absolutely positioned boxes shrink to
+    /// fit too, but are sized by `calculate_positioned_width`.)
+    fn shrinks_to_fit(&self) -> bool {
+        self.is_float() || self.is_atomic_inline()
+    }
+
+    /// The used width for `width: min-content` or `width: max-content`, or `None` if `width` is
+    /// not one of these keywords.
+    fn intrinsic_width_keyword(&self, width: &Value) -> Option<f32> {
+        match *width {
+            Keyword(ref k) if *k == "min-content" => Some(self.min_content_width()),
+            Keyword(ref k) if *k == "max-content" => Some(self.max_content_width()),
+            _ => None
+        }
+    }
+
+    /// The width of each column of this table.
+    ///
+    /// A column is as wide as the widest max-content width of its cells, plus their border and
+    /// padding, so that no cell has to wrap its content. Any width left over in the table is
+    /// shared equally between the columns.
+    fn table_column_widths(&self) -> Vec<f32> {
+        let columns = self.children.iter().map(|row| row.children.len()).max().unwrap_or(0);
+        let mut widths: Vec<f32> = repeat(0.0).take(columns).collect();
+        for row in self.children.iter() {
+            for (i, cell) in row.children.iter().enumerate() {
+                widths[i] = widths[i].max(cell.table_cell_width());
+            }
+        }
+
+        let used_width: f32 = widths.iter().map(|w| *w).sum();
+        let extra = (self.dimensions.content.width - used_width).max(0.0) / columns as f32;
+        widths.iter().map(|w| *w + extra).collect()
+    }
+
+    /// The width this table cell needs to fit its content on one line, including its border
+    /// and padding.
+    fn table_cell_width(&self) -> f32 {
+        let style = match self.box_type {
+            BlockNode(style) => style,
+            _ => return self.max_content_width()
+        };
+        let zero = Length(0.0, Px);
+        self.max_content_width() +
+            style.lookup("border-left-width", "border-width", &zero).to_px() +
+            style.lookup("border-right-width", "border-width", &zero).to_px() +
+            style.lookup("padding-left", "padding", &zero).to_px() +
+            style.lookup("padding-right", "padding", &zero).to_px()
+    }
+
+    /// Lay out a table row below the rows already laid out in `table`, with one cell per column.
+    ///
+    /// The row and all of its cells are as tall as its tallest cell.
+    fn layout_table_row(&mut self, table: Dimensions, column_widths: &[f32], viewport: Dimensions) {
+        let y = table.content.y + table.content.height;
+        let mut x = table.content.x;
+        let mut height: f32 = 0.0;
+        for (cell, &width) in self.children.iter_mut().zip(column_widths.iter()) {
+            let mut column = table;
+            column.content = Rect { x: x, y: y, width: width, height: 0.0 };
+            cell.layout_table_cell(column, viewport);
+            height = height.max(cell.dimensions.margin_box().height);
+            x = x + width;
+        }
+        for cell in self.children.iter_mut() {
+            let d = &mut cell.dimensions;
+            d.content.height = d.content.height + height - d.margin_box().height;
+        }
+        self.dimensions.content = Rect { x: table.content.x, y: y, width: table.content.width,
+                                         height: height };
+    }
+
+    /// Lay out a table cell so that it fills `column`. Margins don't apply to table cells.
+    fn layout_table_cell(&mut self, column: Dimensions, viewport: Dimensions) {
+        let style = match self.box_type {
+            BlockNode(style) => style,
+            _ => return self.layout(column, viewport)
+        };
+
+        // border and padding have initial value 0.
+        let zero = Length(0.0, Px);
+
+        {
+            let d = &mut self.dimensions;
+            d.border.left = style.lookup("border-left-width", "border-width", &zero).to_px();
+            d.border.right = style.lookup("border-right-width", "border-width", &zero).to_px();
+            d.border.top = style.lookup("border-top-width", "border-width", &zero).to_px();
+            d.border.bottom = style.lookup("border-bottom-width", "border-width", &zero).to_px();
+
+            d.padding.left = style.lookup("padding-left", "padding", &zero).to_px();
+            d.padding.right = style.lookup("padding-right", "padding", &zero).to_px();
+            d.padding.top = style.lookup("padding-top", "padding", &zero).to_px();
+            d.padding.bottom = style.lookup("padding-bottom", "padding", &zero).to_px();
+
+            let inner = column.content.contracted_by(d.border).contracted_by(d.padding);
+            d.content.width = inner.width.max(0.0);
+            d.content.x = inner.x;
+            d.content.y = inner.y;
+        }
+
+        self.layout_block_children(viewport);
+        self.calculate_block_height();
+    }
+
+    /// Lay out a `position: fixed` box and its descendants.
+    ///
+    /// http://www.w3.org/TR/CSS2/visuren.html#fixed-positioning
+    ///
+    /// The containing block is always the viewport, no matter where the box's ancestors are.
+    /// `static_block` is the parent box, used to find the static position when the box has no
+    /// `left` or `top` offset.
+    fn layout_fixed(&mut self, static_block: Dimensions, viewport: Dimensions) {
+        self.layout_positioned(static_block, viewport, viewport);
+    }
+
+    /// Lay out the absolutely positioned descendants of this box, and their descendants.
+    ///
+    /// http://www.w3.org/TR/CSS2/visudet.html#containing-block-details
+    ///
+    /// The containing block of an absolute box is the padding box of its nearest ancestor with a
+    /// `position` other than `static` or with layout or paint containment, or `containing_block`
+    /// if there is no such ancestor. When this is called, normal flow layout has already stored
+    /// each absolute box's static position in its dimensions.
+    fn layout_absolute_descendants(&mut self, containing_block: Dimensions, viewport: Dimensions) {
+        let containing_block = match self.box_type {
+            BlockNode(style) | InlineNode(style)
+                    if style.position() != Position::Static || style.contain().layout ||
+                       style.contain().paint => {
+                let mut padding_box: Dimensions = Default::default();
+                padding_box.content = self.dimensions.padding_box();
+                padding_box
+            }
+            _ => containing_block
+        };
+        if self.skips_contents() {
+            return;
+        }
+        for child in self.children.iter_mut() {
+            if child.is_absolute() {
+                let static_block = child.dimensions;
+                child.layout_positioned(static_block, containing_block, viewport);
+            }
+            child.layout_absolute_descendants(containing_block, viewport);
+        }
+    }
+
+    /// Lay out a box that is positioned by `top`, `right`, `bottom`, and `left` offsets from
+    /// the edges of `containing_block`.
+    fn layout_positioned(&mut self, static_block: Dimensions, containing_block: Dimensions,
+                         viewport: Dimensions) {
+        self.calculate_positioned_width(static_block, containing_block);
+        self.calculate_positioned_position(static_block, containing_block);
+        self.layout_block_children(viewport);
+        self.calculate_positioned_height(containing_block);
+    }
+
+    /// Calculate the width and horizontal position of a positioned, non-replaced element.
+    ///
+    /// http://www.w3.org/TR/CSS2/visudet.html#abs-non-replaced-width
+    ///
+    /// Auto margins are treated as zero. An auto width that is not fixed by both `left` and
+    /// `right` shrinks to fit the content.
+    fn calculate_positioned_width(&mut self, static_block: Dimensions,
+                                  containing_block: Dimensions) {
+        let style = self.get_style_node();
+
+        // `width`, `left`, and `right` have initial value `auto`.
+        let auto = Keyword("auto".to_string());
+        let width = style.value("width").unwrap_or(auto.clone());
+        let left = style.value("left").unwrap_or(auto.clone());
+        let right = style.value("right").unwrap_or(auto.clone());
+        let intrinsic_width = self.intrinsic_width_keyword(&width);
+
+        // margin, border, and padding have initial value 0.
+        let zero = Length(0.0, Px);
+
+        let fill = {
+            let d = &mut self.dimensions;
+            d.margin.left = style.lookup("margin-left", "margin", &zero).to_px();
+            d.margin.right = style.lookup("margin-right", "margin", &zero).to_px();
+
+            d.border.left = style.lookup("border-left-width", "border-width", &zero).to_px();
+            d.border.right = style.lookup("border-right-width", "border-width", &zero).to_px();
+
+            d.padding.left = style.lookup("padding-left", "padding", &zero).to_px();
+            d.padding.right = style.lookup("padding-right", "padding", &zero).to_px();
+
+            // The space between the offsets, which `to_px` treats as zero if they are auto.
+            let cb = containing_block.content;
+            d.content_within(Rect {
+                x: cb.x + left.to_px(),
+                width: cb.width - left.to_px() - right.to_px(),
+                ..cb
+            })
+        };
+        let content_width = if let Some(w) = intrinsic_width {
+            w
+        } else if width != auto {
+            width.to_px()
+        } else if left != auto && right != auto {
+            fill.width.max(0.0)
+        } else {
+            self.shrink_to_fit_width(fill.width.max(0.0))
+        };
+
+        let d = &mut self.dimensions;
+        d.content.width = content_width;
+
+        d.content.x = if left != auto {
+            fill.x
+        } else if right != auto {
+            fill.x + fill.width - d.content.width
+        } else {
+            // Static position: where the box would have been in normal flow.
+            d.content_within(static_block.content).x
+        };
+    }
+
+    /// Calculate the vertical edges of a positioned element, and its position if given by `top`
+    /// or by its static position.
+    ///
+    /// http://www.w3.org/TR/CSS2/visudet.html#abs-non-replaced-height
+    fn calculate_positioned_position(&mut self, static_block: Dimensions,
+                                     containing_block: Dimensions) {
+        let style = self.get_style_node();
+        let d = &mut self.dimensions;
+
+        // margin, border, and padding have initial value 0.
+        let zero = Length(0.0, Px);
+
+        // Auto margins are treated as zero.
+        // Percentages are relative to the containing block's width, even on the vertical sides.
+        let cb_width = containing_block.content.width;
+        let lookup = |&: name: &str, shorthand: &str| {
+            resolve_percentage(style.lookup(name, shorthand, &zero), cb_width).to_px()
+        };
+        d.margin.top = lookup("margin-top", "margin");
+        d.margin.bottom = lookup("margin-bottom", "margin");
+
+        d.border.top = style.lookup("border-top-width", "border-width", &zero).to_px();
+        d.border.bottom = style.lookup("border-bottom-width", "border-width", &zero).to_px();
+
+        d.padding.top = lookup("padding-top", "padding");
+        d.padding.bottom = lookup("padding-bottom", "padding");
+
+        let margin_top = match style.value("top") {
+            Some(Length(top, Px)) => containing_block.content.y + top,
+            // If only `bottom` is set, `calculate_positioned_height` moves the box into place.
+            _ => static_block.content.y + static_block.content.height,
+        };
+        d.content.y = d.content_within(Rect { y: margin_top, ..containing_block.content }).y;
+    }
+
+    /// Calculate the height of a positioned element, after its children are laid out.
+    ///
+    /// An auto height comes from the content, like an in-flow block, unless both `top` and
+    /// `bottom` are set. If the box is positioned only by `bottom`, it is moved up from the
+    /// containing block's bottom edge along with its descendants.
+    fn calculate_positioned_height(&mut self, containing_block: Dimensions) {
+        let style = self.get_style_node();
+        let auto = Keyword("auto".to_string());
+        let top = style.value("top").unwrap_or(auto.clone());
+        let bottom = style.value("bottom").unwrap_or(auto.clone());
+        let cb = containing_block.content;
+
+        if let Some(Length(h, Px)) = style.value("height") {
+            self.dimensions.content.height = h;
+        } else if top != auto && bottom != auto {
+            let d = &mut self.dimensions;
+            let fill = d.content_within(Rect {
+                y: cb.y + top.to_px(),
+                height: cb.height - top.to_px() - bottom.to_px(),
+                ..cb
+            });
+            d.content.height = fill.height.max(0.0);
+        }
+
+        if top == auto && bottom != auto {
+            let d = self.dimensions;
+            let fill = d.content_within(Rect { height: cb.height - bottom.to_px(), ..cb });
+            let y = fill.y + fill.height - d.content.height;
+            self.translate(0.0, y - d.content.y);
+        }
+    }
+
+    /// Lay out a floated box, at the top of the space in `containing_block` that is not already
+    /// taken by `left_offset` px of left floats and `right_offset` px of right floats.
+    ///
+    /// http://www.w3.org/TR/CSS2/visuren.html#float-position
+    ///
+    /// An auto width shrinks to fit the content; see `calculate_block_width`.
+    fn layout_float(&mut self, containing_block: Dimensions, left_offset: f32, right_offset: f32,
+                    viewport: Dimensions) {
+        self.calculate_block_width(containing_block);
+        {
+            // Unlike an in-flow block, a float's margins don't stretch to fill its container.
+            // Auto margins are treated as zero.
+            let style = self.get_style_node();
+            let zero = Length(0.0, Px);
+            let cb_width = containing_block.content.width;
+            let d = &mut self.dimensions;
+            d.margin.left = resolve_percentage(style.lookup("margin-left", "margin", &zero),
+                                               cb_width).to_px();
+            d.margin.right = resolve_percentage(style.lookup("margin-right", "margin", &zero),
+                                                cb_width).to_px();
+        }
+        self.calculate_block_position(containing_block);
+        {
+            let right = self.get_style_node().float() == CssFloat::Right;
+            let cb = containing_block.content;
+            let d = &mut self.dimensions;
+            d.content.x = if right {
+                let fill = d.content_within(Rect { width: cb.width - right_offset, ..cb });
+                fill.x + fill.width - d.content.width
+            } else {
+                d.content.x + left_offset
+            };
+        }
+        self.layout_block_children(viewport);
+        self.calculate_block_height();
+    }
+
+    /// Lay out an anonymous block box, which holds the inline children of a block.
+    ///
+    /// It fills the width of its containing block and has no margins, borders, or padding. Its
+    /// first line starts `text_indent` px in from the left.
+    fn layout_anonymous_block(&mut self, containing_block: Dimensions, text_indent: f32,
+                              viewport: Dimensions) {
+        {
+            let d = &mut self.dimensions;
+            d.content.width = containing_block.content.width;
+            d.content.x = containing_block.content.x;
+
+            // Position the box below all the previous boxes in the container.
+            d.content.y = containing_block.content.height + containing_block.content.y;
+        }
+        self.layout_inline_children(text_indent, viewport);
+    }
+
+    /// Break the inline children of this box into line boxes, stacked from the top of its
+    /// content area. The first line is indented by `text_indent` px.
+    ///
+    /// Sets `self.dimensions.height` to the total height of the lines.
+    fn layout_inline_children(&mut self, text_indent: f32, viewport: Dimensions) {
+        let mut lines = LineBuilder::new(self.dimensions.content.width);
+        lines.x = text_indent;
+        for child in self.children.iter_mut() {
+            child.layout_inline(&mut lines, viewport);
+        }
+        for child in self.children.iter_mut() {
+            child.trim_line_ends(&*lines.widths);
+        }
+
+        // Now that each line's height is known, stack the lines vertically.
+        let mut line_tops = Vec::new();
+        let mut y = self.dimensions.content.y;
+        for &height in lines.heights.iter() {
+            line_tops.push(y);
+            y = y + height;
+        }
+        let x = self.dimensions.content.x;
+        for child in self.children.iter_mut() {
+            child.finish_inline(x, &*line_tops, &*lines.heights, VerticalAlign::Baseline);
+        }
+        self.dimensions.content.height = y - self.dimensions.content.y;
+    }
+
+    /// Place this inline box and its descendants on the lines in `lines`.
+    ///
+    /// The fragments are positioned relative to the start of their line. `finish_inline` moves
+    /// them to their final position once all the lines are built.
+    fn layout_inline(&mut self, lines: &mut LineBuilder, viewport: Dimensions) {
+        let style = self.get_style_node();
+        self.fragments.clear();
+        if self.is_atomic_inline() {
+            return self.layout_inline_block(lines, viewport);
+        }
+        match style.text() {
+            Some(text) => match style.white_space() {
+                WhiteSpace::Normal => self.layout_text(text, lines),
+                WhiteSpace::Pre => self.layout_preformatted_text(text, lines, false),
+                WhiteSpace::PreWrap => self.layout_preformatted_text(text, lines, true),
+                WhiteSpace::PreLine => self.layout_pre_line_text(text, lines),
+            },
+            None => {
+                // A `<br>` forces a line break, even if there is room left on the line.
+                if style.element().map_or(false, |elem| &*elem.tag_name == "br") {
+                    lines.force_break(self.line_height(style));
+                }
+                for child in self.children.iter_mut() {
+                    child.layout_inline(lines, viewport);
+                }
+            }
+        }
+    }
+
+    /// The height of each line of this box's text: `line-height` if it is a length, or else the
+    /// line height of the font.
+    fn line_height(&self, style: &StyledNode) -> f32 {
+        match style.value("line-height") {
+            Some(Length(height, Px)) => height,
+            _ => self.metrics.line_height(style.font_size())
+        }
+    }
+
+    /// Remove the collapsible whitespace at the end of each line from the text fragments of this
+    /// inline box and its descendants, so it doesn't take up space on the line. `line_widths` is
+    /// where each line's content ends without it.
+    fn trim_line_ends(&mut self, line_widths: &[f32]) {
+        if self.is_atomic_inline() {
+            // The contents are on lines of their own.
+            return;
+        }
+        let style = self.get_style_node();
+        let collapsible = match style.white_space() {
+            WhiteSpace::Normal | WhiteSpace::PreLine => true,
+            WhiteSpace::Pre | WhiteSpace::PreWrap => false,
+        };
+        if style.text().is_some() && collapsible {
+            let font = style.font();
+            for fragment in self.fragments.iter_mut() {
+                let rect = fragment.rect;
+                let line_width = line_widths[fragment.line];
+                if fragment.text.ends_with(" ") && rect.x + rect.width > line_width {
+                    fragment.text = fragment.text.trim_right().to_string();
+                    fragment.rect.width = text_width(&*fragment.text, &font, self.metrics);
+                }
+            }
+        }
+        for child in self.children.iter_mut() {
+            child.trim_line_ends(line_widths);
+        }
+    }
+
+    /// Lay out an `inline-block` box as a block, or an `inline-flex` box as a flex container, and
+    /// place its margin box on the current line.
+    ///
+    /// The box is laid out at the origin; `finish_inline` moves it into place.
+    fn layout_inline_block(&mut self, lines: &mut LineBuilder, viewport: Dimensions) {
+        // An auto width shrinks to fit the content; see `calculate_block_width`.
+        let mut containing_block: Dimensions = Default::default();
+        containing_block.content.width = lines.width;
+        match self.get_style_node().display() {
+            Display::InlineFlex if !self.skips_contents() =>
+                self.layout_flex(containing_block, viewport),
+            _ => self.layout_block(containing_block, viewport)
+        }
+
+        let margin_box = self.dimensions.margin_box();
+        let (line, x) = lines.place(margin_box.width, 0.0, margin_box.height);
+        lines.ends_with_space = false;
+        self.fragments.push(Fragment {
+            rect: Rect { x: x, y: 0.0, width: margin_box.width, height: margin_box.height },
+            text: String::new(),
+            line: line,
+        });
+    }
+
+    /// Place the words of a text node on the lines in `lines`, wrapping at whitespace.
+    ///
+    /// With `word-break: break-all`, a word that doesn't fit in the rest of the line is broken
+    /// between characters. With `overflow-wrap: break-word` or `anywhere`, only a word too long
+    /// for a line of its own is, starting on a new line.
+    ///
+    /// Consecutive words on the same line are merged into a single fragment.
+    fn layout_text(&mut self, text: &str, lines: &mut LineBuilder) {
+        let style = self.get_style_node();
+        let font = style.font();
+        let line_height = self.line_height(style);
+        let word_break = style.word_break();
+        let overflow_wrap = style.overflow_wrap();
+
+        let mut words = split_words(text);
+        // Leading whitespace separates this text from the inline content before it.
+        let leading_space = text.chars().next().map_or(false, |c| c.is_whitespace());
+        if leading_space && lines.x > 0.0 && !lines.ends_with_space {
+            words.insert(0, " ".to_string());
+        }
+
+        for word in words.iter() {
+            let width = text_width(&**word, &font, self.metrics);
+            let hanging = width - text_width(word.trim_right(), &font, self.metrics);
+            let overflows = match (word_break, overflow_wrap) {
+                (WordBreak::BreakAll, _) => lines.x + width - hanging > lines.width,
+                (_, OverflowWrap::Normal) => false,
+                _ => width - hanging > lines.width
+            };
+            if overflows && width > hanging {
+                if word_break == WordBreak::Normal {
+                    lines.new_line();
+                }
+                self.layout_broken_word(&**word, &font, line_height, lines);
+                continue;
+            }
+            let (line, x) = lines.place(width, hanging, line_height);
+            lines.ends_with_space = word.ends_with(" ");
+            self.push_text_fragment(&**word, line, x, width, line_height);
+        }
+    }
+
+    /// Place a word on the lines in `lines` one character at a time, wrapping between any two
+    /// characters. Whitespace at the end of the word stays with its last character.
+    fn layout_broken_word(&mut self, word: &str, font: &Font, line_height: f32,
+                          lines: &mut LineBuilder) {
+        let trimmed = word.trim_right();
+        let count = trimmed.chars().count();
+        for (i, c) in trimmed.chars().enumerate() {
+            let mut piece = c.to_string();
+            if i + 1 == count {
+                piece.push_str(&word[trimmed.len() ..]);
+            }
+            let width = text_width(&*piece, font, self.metrics);
+            let hanging = width - text_width(piece.trim_right(), font, self.metrics);
+            let (line, x) = lines.place(width, hanging, line_height);
+            self.push_text_fragment(&*piece, line, x, width, line_height);
+        }
+        lines.ends_with_space = word.ends_with(" ");
+    }
+
+    /// Place `white-space: pre-line` text on the lines in `lines`. Spaces are collapsed and
+    /// lines wrap as in `layout_text`, but each newline also breaks the line.
+    fn layout_pre_line_text(&mut self, text: &str, lines: &mut LineBuilder) {
+        let style = self.get_style_node();
+        let segments: Vec<&str> = text.split('\n').collect();
+        for (i, segment) in segments.iter().enumerate() {
+            if i > 0 {
+                lines.force_break(self.line_height(style));
+            }
+            // Spaces just before a newline are removed. Those just after it are collapsed away
+            // because they start a line.
+            let segment = if i + 1 < segments.len() { segment.trim_right() } else { *segment };
+            self.layout_text(segment, lines);
+        }
+    }
+
+    /// Place preformatted text on the lines in `lines`. Whitespace is kept, and the text breaks
+    /// at newlines. If `wrap` is set (for `pre-wrap`), lines are also wrapped after whitespace
+    /// to fit; otherwise they may overflow.
+    ///
+    /// Tabs advance to the next tab stop, every `tab-size` spaces from the start of the line.
+    /// They are replaced by spaces in the fragment text, so painting doesn't have to expand them
+    /// again. Tab stops are measured in the spaces of this text's font.
+    fn layout_preformatted_text(&mut self, text: &str, lines: &mut LineBuilder, wrap: bool) {
+        let style = self.get_style_node();
+        let font = style.font();
+        let line_height = self.line_height(style);
+        let space_width = text_width(" ", &font, self.metrics);
+
+        for (i, segment) in text.split('\n').enumerate() {
+            if i > 0 {
+                lines.force_break(line_height);
+            }
+            if segment.is_empty() {
+                continue;
+            }
+            let column = if space_width > 0.0 { (lines.x / space_width).round() as usize }
+                         else { 0 };
+            let segment = expand_tabs(segment, column, style.tab_size());
+            let words = if wrap { split_preserved_words(&*segment) } else { vec![segment] };
+            for word in words.iter() {
+                let width = text_width(&**word, &font, self.metrics);
+                let (line, x) = if wrap { lines.place(width, 0.0, line_height) }
+                                else { lines.append(width, 0.0, line_height) };
+                lines.ends_with_space = word.ends_with(" ");
+                self.push_text_fragment(&**word, line, x, width, line_height);
+            }
+        }
+    }
+
+    /// Add `text` to this box's fragment on `line`, or start a new fragment for it at `x` if
+    /// this box doesn't have one on that line yet.
+    fn push_text_fragment(&mut self, text: &str, line: usize, x: f32, width: f32, height: f32) {
+        let continues_fragment = match self.fragments.last() {
+            Some(fragment) => fragment.line == line,
+            None => false
+        };
+        if continues_fragment {
+            let fragment = self.fragments.last_mut().unwrap();
+            fragment.text.push_str(text);
+            fragment.rect.width = fragment.rect.width + width;
+        } else {
+            self.fragments.push(Fragment {
+                rect: Rect { x: x, y: 0.0, width: width, height: height },
+                text: text.to_string(),
+                line: line,
+            });
+        }
+    }
+
+    /// Move the fragments of this box and its descendants to their final position, now that the
+    /// lines have been stacked. `x` is the left edge of the lines, and line `i` has its top at
+    /// `line_tops[i]` and is `line_heights[i]` tall.
+    ///
+    /// Fragments are aligned within their line by `vertical-align`. Boxes that don't set it use
+    /// `parent_align`, the alignment of their parent inline box.
+    ///
+    /// Sets the dimensions of each box to the bounding box of its fragments.
+    fn finish_inline(&mut self, x: f32, line_tops: &[f32], line_heights: &[f32],
+                     parent_align: VerticalAlign) {
+        let style = self.get_style_node();
+        let align = match style.value("vertical-align") {
+            Some(_) => style.vertical_align(),
+            None => parent_align
+        };
+
+        if self.is_atomic_inline() {
+            // This box was laid out at the origin. Move it and its contents into place.
+            let mut rect = self.fragments[0].rect;
+            rect.x = rect.x + x;
+            rect.y = align_in_line(align, line_tops[self.fragments[0].line],
+                                   line_heights[self.fragments[0].line], rect.height);
+            self.translate(rect.x, rect.y);
+            self.fragments[0].rect = rect;
+            return;
+        }
+
+        for child in self.children.iter_mut() {
+            child.finish_inline(x, line_tops, line_heights, align);
+        }
+        match style.text() {
+            Some(_) => {
+                for fragment in self.fragments.iter_mut() {
+                    fragment.rect.x = fragment.rect.x + x;
+                    fragment.rect.y = align_in_line(align, line_tops[fragment.line],
+                                                    line_heights[fragment.line],
+                                                    fragment.rect.height);
+                }
+            }
+            None => {
+                // An inline element covers the fragments of its children on each line.
+                let mut fragments: Vec<Fragment> = Vec::new();
+                for child in self.children.iter() {
+                    for child_fragment in child.fragments.iter() {
+                        let same_line = fragments.iter()
+                                                 .position(|f| f.line == child_fragment.line);
+                        match same_line {
+                            Some(i) =>
+                                fragments[i].rect = fragments[i].rect.union(child_fragment.rect),
+                            None => fragments.push(Fragment {
+                                rect: child_fragment.rect,
+                                text: String::new(),
+                                line: child_fragment.line,
+                            })
+                        }
+                    }
+                }
+                self.fragments = fragments;
+            }
+        }
+        let mut bounds: Option<Rect> = None;
+        for fragment in self.fragments.iter() {
+            bounds = Some(match bounds {
+                Some(rect) => rect.union(fragment.rect),
+                None => fragment.rect
+            });
+        }
+        self.dimensions.content = bounds.unwrap_or(Default::default());
+    }
+
+    /// Move this box and all of its descendants by the given offset.
+    fn translate(&mut self, dx: f32, dy: f32) {
+        self.dimensions.content.x = self.dimensions.content.x + dx;
+        self.dimensions.content.y = self.dimensions.content.y + dy;
+        for fragment in self.fragments.iter_mut() {
+            fragment.rect.x = fragment.rect.x + dx;
+            fragment.rect.y = fragment.rect.y + dy;
+        }
+        for child in self.children.iter_mut() {
+            child.translate(dx, dy);
+        }
+    }
+
     /// Calculate the width of a block-level non-replaced element in normal flow.
     ///
     /// http://www.w3.org/TR/CSS2/visudet.html#blockwidth
@@ -139,19 +2205,48 @@ impl<'a> LayoutBox<'a> {
 
         // `width` has initial value `auto`.
         let auto = Keyword("auto".to_string());
-        let mut width = style.value("width").unwrap_or(auto.clone());
+        let cb_width = containing_block.content.width;
+        let mut width = resolve_percentage(style.value("width").unwrap_or(auto.clone()), cb_width);
+
+        // With an aspect ratio, an auto width follows from a definite height.
+        if width == auto {
+            let height = style.value("height");
+            if let (Some(ratio), Some(Length(h, Px))) = (style.aspect_ratio(), height) {
+                width = Length(h * ratio, Px);
+            }
+        }
+
+        // A replaced element's auto width is its intrinsic width, or follows from a definite
+        // height and its intrinsic aspect ratio.
+        if width == auto {
+            if let Some((w, h)) = self.replaced_intrinsic_size() {
+                width = Length(match style.value("height") {
+                    Some(Length(height, Px)) if h > 0.0 => height * w / h,
+                    _ => w
+                }, Px);
+            }
+        }
+
+        // The intrinsic sizing keywords replace the usual width calculation.
+        if let Some(w) = self.intrinsic_width_keyword(&width) {
+            width = Length(w, Px);
+        }
 
         // margin, border, and padding have initial value 0.
         let zero = Length(0.0, Px);
 
-        let mut margin_left = style.lookup("margin-left", "margin", &zero);
-        let mut margin_right = style.lookup("margin-right", "margin", &zero);
+        // Percentage margins and padding are relative to the width of the containing block.
+        let lookup = |&: name: &str, shorthand: &str| {
+            resolve_percentage(style.lookup(name, shorthand, &zero), cb_width)
+        };
+        let mut margin_left = lookup("margin-left", "margin");
+        let mut margin_right = lookup("margin-right", "margin");
 
         let border_left = style.lookup("border-left-width", "border-width", &zero);
         let border_right = style.lookup("border-right-width", "border-width", &zero);
 
-        let padding_left = style.lookup("padding-left", "padding", &zero);
-        let padding_right = style.lookup("padding-right", "padding", &zero);
+        let padding_left = lookup("padding-left", "padding");
+        let padding_right = lookup("padding-right", "padding");
 
         let total = [&margin_left, &margin_right, &border_left, &border_right,
                      &padding_left, &padding_right, &width].iter().map(|v| v.to_px()).sum();
@@ -171,10 +2266,18 @@ impl<'a> LayoutBox<'a> {
         // and afterward all values should be absolute lengths in px.
         let underflow = containing_block.content.width - total;
 
+        // In a right-to-left containing block, the box is placed against its right edge, so
+        // leftover space goes to the left margin instead of the right one.
+        let rtl = self.containing_direction == Direction::Rtl;
+
         match (width == auto, margin_left == auto, margin_right == auto) {
-            // If the values are overconstrained, calculate margin_right.
+            // If the values are overconstrained, calculate margin_right (margin_left if rtl).
             (false, false, false) => {
-                margin_right = Length(margin_right.to_px() + underflow, Px);
+                if rtl {
+                    margin_left = Length(margin_left.to_px() + underflow, Px);
+                } else {
+                    margin_right = Length(margin_right.to_px() + underflow, Px);
+                }
             }
 
             // If exactly one size is auto, its used value follows from the equality.
@@ -186,9 +2289,17 @@ impl<'a> LayoutBox<'a> {
                 if margin_left == auto { margin_left = Length(0.0, Px); }
                 if margin_right == auto { margin_right = Length(0.0, Px); }
 
-                if underflow >= 0.0 {
+                if underflow >= 0.0 && self.shrinks_to_fit() {
+                    // Shrink to fit the content, without growing past the underflow. The
+                    // margins keep their specified values.
+                    width = Length(self.shrink_to_fit_width(underflow), Px);
+                } else if underflow >= 0.0 {
                     // Expand width to fill the underflow.
                     width = Length(underflow, Px);
+                } else if rtl {
+                    // Width can't be negative. Adjust the left margin instead.
+                    width = Length(0.0, Px);
+                    margin_left = Length(margin_left.to_px() + underflow, Px);
                 } else {
                     // Width can't be negative. Adjust the right margin instead.
                     width = Length(0.0, Px);
@@ -196,7 +2307,8 @@ impl<'a> LayoutBox<'a> {
                 }
             }
 
-            // If margin-left and margin-right are both auto, their used values are equal.
+            // If margin-left and margin-right are both auto, their used values are equal. (If the
+            // box overflows, both were set to 0 above and the overconstrained arm applies.)
             (false, true, true) => {
                 margin_left = Length(underflow / 2.0, Px);
                 margin_right = Length(underflow / 2.0, Px);
@@ -229,62 +2341,476 @@ impl<'a> LayoutBox<'a> {
         let zero = Length(0.0, Px);
 
         // If margin-top or margin-bottom is `auto`, the used value is zero.
-        d.margin.top = style.lookup("margin-top", "margin", &zero).to_px();
-        d.margin.bottom = style.lookup("margin-bottom", "margin", &zero).to_px();
+        // Percentages are relative to the containing block's width, even on the vertical sides.
+        let cb_width = containing_block.content.width;
+        let lookup = |&: name: &str, shorthand: &str| {
+            resolve_percentage(style.lookup(name, shorthand, &zero), cb_width).to_px()
+        };
+        d.margin.top = lookup("margin-top", "margin");
+        d.margin.bottom = lookup("margin-bottom", "margin");
 
         d.border.top = style.lookup("border-top-width", "border-width", &zero).to_px();
         d.border.bottom = style.lookup("border-bottom-width", "border-width", &zero).to_px();
 
-        d.padding.top = style.lookup("padding-top", "padding", &zero).to_px();
-        d.padding.bottom = style.lookup("padding-bottom", "padding", &zero).to_px();
-
-        d.content.x = containing_block.content.x +
-                      d.margin.left + d.border.left + d.padding.left;
+        d.padding.top = lookup("padding-top", "padding");
+        d.padding.bottom = lookup("padding-bottom", "padding");
 
         // Position the box below all the previous boxes in the container.
-        d.content.y = containing_block.content.height + containing_block.content.y +
-                      d.margin.top + d.border.top + d.padding.top;
+        let cb = containing_block.content;
+        let content = d.content_within(Rect { y: cb.y + cb.height, ..cb });
+        d.content.x = content.x;
+        d.content.y = content.y;
     }
 
     /// Lay out the block's children within its content area.
     ///
-    /// Sets `self.dimensions.height` to the total content height.
-    fn layout_block_children(&mut self) {
+    /// Sets `self.dimensions.height` to the total content height. Floats don't count towards the
+    /// height, unless this box `contains_floats`.
+    ///
+    /// If the first child holds inline content, its first line is indented by `text-indent`.
+    fn layout_block_children(&mut self, viewport: Dimensions) {
+        if self.skips_contents() {
+            // The children keep their empty dimensions, and take up no space.
+            self.dimensions.content.height = self.contained_size().1;
+            return;
+        }
+        let contains_floats = self.contains_floats();
+        let text_indent = self.get_style_node().value("text-indent");
         let d = &mut self.dimensions;
-        for child in self.children.iter_mut() {
-            child.layout(*d);
+        // The box may have been laid out before, e.g. as a stretched flex item.
+        d.content.height = 0.0;
+
+        // Floats that start at the same height are placed side by side.
+        let mut float_row: Option<f32> = None;
+        let mut float_left = 0.0;
+        let mut float_right = 0.0;
+        let mut float_bottom = d.content.y;
+
+        for (i, child) in self.children.iter_mut().enumerate() {
+            let is_anonymous = match child.box_type { AnonymousBlock => true, _ => false };
+            if let (0, true, Some(indent)) = (i, is_anonymous, text_indent.clone()) {
+                // Percentages are relative to the width of this block.
+                let indent = resolve_percentage(indent, d.content.width).to_px();
+                child.layout_anonymous_block(*d, indent, viewport);
+                d.content.height = d.content.height + child.dimensions.margin_box().height;
+                continue;
+            }
+            if child.is_fixed() {
+                // Fixed boxes are out of flow, and don't affect the height of their parent.
+                child.layout_fixed(*d, viewport);
+                continue;
+            }
+            if child.is_absolute() {
+                // Absolute boxes are out of flow too. Remember where the box would have gone, for
+                // `layout_absolute_descendants`.
+                child.dimensions = Default::default();
+                child.dimensions.content = Rect {
+                    x: d.content.x,
+                    y: d.content.y + d.content.height,
+                    width: d.content.width,
+                    height: 0.0,
+                };
+                continue;
+            }
+            if child.is_float() {
+                if float_row != Some(d.content.height) {
+                    float_row = Some(d.content.height);
+                    float_left = 0.0;
+                    float_right = 0.0;
+                }
+                child.layout_float(*d, float_left, float_right, viewport);
+                let margin_box = child.dimensions.margin_box();
+                if child.get_style_node().float() == CssFloat::Right {
+                    float_right = float_right + margin_box.width;
+                } else {
+                    float_left = float_left + margin_box.width;
+                }
+                float_bottom = float_bottom.max(margin_box.y + margin_box.height);
+                continue;
+            }
+            child.layout_cached(*d, viewport);
             // Increment the height so each child is laid out below the previous one.
             d.content.height = d.content.height + child.dimensions.margin_box().height;
         }
+
+        if contains_floats {
+            d.content.height = d.content.height.max(float_bottom - d.content.y);
+        }
     }
 
     /// Height of a block-level non-replaced element in normal flow with overflow visible.
     fn calculate_block_height(&mut self) {
+        let style = self.get_style_node();
         // If the height is set to an explicit length, use that exact length.
-        // Otherwise, just keep the value set by `layout_block_children`.
-        if let Some(Length(h, Px)) = self.get_style_node().value("height") {
+        if let Some(Length(h, Px)) = style.value("height") {
             self.dimensions.content.height = h;
+            return;
+        }
+        // An auto height follows from a definite width and an aspect ratio.
+        if let (Some(ratio), Some(Length(..))) = (style.aspect_ratio(), style.value("width")) {
+            self.dimensions.content.height = self.dimensions.content.width / ratio;
+            return;
+        }
+        // A replaced element keeps its intrinsic aspect ratio at its used width.
+        if let Some((w, h)) = self.replaced_intrinsic_size() {
+            let d = &mut self.dimensions;
+            d.content.height = if w > 0.0 { d.content.width * h / w } else { h };
+            return;
+        }
+        // With size containment, the children don't affect the height. Flex, grid, and table
+        // containers also end up here after laying out their contents.
+        if self.has_size_containment() {
+            self.dimensions.content.height = self.contained_size().1;
+            return;
         }
+        // Otherwise, just keep the value set by `layout_block_children`.
     }
 
     /// Where a new inline child should go.
     fn get_inline_container(&mut self) -> &mut LayoutBox<'a> {
-        match self.box_type {
-            InlineNode(_) | AnonymousBlock => self,
-            BlockNode(_) => {
-                // If we've just generated an anonymous block box, keep using it.
-                // Otherwise, create a new one.
-                match self.children.last() {
-                    Some(&LayoutBox { box_type: AnonymousBlock,..}) => {}
-                    _ => self.children.push(LayoutBox::new(AnonymousBlock))
-                }
-                self.children.last_mut().unwrap()
+        // An inline-block contains its inline children in an anonymous block, like a block does.
+        let is_block_container = match self.box_type {
+            InlineNode(_) => self.is_atomic_inline(),
+            BlockNode(_) => true,
+            AnonymousBlock => false
+        };
+        if !is_block_container {
+            return self;
+        }
+        // If we've just generated an anonymous block box, keep using it.
+        // Otherwise, create a new one.
+        let metrics = self.metrics;
+        match self.children.last() {
+            Some(&LayoutBox { box_type: AnonymousBlock,..}) => {}
+            _ => self.children.push(LayoutBox::new(AnonymousBlock, metrics))
+        }
+        self.children.last_mut().unwrap()
+    }
+}
+
+/// Keeps track of line boxes while inline content is being placed.
+struct LineBuilder {
+    /// Width available for each line.
+    width: f32,
+    /// Where the next fragment goes, relative to the start of the current line.
+    x: f32,
+    /// Height of each line so far. The last one is the current line.
+    heights: Vec<f32>,
+    /// Width of the content on each line, not counting collapsible whitespace at the end.
+    widths: Vec<f32>,
+    /// Did the last fragment end with whitespace? If so, more whitespace collapses into it.
+    ends_with_space: bool,
+}
+
+impl LineBuilder {
+    fn new(width: f32) -> LineBuilder {
+        LineBuilder {
+            width: width,
+            x: 0.0,
+            heights: vec![0.0],
+            widths: vec![0.0],
+            ends_with_space: false,
+        }
+    }
+
+    /// Reserve space for a fragment, starting a new line if it doesn't fit on this one.
+    ///
+    /// `hanging` is the width of the collapsible whitespace at the end of the fragment, which
+    /// doesn't have to fit because it is removed if the line ends there.
+    ///
+    /// Returns the line index and x offset of the fragment.
+    fn place(&mut self, width: f32, hanging: f32, height: f32) -> (usize, f32) {
+        if self.x + width - hanging > self.width {
+            self.new_line();
+        }
+        self.append(width, hanging, height)
+    }
+
+    /// Start a new line, unless the current one is still empty.
+    fn new_line(&mut self) {
+        if self.x > 0.0 {
+            self.heights.push(0.0);
+            self.widths.push(0.0);
+            self.x = 0.0;
+        }
+    }
+
+    /// Reserve space for a fragment on the current line, even if it doesn't fit.
+    ///
+    /// Returns the line index and x offset of the fragment.
+    fn append(&mut self, width: f32, hanging: f32, height: f32) -> (usize, f32) {
+        let line = self.heights.len() - 1;
+        let x = self.x;
+        self.x = self.x + width;
+        self.heights[line] = self.heights[line].max(height);
+        self.widths[line] = self.x - hanging;
+        return (line, x);
+    }
+
+    /// End the current line, which is at least `height` tall even if it's empty.
+    fn force_break(&mut self, height: f32) {
+        let line = self.heights.len() - 1;
+        self.heights[line] = self.heights[line].max(height);
+        self.heights.push(0.0);
+        self.widths.push(0.0);
+        self.x = 0.0;
+        self.ends_with_space = false;
+    }
+}
+
+/// Resolve a percentage length against `base`, including the arguments of `min()` and `max()`.
+/// Other values are returned unchanged.
+fn resolve_percentage(value: Value, base: f32) -> Value {
+    match value {
+        Length(percent, Percent) => Length(percent / 100.0 * base, Px),
+        Value::Function(name, args) => {
+            let args = args.into_iter().map(|arg| resolve_percentage(arg, base)).collect();
+            Value::Function(name, args)
+        }
+        _ => value
+    }
+}
+
+/// The y position of a fragment `height` tall, aligned within a line by `vertical-align`.
+///
+/// `baseline` alignment is approximated by aligning the bottom of the fragment with the bottom
+/// of the line.
+fn align_in_line(align: VerticalAlign, line_top: f32, line_height: f32, height: f32) -> f32 {
+    match align {
+        VerticalAlign::Top => line_top,
+        VerticalAlign::Middle => line_top + (line_height - height) / 2.0,
+        VerticalAlign::Bottom | VerticalAlign::Baseline => line_top + line_height - height,
+    }
+}
+
+/// The space before the first flex item in a line, and the extra space between items, for
+/// distributing `free_space` px among `n` items by `justify-content`.
+fn justify_offsets(justify: JustifyContent, free_space: f32, n: f32) -> (f32, f32) {
+    match justify {
+        JustifyContent::FlexStart => (0.0, 0.0),
+        JustifyContent::FlexEnd => (free_space, 0.0),
+        JustifyContent::Center => (free_space / 2.0, 0.0),
+        JustifyContent::SpaceBetween if n > 1.0 => (0.0, free_space / (n - 1.0)),
+        JustifyContent::SpaceBetween => (0.0, 0.0),
+        JustifyContent::SpaceAround => (free_space / n / 2.0, free_space / n),
+        JustifyContent::SpaceEvenly => (free_space / (n + 1.0), free_space / (n + 1.0)),
+    }
+}
+
+/// How far to move a flex item along the cross axis to align it within `space` px of free
+/// space by `align-items`. Stretched items aren't moved.
+fn align_offset(align: AlignItems, space: f32) -> f32 {
+    match align {
+        AlignItems::FlexStart | AlignItems::Stretch => 0.0,
+        AlignItems::FlexEnd => space,
+        AlignItems::Center => space / 2.0,
+    }
+}
+
+/// The widths of grid tracks in a container `width` px wide, with `gap` px between them.
+///
+/// http://www.w3.org/TR/css-grid-1/#fr-unit
+///
+/// Fixed tracks get their own size, and `fr` tracks share out whatever is left. If the `fr`
+/// values add up to less than 1, they only take that fraction of the free space.
+fn grid_track_widths(tracks: &[TrackSize], width: f32, gap: f32) -> Vec<f32> {
+    let gaps = (tracks.len() as f32 - 1.0).max(0.0);
+    let mut free_space = width - gap * gaps;
+    let mut total_fr: f32 = 0.0;
+    for track in tracks.iter() {
+        match *track {
+            TrackSize::Fixed(size) => free_space = free_space - size,
+            TrackSize::Fraction(fr) => total_fr = total_fr + fr,
+        }
+    }
+    let fr_size = free_space.max(0.0) / total_fr.max(1.0);
+    tracks.iter().map(|track| match *track {
+        TrackSize::Fixed(size) => size,
+        TrackSize::Fraction(fr) => fr * fr_size,
+    }).collect()
+}
+
+/// Measurements of the glyphs in a font, for placing text in inline layout.
+///
+/// Embedders can lay out text in real fonts by implementing this and calling
+/// `layout_tree_with_metrics`.
+pub trait FontMetrics {
+    /// How far to move along the line after `glyph`, in px, for text in `font`.
+    fn advance(&self, glyph: char, font: &Font) -> f32;
+
+    /// The height of a line of text `size` px tall, for `line-height: normal`.
+    fn line_height(&self, size: f32) -> f32;
+}
+
+/// How a box is painted within its stacking context. See `LayoutBox::paint_order`.
+#[derive(PartialEq)]
+enum LayerType {
+    /// Painted in normal flow, with its parent.
+    InFlow,
+    /// A positioned box with `z-index: auto`. It is painted as a layer at `z-index: 0`, but the
+    /// layers inside it belong to the enclosing stacking context.
+    Auto,
+    /// A positioned box with an integer `z-index`, or a transformed box. It is painted as a
+    /// layer that forms a stacking context of its own.
+    StackingContext(i32),
+}
+
+/// A phase of laying out a block box. See `LayoutRecorder`.
+#[derive(PartialEq, Show)]
+pub enum LayoutPhase {
+    /// Calculating the box's width and position.
+    Width,
+    /// Laying out the box's children.
+    Content,
+    /// Calculating the box's height from its children.
+    Height,
+}
+
+impl Copy for LayoutPhase {}
+
+/// Receives timings of block layout, for profiling.
+///
+/// Embedders can find slow subtrees by implementing this and calling
+/// `layout_tree_with_recorder`. Without a recorder, nothing is timed.
+pub trait LayoutRecorder {
+    /// Called after `phase` of laying out `layout_box` took `duration`. `boxes` is the number of
+    /// boxes in its subtree, including itself.
+    fn record(&self, phase: LayoutPhase, layout_box: &LayoutBox, duration: Duration,
+              boxes: usize);
+}
+
+/// The built-in monospace font. Italic glyphs are slanted but have the same advance as upright
+/// ones.
+pub struct BuiltinFont;
+
+/// The metrics `layout_tree` uses.
+pub static BUILTIN_FONT: BuiltinFont = BuiltinFont;
+
+/// Identifies a `FontMetrics` object, so that blocks measured with one aren't reused with another.
+///
+/// A trait object is a pointer to the data and a pointer to the vtable. Both are compared, since
+/// different zero-sized implementations may share an address.
+fn metrics_id(metrics: &FontMetrics) -> (usize, usize) {
+    unsafe { mem::transmute(metrics) }
+}
+
+/// Width of each glyph of the built-in monospace font, as a fraction of the font size.
+const GLYPH_ADVANCE: f32 = 0.6;
+
+/// Width of each glyph of the bold variant of the built-in font, as a fraction of the font size.
+const BOLD_GLYPH_ADVANCE: f32 = 0.65;
+
+impl FontMetrics for BuiltinFont {
+    fn advance(&self, _glyph: char, font: &Font) -> f32 {
+        font.size * if font.is_bold() { BOLD_GLYPH_ADVANCE } else { GLYPH_ADVANCE }
+    }
+
+    fn line_height(&self, size: f32) -> f32 {
+        size * NORMAL_LINE_HEIGHT
+    }
+}
+
+/// The width of a run of text in `font`, measured by `metrics`.
+///
+/// `letter-spacing` is added after every character, and `word-spacing` after every space, so
+/// the width of a run is the sum of the widths of its parts. This includes the spacing after the
+/// last character, which browsers also leave in place at the end of a line.
+fn text_width(text: &str, font: &Font, metrics: &FontMetrics) -> f32 {
+    text.chars().map(|c| {
+        let spacing = if c == ' ' { font.letter_spacing + font.word_spacing }
+                      else { font.letter_spacing };
+        metrics.advance(c, font) + spacing
+    }).sum()
+}
+
+/// Replace each tab in `text` with spaces up to the next tab stop. Tab stops are every
+/// `tab_size` columns, and the text starts at column `start_column`.
+fn expand_tabs(text: &str, start_column: usize, tab_size: usize) -> String {
+    let mut result = String::new();
+    let mut column = start_column;
+    for c in text.chars() {
+        if c == '\t' && tab_size > 0 {
+            let spaces = tab_size - column % tab_size;
+            for _ in (0 .. spaces) {
+                result.push(' ');
+            }
+            column = column + spaces;
+        } else if c != '\t' {
+            result.push(c);
+            column = column + 1;
+        }
+    }
+    result
+}
+
+/// Split text at whitespace into words, each followed by a single collapsed space if there was
+/// whitespace after it.
+fn split_words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for c in text.chars() {
+        if c.is_whitespace() {
+            if !word.is_empty() && !word.ends_with(" ") {
+                word.push(' ');
             }
+        } else {
+            if word.ends_with(" ") {
+                words.push(word);
+                word = String::new();
+            }
+            word.push(c);
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    return words;
+}
+
+/// Split text into words for `white-space: pre-wrap`. Unlike `split_words`, whitespace is kept
+/// as it is: each word is followed by all of the whitespace after it, and any whitespace at the
+/// start is a word of its own.
+fn split_preserved_words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for c in text.chars() {
+        if !c.is_whitespace() && word.ends_with(" ") {
+            words.push(word);
+            word = String::new();
         }
+        word.push(c);
     }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    return words;
 }
 
 impl Rect {
+    /// Is the point (`x`, `y`) inside this rectangle?
+    pub fn contains(self, x: f32, y: f32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    /// Do `self` and `other` overlap? Rectangles that only touch along an edge don't.
+    pub fn intersects(self, other: Rect) -> bool {
+        self.x < other.x + other.width && other.x < self.x + self.width &&
+        self.y < other.y + other.height && other.y < self.y + self.height
+    }
+
+    /// The smallest rectangle containing both `self` and `other`.
+    pub fn union(self, other: Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect {
+            x: x,
+            y: y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+
+    /// This rectangle grown outward by `edge` on each side.
     pub fn expanded_by(self, edge: EdgeSizes) -> Rect {
         Rect {
             x: self.x - edge.left,
@@ -293,6 +2819,18 @@ impl Rect {
             height: self.height + edge.top + edge.bottom,
         }
     }
+
+    /// This rectangle shrunk inward by `edge` on each side. The inverse of `expanded_by`.
+    ///
+    /// The result may have a negative width or height if the edges are bigger than the rectangle.
+    pub fn contracted_by(self, edge: EdgeSizes) -> Rect {
+        Rect {
+            x: self.x + edge.left,
+            y: self.y + edge.top,
+            width: self.width - edge.left - edge.right,
+            height: self.height - edge.top - edge.bottom,
+        }
+    }
 }
 
 impl Dimensions {
@@ -308,4 +2846,710 @@ impl Dimensions {
     pub fn margin_box(self) -> Rect {
         self.border_box().expanded_by(self.margin)
     }
+    /// The content area of a box with these edges whose margin box is `margin_box`. The inverse
+    /// of `margin_box`.
+    pub fn content_within(self, margin_box: Rect) -> Rect {
+        margin_box.contracted_by(self.margin).contracted_by(self.border).contracted_by(self.padding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::expand_tabs;
+    use std::default::Default;
+    use css::{self, Stylesheet};
+    use dom::Node;
+    use html;
+    use images::ImageCache;
+    use json;
+    use std::cell::RefCell;
+    use std::time::Duration;
+    use style::{style_tree, StyledNode, Font};
+
+    /// Style a tree with the default 16px root font size and 96px per inch, for an 800x600
+    /// viewport.
+    fn style<'a>(root: &'a Node, stylesheet: &'a Stylesheet) -> StyledNode<'a> {
+        style_tree(root, stylesheet, 16.0, (800.0, 600.0), 96.0)
+    }
+
+    fn viewport() -> Dimensions {
+        let mut viewport: Dimensions = Default::default();
+        viewport.content.width = 800.0;
+        viewport.content.height = 600.0;
+        viewport
+    }
+
+    #[test]
+    fn auto_horizontal_margins_center_a_block() {
+        let root = html::parse("<div></div>".to_string());
+        let stylesheet = css::parse("div { display: block; width: 200px; margin: 0 auto; }"
+                                    .to_string());
+        let styled = style(&root, &stylesheet);
+        let layout = layout_tree(&styled, viewport());
+
+        assert_eq!(layout.dimensions.margin.left, 300.0);
+        assert_eq!(layout.dimensions.margin.right, 300.0);
+        assert_eq!(layout.dimensions.content.x, 300.0);
+        assert_eq!(layout.dimensions.margin.top, 0.0);
+    }
+
+    #[test]
+    fn br_breaks_the_line() {
+        let root = html::parse("<div>a<br>b</div>".to_string());
+        let stylesheet = css::parse("div { display: block; }".to_string());
+        let styled = style(&root, &stylesheet);
+        let layout = layout_tree(&styled, viewport());
+
+        // The text and the `<br>` share one anonymous block.
+        let lines = &layout.children[0];
+        let a = &lines.children[0].fragments[0];
+        let b = &lines.children[2].fragments[0];
+        assert_eq!(&*a.text, "a");
+        assert_eq!(&*b.text, "b");
+        assert_eq!((a.line, a.rect.x, a.rect.y), (0, 0.0, 0.0));
+        assert_eq!((b.line, b.rect.x, b.rect.y), (1, 0.0, 19.2));
+        assert_eq!(layout.dimensions.content.height, 38.4);
+    }
+
+    #[test]
+    fn text_wraps_at_the_container_width() {
+        let root = html::parse("<div>aaaa bbbb</div>".to_string());
+        // Each glyph is 0.6 * 10px wide, so only one word fits in 40px.
+        let stylesheet = css::parse("div { display: block; width: 40px; font-size: 10px; }"
+                                    .to_string());
+        let styled = style(&root, &stylesheet);
+        let layout = layout_tree(&styled, viewport());
+
+        let text = &layout.children[0].children[0];
+        assert_eq!(text.fragments.len(), 2);
+        assert_eq!(text.fragments[1].line, 1);
+        assert_eq!(text.fragments[1].rect.y, 12.0);
+    }
+
+    #[test]
+    fn spaces_at_the_ends_of_lines_are_trimmed() {
+        let root = html::parse("<div>aaaa bbbb</div>".to_string());
+        let stylesheet = css::parse("div { display: block; width: 40px; font-size: 10px; }"
+                                    .to_string());
+        let styled = style(&root, &stylesheet);
+        let layout = layout_tree(&styled, viewport());
+
+        // The space after "aaaa" would fit, but it ends the line so it takes up no room.
+        let first = &layout.children[0].children[0].fragments[0];
+        assert_eq!(&*first.text, "aaaa");
+        assert_eq!(first.rect.width, 24.0);
+    }
+
+    static TABLE_CSS: &'static str = "table { display: table; font-size: 10px; } \
+                                      tr { display: table-row; } td { display: table-cell; }";
+
+    #[test]
+    fn table_cells_form_aligned_columns() {
+        let root = html::parse("<table><tr><td>a</td><td>bbbb</td></tr>\
+                                <tr><td>cc</td><td>d</td></tr></table>".to_string());
+        let stylesheet = css::parse(format!("{} table {{ width: 100px; }}", TABLE_CSS));
+        let styled = style(&root, &stylesheet);
+        let table = layout_tree(&styled, viewport());
+
+        // The widest cells are 12px and 24px, and the other 64px is shared between the columns.
+        let cells: Vec<Rect> = table.children.iter()
+            .flat_map(|row| row.children.iter().map(|cell| cell.dimensions.content))
+            .collect();
+        assert_eq!(cells.len(), 4);
+        assert_eq!((cells[0].x, cells[0].y, cells[0].width), (0.0, 0.0, 44.0));
+        assert_eq!((cells[1].x, cells[1].y, cells[1].width), (44.0, 0.0, 56.0));
+        assert_eq!((cells[2].x, cells[2].y, cells[2].width), (0.0, 12.0, 44.0));
+        assert_eq!((cells[3].x, cells[3].y, cells[3].width), (44.0, 12.0, 56.0));
+        assert_eq!(table.dimensions.content.height, 24.0);
+    }
+
+    #[test]
+    fn table_column_fits_its_widest_cell() {
+        let root = html::parse("<table><tr><td>a</td><td>b</td></tr>\
+                                <tr><td id=\"wide\">c</td><td>d</td></tr></table>".to_string());
+        let stylesheet = css::parse(format!("{} table {{ width: 0px; }} \
+                                            #wide {{ width: 50px; padding: 5px; }}", TABLE_CSS));
+        let styled = style(&root, &stylesheet);
+        let table = layout_tree(&styled, viewport());
+
+        let first_row = &table.children[0];
+        assert_eq!(first_row.children[0].dimensions.content.width, 60.0);
+        assert_eq!(first_row.children[1].dimensions.content.x, 60.0);
+        assert_eq!(first_row.children[1].dimensions.content.width, 6.0);
+    }
+
+    #[test]
+    fn collapsed_table_rows_take_no_space() {
+        let root = html::parse("<table><tr><td>a</td></tr><tr class=\"gone\"><td>b</td></tr>\
+                                <tr><td>c</td></tr></table>".to_string());
+        let stylesheet = css::parse(format!("{} .gone {{ visibility: collapse; }}", TABLE_CSS));
+        let styled = style(&root, &stylesheet);
+        let table = layout_tree(&styled, viewport());
+
+        let rows: Vec<Rect> = table.children.iter().map(|row| row.dimensions.content).collect();
+        assert_eq!((rows[0].y, rows[0].height), (0.0, 12.0));
+        assert_eq!((rows[1].y, rows[1].height), (12.0, 0.0));
+        assert_eq!((rows[2].y, rows[2].height), (12.0, 12.0));
+        assert_eq!(table.dimensions.content.height, 24.0);
+        // The cells of the collapsed row aren't laid out.
+        assert!(table.children[1].children[0].dimensions.content.width == 0.0);
+    }
+
+    #[test]
+    fn stretched_flex_item_lays_out_its_contents_at_the_new_height() {
+        let root = html::parse("<div id=\"outer\"><div id=\"inner\"><p></p></div>\
+                                <div id=\"tall\"></div></div>".to_string());
+        let stylesheet = css::parse("div { display: flex; } p { display: block; height: 10px; \
+                                     width: 10px; } #inner { align-items: flex-end; } \
+                                     #tall { display: block; width: 10px; height: 100px; }"
+                                    .to_string());
+        let styled = style(&root, &stylesheet);
+        let outer = layout_tree(&styled, viewport());
+
+        // `#inner` is stretched to the height of `#tall`, and its item is aligned to its new
+        // bottom edge.
+        let inner = &outer.children[0];
+        assert_eq!(inner.dimensions.content.height, 100.0);
+        assert_eq!(inner.children[0].dimensions.content.y, 90.0);
+        assert_eq!(outer.dimensions.content.height, 100.0);
+    }
+
+    /// The content width of the first child of the root, laid out from `html` and `css` with
+    /// a 10px font, so each glyph is 6px wide.
+    fn child_width(html: &str, css: &str) -> f32 {
+        let root = html::parse(html.to_string());
+        let stylesheet = css::parse(format!("div, p {{ display: block; font-size: 10px; }} {}",
+                                            css));
+        let styled = style(&root, &stylesheet);
+        let layout = layout_tree(&styled, viewport());
+        layout.children[0].dimensions.content.width
+    }
+
+    #[test]
+    fn intrinsic_width_keywords() {
+        let html = "<div><p>aa bbb</p></div>";
+        assert_eq!(child_width(html, "p { width: max-content; }"), 36.0);
+        assert_eq!(child_width(html, "p { width: min-content; }"), 18.0);
+        // The keywords size the content box, inside the padding.
+        assert_eq!(child_width(html, "p { width: max-content; padding: 5px; }"), 36.0);
+    }
+
+    #[test]
+    fn auto_width_floats_shrink_to_fit() {
+        let html = "<div><p>aa bbb</p></div>";
+        assert_eq!(child_width(html, "p { float: left; }"), 36.0);
+        // Narrower than the max-content width, but no narrower than the min-content width.
+        assert_eq!(child_width(html, "div { width: 24px; } p { float: left; }"), 24.0);
+        assert_eq!(child_width(html, "div { width: 10px; } p { float: right; }"), 18.0);
+        // The margins don't count towards the available width.
+        assert_eq!(child_width(html, "div { width: 30px; } p { float: left; margin: 0 5px; }"),
+                   20.0);
+    }
+
+    #[test]
+    fn auto_width_absolute_boxes_shrink_to_fit() {
+        let html = "<div><p>aa bbb</p></div>";
+        assert_eq!(child_width(html, "p { position: absolute; left: 10px; }"), 36.0);
+        // Unless both `left` and `right` are set, which fixes the width.
+        assert_eq!(child_width(html, "div { width: 100px; position: relative; } \
+                                      p { position: absolute; left: 10px; right: 10px; }"),
+                   80.0);
+    }
+
+    #[test]
+    fn auto_width_inline_blocks_shrink_to_fit() {
+        let root = html::parse("<div><span>aa bbb</span></div>".to_string());
+        let stylesheet = css::parse("div { display: block; font-size: 10px; } \
+                                     span { display: inline-block; }".to_string());
+        let styled = style(&root, &stylesheet);
+        let layout = layout_tree(&styled, viewport());
+
+        let inline_block = &layout.children[0].children[0];
+        assert_eq!(inline_block.dimensions.content.width, 36.0);
+    }
+
+    /// The content boxes of the children of a grid container with the given style, which has
+    /// three 10px tall items.
+    fn grid_cells(grid_css: &str) -> Vec<(f32, f32, f32)> {
+        let root = html::parse("<div><p></p><p></p><p></p></div>".to_string());
+        let stylesheet = css::parse(format!("div {{ display: grid; width: 400px; {} }} \
+                                             p {{ display: block; height: 10px; }}", grid_css));
+        let styled = style(&root, &stylesheet);
+        let grid = layout_tree(&styled, viewport());
+        grid.children.iter()
+            .map(|item| (item.dimensions.content.x, item.dimensions.content.y,
+                         item.dimensions.content.width))
+            .collect()
+    }
+
+    #[test]
+    fn grid_items_fill_fixed_columns_row_by_row() {
+        assert_eq!(grid_cells("grid-template-columns: 100px 200px; gap: 5px 10px;"), vec![
+            (0.0, 0.0, 100.0),
+            (110.0, 0.0, 200.0),
+            (0.0, 15.0, 100.0),
+        ]);
+        // Without a template, each item is in its own row.
+        assert_eq!(grid_cells("").iter().map(|&(_, y, width)| (y, width)).collect::<Vec<_>>(),
+                   vec![(0.0, 400.0), (10.0, 400.0), (20.0, 400.0)]);
+    }
+
+    #[test]
+    fn fr_tracks_share_the_space_left_by_fixed_tracks() {
+        assert_eq!(grid_cells("grid-template-columns: 100px 1fr 2fr;"), vec![
+            (0.0, 0.0, 100.0),
+            (100.0, 0.0, 100.0),
+            (200.0, 0.0, 200.0),
+        ]);
+        // The gaps are taken out first.
+        assert_eq!(grid_cells("grid-template-columns: 100px 1fr 2fr; column-gap: 20px;"), vec![
+            (0.0, 0.0, 100.0),
+            (120.0, 0.0, 80.0),
+            (220.0, 0.0, 160.0),
+        ]);
+    }
+
+    /// The content width of the box hit at (5, 5), where a 100px wide paragraph is overlapped
+    /// by a 50px wide one.
+    fn hit_width(extra_css: &str) -> Option<f32> {
+        let root = html::parse("<div><p class=\"a\"></p><p class=\"b\"></p></div>".to_string());
+        let stylesheet = css::parse(format!("div, p {{ display: block; }} \
+                                             p {{ height: 10px; }} .a {{ width: 100px; }} \
+                                             .b {{ width: 50px; margin-top: -10px; }} {}",
+                                            extra_css));
+        let styled = style(&root, &stylesheet);
+        let layout = layout_tree(&styled, viewport());
+        layout.hit_test(5.0, 5.0).map(|hit| hit.dimensions.content.width)
+    }
+
+    #[test]
+    fn hit_test_finds_the_frontmost_box() {
+        assert_eq!(hit_width(""), Some(50.0));
+        assert_eq!(hit_width(".b { pointer-events: none; }"), Some(100.0));
+        // The container is hit if neither paragraph can be.
+        assert_eq!(hit_width("p { pointer-events: none; }"), Some(800.0));
+        assert_eq!(hit_width("div { pointer-events: none; }"), None);
+        // A higher `z-index` is in front of later boxes.
+        assert_eq!(hit_width(".a { position: relative; z-index: 1; }"), Some(100.0));
+    }
+
+    #[test]
+    fn paint_order_follows_the_stacking_context() {
+        let root = html::parse("<div id=\"root\"><p id=\"block\"></p><p id=\"float\"></p>\
+                                <p id=\"neg\"></p><p id=\"auto\"><p id=\"inner\"></p></p>\
+                                <p id=\"top\"></p></div>".to_string());
+        let stylesheet = css::parse("div, p { display: block; height: 10px; } \
+                                     #float { float: left; width: 10px; } \
+                                     #neg { position: relative; z-index: -1; } \
+                                     #auto { position: relative; } \
+                                     #inner { position: relative; z-index: 2; } \
+                                     #top { position: relative; z-index: 1; }".to_string());
+        let styled = style(&root, &stylesheet);
+        let layout = layout_tree(&styled, viewport());
+        let ids: Vec<&str> = layout.paint_order().iter().map(|b| {
+            &**b.get_style_node().element().and_then(|e| e.id()).unwrap()
+        }).collect();
+        // Negative layers, then blocks, then floats, then the other layers by `z-index`. The
+        // layer inside the `z-index: auto` box is ordered with the root's own layers.
+        assert_eq!(ids, vec!["root", "neg", "block", "float", "auto", "top", "inner"]);
+    }
+
+    #[test]
+    fn boxes_in_rect_are_listed_back_to_front() {
+        let root = html::parse("<div><p class=\"a\"></p><p class=\"b\"></p></div>".to_string());
+        let stylesheet = css::parse("div, p { display: block; height: 10px; } \
+                                     .a { width: 100px; } .b { width: 50px; }".to_string());
+        let styled = style(&root, &stylesheet);
+        let layout = layout_tree(&styled, viewport());
+        let widths = |&: rect: Rect| -> Vec<f32> {
+            layout.boxes_in_rect(rect).iter().map(|b| b.dimensions.content.width).collect()
+        };
+        assert_eq!(widths(Rect { x: 0.0, y: 0.0, width: 10.0, height: 10.0 }), vec![800.0, 100.0]);
+        assert_eq!(widths(Rect { x: 0.0, y: 5.0, width: 10.0, height: 10.0 }),
+                   vec![800.0, 100.0, 50.0]);
+        // Touching an edge isn't overlapping.
+        assert_eq!(widths(Rect { x: 100.0, y: 0.0, width: 10.0, height: 5.0 }), vec![800.0]);
+    }
+
+    /// The position of each of the three 50x10px items `.a`, `.b`, and `.c` of a 300px wide flex
+    /// container, in document order.
+    fn flex_positions(extra_css: &str) -> Vec<(f32, f32)> {
+        let root = html::parse("<div><p class=\"a\"></p><p class=\"b\"></p>\
+                                <p class=\"c\"></p></div>".to_string());
+        let stylesheet = css::parse(format!("div {{ display: flex; width: 300px; }} \
+                                             p {{ display: block; width: 50px; height: 10px; }} \
+                                             {}", extra_css));
+        let styled = style(&root, &stylesheet);
+        let flex = layout_tree(&styled, viewport());
+        flex.children.iter()
+            .map(|item| (item.dimensions.content.x, item.dimensions.content.y))
+            .collect()
+    }
+
+    #[test]
+    fn flex_items_are_placed_in_order() {
+        assert_eq!(flex_positions(".a { order: 2; } .c { order: -1; }"),
+                   vec![(100.0, 0.0), (50.0, 0.0), (0.0, 0.0)]);
+        // Items with the same order stay in document order.
+        assert_eq!(flex_positions(".a { order: 1; }"),
+                   vec![(100.0, 0.0), (0.0, 0.0), (50.0, 0.0)]);
+    }
+
+    #[test]
+    fn flex_wrap_breaks_items_into_lines() {
+        let css = "p { width: 120px; } div { flex-wrap: wrap; gap: 5px 10px; }";
+        assert_eq!(flex_positions(css), vec![(0.0, 0.0), (130.0, 0.0), (0.0, 15.0)]);
+        // Without wrapping, the items overflow a single line.
+        let css = "p { width: 120px; } div { gap: 5px 10px; }";
+        assert_eq!(flex_positions(css), vec![(0.0, 0.0), (130.0, 0.0), (260.0, 0.0)]);
+    }
+
+    #[test]
+    fn flex_column_stacks_items_vertically() {
+        assert_eq!(flex_positions("div { flex-direction: column; row-gap: 5px; }"),
+                   vec![(0.0, 0.0), (0.0, 15.0), (0.0, 30.0)]);
+        assert_eq!(flex_positions("div { flex-direction: column; align-items: center; }"),
+                   vec![(125.0, 0.0), (125.0, 10.0), (125.0, 20.0)]);
+        // Free space in a definite height is distributed along the column.
+        assert_eq!(flex_positions("div { flex-direction: column; height: 60px; \
+                                         justify-content: flex-end; }"),
+                   vec![(0.0, 30.0), (0.0, 40.0), (0.0, 50.0)]);
+    }
+
+    #[test]
+    fn layout_many_matches_laying_out_each_tree() {
+        let stylesheet = css::parse("div { display: block; padding: 5px; } \
+                                     p { display: block; width: 50%; height: 10px; }"
+                                    .to_string());
+        let roots = vec![html::parse("<div><p></p></div>".to_string()),
+                         html::parse("<div>some text</div>".to_string())];
+        let styled: Vec<StyledNode> = roots.iter().map(|root| style(root, &stylesheet)).collect();
+        let images = ImageCache::new(Path::new("."));
+
+        let batch = layout_many(&*styled, viewport(), &BUILTIN_FONT, &images);
+        assert_eq!(batch.len(), 2);
+        for (node, layout) in styled.iter().zip(batch.iter()) {
+            assert_eq!(json::layout_to_json(layout),
+                       json::layout_to_json(&layout_tree(node, viewport())));
+        }
+    }
+
+    /// The content size of a 200x100 image laid out with the given attributes and style.
+    fn image_size(attributes: &str, img_css: &str) -> (f32, f32) {
+        let root = html::parse(format!("<div><img src=\"wide.png\" {}></div>", attributes));
+        let stylesheet = css::parse(format!("div {{ display: block; }} img {{ {} }}", img_css));
+        let styled = style(&root, &stylesheet);
+        let images = ImageCache::new(Path::new("examples/images"));
+        let cache = LayoutCache::new();
+        let layout = layout_document(&styled, viewport(), &BUILTIN_FONT, None, &images, &cache);
+        let img = layout.children[0].dimensions.content;
+        (img.width, img.height)
+    }
+
+    #[test]
+    fn images_keep_their_aspect_ratio() {
+        assert_eq!(image_size("", "display: block;"), (200.0, 100.0));
+        assert_eq!(image_size("", "display: block; width: 100px;"), (100.0, 50.0));
+        assert_eq!(image_size("", "display: block; height: 20px;"), (40.0, 20.0));
+        assert_eq!(image_size("width=\"50\"", "display: block;"), (50.0, 25.0));
+        assert_eq!(image_size("width=\"50\" height=\"50\"", "display: block;"), (50.0, 50.0));
+    }
+
+    /// The text and line of each fragment of some text in a `<div>` with a 10px font.
+    fn text_fragments(text: &str, div_css: &str) -> Vec<(String, usize)> {
+        let root = html::parse(format!("<div>{}</div>", text));
+        let stylesheet = css::parse(format!("div {{ display: block; font-size: 10px; {} }}",
+                                            div_css));
+        let styled = style(&root, &stylesheet);
+        let layout = layout_tree(&styled, viewport());
+        layout.children[0].children[0].fragments.iter()
+            .map(|fragment| (fragment.text.clone(), fragment.line))
+            .collect()
+    }
+
+    #[test]
+    fn pre_wrap_keeps_spaces_and_wraps() {
+        assert_eq!(text_fragments("aa  bb\ncc", "white-space: pre-wrap;"),
+                   vec![("aa  bb".to_string(), 0), ("cc".to_string(), 1)]);
+        assert_eq!(text_fragments("aa  bb\ncc", "white-space: pre-wrap; width: 30px;"),
+                   vec![("aa  ".to_string(), 0), ("bb".to_string(), 1), ("cc".to_string(), 2)]);
+        // `pre` never wraps.
+        assert_eq!(text_fragments("aa  bb\ncc", "white-space: pre; width: 30px;"),
+                   vec![("aa  bb".to_string(), 0), ("cc".to_string(), 1)]);
+    }
+
+    #[test]
+    fn pre_line_collapses_spaces_but_keeps_newlines() {
+        assert_eq!(text_fragments("aa   bb \n  cc", "white-space: pre-line;"),
+                   vec![("aa bb".to_string(), 0), ("cc".to_string(), 1)]);
+        assert_eq!(text_fragments("aa   bb \n  cc", ""),
+                   vec![("aa bb cc".to_string(), 0)]);
+    }
+
+    #[test]
+    fn display_contents_children_replace_the_element() {
+        let root = html::parse("<div><section><p></p><p></p></section></div>".to_string());
+        let stylesheet = css::parse("div { display: block; } p { display: block; height: 10px; } \
+                                     section { display: contents; height: 100px; }"
+                                    .to_string());
+        let styled = style(&root, &stylesheet);
+        let layout = layout_tree(&styled, viewport());
+
+        assert_eq!(layout.children.len(), 2);
+        assert_eq!(layout.children[1].dimensions.content.y, 10.0);
+        assert_eq!(layout.dimensions.content.height, 20.0);
+        // The root element still generates a box.
+        let stylesheet = css::parse("div { display: contents; width: 100px; }".to_string());
+        let styled = style(&root, &stylesheet);
+        assert_eq!(layout_tree(&styled, viewport()).dimensions.content.width, 100.0);
+    }
+
+    #[test]
+    fn display_contents_children_become_flex_items() {
+        assert_eq!(flex_positions(".b { display: contents; }"),
+                   vec![(0.0, 0.0), (50.0, 0.0)]);
+    }
+
+    #[test]
+    fn text_indent_moves_only_the_first_line() {
+        let root = html::parse("<div>aaaa bbbb</div>".to_string());
+        for indent in ["20px", "10%"].iter() {
+            let stylesheet = css::parse(format!("div {{ display: block; width: 200px; \
+                                                 font-size: 10px; text-indent: {}; }}",
+                                                indent));
+            let styled = style(&root, &stylesheet);
+            let layout = layout_tree(&styled, viewport());
+            let text = &layout.children[0].children[0];
+            assert_eq!(text.fragments[0].rect.x, 20.0);
+        }
+
+        // Lines after the first start at the left edge.
+        assert_eq!(text_fragments("aaaa bbbb", "width: 60px; text-indent: 20px;"),
+                   vec![("aaaa".to_string(), 0), ("bbbb".to_string(), 1)]);
+        let root = html::parse("<div>aaaa bbbb</div>".to_string());
+        let stylesheet = css::parse("div { display: block; width: 60px; font-size: 10px; \
+                                     text-indent: 20px; }".to_string());
+        let styled = style(&root, &stylesheet);
+        let layout = layout_tree(&styled, viewport());
+        assert_eq!(layout.children[0].children[0].fragments[1].rect.x, 0.0);
+    }
+
+    #[test]
+    fn letter_and_word_spacing_widen_text() {
+        let root = html::parse("<div>ab cd</div>".to_string());
+        let stylesheet = css::parse("div { display: block; font-size: 10px; \
+                                     letter-spacing: 2px; word-spacing: 5px; }".to_string());
+        let styled = style(&root, &stylesheet);
+        let layout = layout_tree(&styled, viewport());
+
+        // Five 6px glyphs, each followed by 2px, and 5px more after the space.
+        let text = &layout.children[0].children[0];
+        assert_eq!(text.fragments[0].rect.width, 45.0);
+    }
+
+    #[test]
+    fn layout_cache_forgets_removed_elements() {
+        let mut root = html::parse("<div><p></p><p><p></p></p></div>".to_string());
+        let stylesheet = css::parse("div, p { display: block; height: 10px; }".to_string());
+        let images = ImageCache::new(Path::new("."));
+        let cache = LayoutCache::new();
+        let relayout = |&: root: &Node| {
+            root.propagate_dirty();
+            let styled = style(root, &stylesheet);
+            layout_document(&styled, viewport(), &BUILTIN_FONT, None, &images, &cache);
+            root.clear_dirty();
+        };
+        relayout(&root);
+        assert_eq!(cache.len(), 3);
+
+        root.remove_child(1);
+        relayout(&root);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn tabs_advance_to_the_next_tab_stop() {
+        assert_eq!(&*expand_tabs("a\tb", 0, 4), "a   b");
+        assert_eq!(&*expand_tabs("\tab\tc", 0, 4), "    ab  c");
+        // Columns are counted from where the text starts on its line.
+        assert_eq!(&*expand_tabs("a\tb", 2, 4), "a b");
+        assert_eq!(&*expand_tabs("a\tb", 0, 0), "ab");
+    }
+
+    #[test]
+    fn preformatted_text_keeps_tabs_and_newlines() {
+        let root = html::parse("<pre>a\tb\n\tc</pre>".to_string());
+        let stylesheet = css::parse("pre { display: block; white-space: pre; font-size: 10px; \
+                                     tab-size: 4; }".to_string());
+        let styled = style(&root, &stylesheet);
+        let layout = layout_tree(&styled, viewport());
+
+        let text = &layout.children[0].children[0];
+        assert_eq!(text.fragments.len(), 2);
+        assert_eq!(&*text.fragments[0].text, "a   b");
+        assert_eq!(text.fragments[0].rect.width, 30.0);
+        assert_eq!(&*text.fragments[1].text, "    c");
+        assert_eq!(text.fragments[1].line, 1);
+    }
+
+    #[test]
+    fn vertical_percentages_use_the_containing_block_width() {
+        let root = html::parse("<div></div>".to_string());
+        let stylesheet = css::parse("div { display: block; margin-top: 10%; \
+                                           padding-bottom: 5%; }".to_string());
+        let styled = style(&root, &stylesheet);
+        let d = layout_tree(&styled, viewport()).dimensions;
+        assert_eq!(d.margin.top, 80.0);
+        assert_eq!(d.padding.bottom, 40.0);
+    }
+
+    /// Metrics with glyphs 10px wide, and lines twice the font size.
+    struct WideFont;
+
+    impl FontMetrics for WideFont {
+        fn advance(&self, _glyph: char, _font: &Font) -> f32 {
+            10.0
+        }
+
+        fn line_height(&self, size: f32) -> f32 {
+            2.0 * size
+        }
+    }
+
+    #[test]
+    fn text_is_measured_with_the_given_metrics() {
+        let root = html::parse("<div>ab cd</div>".to_string());
+        let stylesheet = css::parse("div { display: block; width: 30px; }".to_string());
+        let styled = style(&root, &stylesheet);
+        let layout = layout_tree_with_metrics(&styled, viewport(), &WideFont);
+        let text = &layout.children[0].children[0];
+        let fragments: Vec<(&str, f32, f32)> = text.fragments.iter().map(|f| {
+            (&*f.text, f.rect.width, f.rect.y)
+        }).collect();
+        // The space after "ab" is trimmed from the end of the first line.
+        assert_eq!(fragments, vec![("ab", 20.0, 0.0), ("cd", 20.0, 32.0)]);
+        assert_eq!(layout.dimensions.content.height, 64.0);
+    }
+
+    #[test]
+    fn cached_blocks_are_not_reused_with_other_metrics() {
+        let root = html::parse("<div><p>abc</p></div>".to_string());
+        let stylesheet = css::parse("div, p { display: block; }".to_string());
+        let images = ImageCache::new(Path::new("."));
+        let cache = LayoutCache::new();
+        let width = |&: metrics: &FontMetrics| {
+            root.propagate_dirty();
+            let styled = style(&root, &stylesheet);
+            let layout = layout_document(&styled, viewport(), metrics, None, &images, &cache);
+            root.clear_dirty();
+            layout.children[0].children[0].children[0].fragments[0].rect.width
+        };
+        let builtin = width(&BUILTIN_FONT);
+        assert_eq!(width(&WideFont), 30.0);
+        assert_eq!(width(&BUILTIN_FONT), builtin);
+        assert_eq!(cache.recompute_count(), 3);
+    }
+
+    /// Keeps the phase and box count of every timing it is given.
+    struct PhaseLog {
+        phases: RefCell<Vec<(LayoutPhase, usize)>>,
+    }
+
+    impl LayoutRecorder for PhaseLog {
+        fn record(&self, phase: LayoutPhase, _layout_box: &LayoutBox, _duration: Duration,
+                  boxes: usize) {
+            self.phases.borrow_mut().push((phase, boxes));
+        }
+    }
+
+    #[test]
+    fn recorder_gets_each_phase_with_the_subtree_size() {
+        let root = html::parse("<div><p></p><p></p></div>".to_string());
+        let stylesheet = css::parse("div, p { display: block; }".to_string());
+        let styled = style(&root, &stylesheet);
+        let log = PhaseLog { phases: RefCell::new(vec![]) };
+        layout_tree_with_recorder(&styled, viewport(), &BUILTIN_FONT, Some(&log));
+        // Each phase is reported once it finishes, so the children come inside the div's
+        // content phase.
+        let phases = log.phases.borrow();
+        assert_eq!(*phases, vec![(LayoutPhase::Width, 3),
+                                 (LayoutPhase::Width, 1), (LayoutPhase::Content, 1),
+                                 (LayoutPhase::Height, 1),
+                                 (LayoutPhase::Width, 1), (LayoutPhase::Content, 1),
+                                 (LayoutPhase::Height, 1),
+                                 (LayoutPhase::Content, 3), (LayoutPhase::Height, 3)]);
+    }
+
+    #[test]
+    fn long_words_break_with_overflow_wrap_or_word_break() {
+        // Five 6px glyphs fit on each 30px line.
+        let css = "width: 30px;";
+        assert_eq!(text_fragments("aa bbbbbbbbbb", css),
+                   vec![("aa".to_string(), 0), ("bbbbbbbbbb".to_string(), 1)]);
+        // The long word starts a new line, and is broken only where it overflows.
+        let css = "width: 30px; overflow-wrap: break-word;";
+        assert_eq!(text_fragments("aa bbbbbbbbbb", css),
+                   vec![("aa".to_string(), 0), ("bbbbb".to_string(), 1),
+                        ("bbbbb".to_string(), 2)]);
+        // Any word is broken where the line runs out.
+        let css = "width: 30px; word-break: break-all;";
+        assert_eq!(text_fragments("aaa bbbbbbb", css),
+                   vec![("aaa b".to_string(), 0), ("bbbbb".to_string(), 1),
+                        ("b".to_string(), 2)]);
+    }
+
+    #[test]
+    fn overflow_wrap_anywhere_narrows_the_min_content_width() {
+        let html = "<div><p>aaa bbbb</p></div>";
+        assert_eq!(child_width(html, "p { width: min-content; overflow-wrap: break-word; }"),
+                   24.0);
+        assert_eq!(child_width(html, "p { width: min-content; overflow-wrap: anywhere; }"), 6.0);
+        assert_eq!(child_width(html, "p { width: min-content; word-break: break-all; }"), 6.0);
+    }
+
+    #[test]
+    fn background_rect_is_the_border_box() {
+        let root = html::parse("<div></div>".to_string());
+        let stylesheet = css::parse("div { display: block; width: 100px; height: 50px; \
+                                     margin: 5px; padding: 10px; border-width: 2px; }"
+                                    .to_string());
+        let styled = style(&root, &stylesheet);
+        let r = layout_tree(&styled, viewport()).background_rect();
+        assert_eq!((r.x, r.y, r.width, r.height), (5.0, 5.0, 124.0, 74.0));
+    }
+
+    #[test]
+    fn background_clip_picks_the_background_rect() {
+        let rect = |&: clip: &str| {
+            let root = html::parse("<div></div>".to_string());
+            let stylesheet = css::parse(format!("div {{ display: block; width: 100px; \
+                                                 height: 50px; padding: 10px; \
+                                                 border-width: 2px; background-clip: {}; }}",
+                                                clip));
+            let styled = style(&root, &stylesheet);
+            let r = layout_tree(&styled, viewport()).background_rect();
+            (r.x, r.y, r.width, r.height)
+        };
+        assert_eq!(rect("border-box"), (0.0, 0.0, 124.0, 74.0));
+        assert_eq!(rect("padding-box"), (2.0, 2.0, 120.0, 70.0));
+        assert_eq!(rect("content-box"), (12.0, 12.0, 100.0, 50.0));
+    }
+
+    #[test]
+    fn wrapped_inline_boxes_have_a_background_rect_per_line() {
+        let root = html::parse("<div><span>aaa bbb</span></div>".to_string());
+        let stylesheet = css::parse("div { display: block; width: 30px; font-size: 10px; }"
+                                    .to_string());
+        let styled = style(&root, &stylesheet);
+        let layout = layout_tree(&styled, viewport());
+        let span = &layout.children[0].children[0];
+        let rects: Vec<(f32, f32, f32, f32)> = span.background_rects().iter().map(|r| {
+            (r.x, r.y, r.width, r.height)
+        }).collect();
+        // The space at the end of the first line isn't painted.
+        assert_eq!(rects, vec![(0.0, 0.0, 18.0, 12.0), (0.0, 12.0, 18.0, 12.0)]);
+        // A block box has just the one.
+        assert_eq!(layout.background_rects().len(), 1);
+    }
 }
@@ -0,0 +1,20 @@
+//! A toy web rendering engine: HTML and CSS parsing, style, layout, and painting.
+//!
+//! The `robinson` binary in `main.rs` is a command-line front end for this library.
+
+#![feature(core, std_misc, collections, path, io, os)]
+
+extern crate image;
+
+pub mod css;
+pub mod document;
+pub mod dom;
+pub mod golden;
+pub mod html;
+pub mod images;
+pub mod json;
+pub mod layout;
+pub mod style;
+pub mod painting;
+pub mod pdf;
+pub mod svg;
@@ -2,19 +2,13 @@
 
 extern crate getopts;
 extern crate image;
+extern crate robinson;
 
 use getopts::Options;
+use robinson::{css, document, golden, html, layout, painting, pdf, svg};
 use std::default::Default;
 use std::old_io::fs::File;
-use std::os::args;
-
-mod css;
-mod dom;
-mod html;
-mod layout;
-mod style;
-mod painting;
-mod pdf;
+use std::os::{args, set_exit_status};
 
 fn main() {
     // Parse command-line options:
@@ -22,22 +16,36 @@ fn main() {
     opts.optopt("h", "html", "HTML document", "FILENAME");
     opts.optopt("c", "css", "CSS stylesheet", "FILENAME");
     opts.optopt("o", "output", "Output file", "FILENAME");
-    opts.optopt("f", "format", "Output file format", "png | pdf");
+    opts.optopt("f", "format", "Output file format", "png | pdf | svg");
+    opts.optopt("g", "golden", "Check layout against the fixtures in a directory", "DIRECTORY");
 
     let matches = match opts.parse(args().tail()) {
         Ok(m) => m,
         Err(f) => panic!(f.to_string())
     };
 
-    let png = match matches.opt_str("f") {
-        None => true,
-        Some(format) => match &*format {
-            "png" => true,
-            "pdf" => false,
-            _ => panic!("Unknow output format: {}", format),
-        }
+    let format = matches.opt_str("f").unwrap_or("png".to_string());
+    match &*format {
+        "png" | "pdf" | "svg" => {}
+        _ => panic!("Unknow output format: {}", format),
+    }
+
+    // Since we don't have an actual window, hard-code the "viewport" size.
+    let initial_containing_block = layout::Dimensions {
+        content: layout::Rect { x: 0.0, y: 0.0, width: 800.0, height: 600.0 },
+        padding: Default::default(),
+        border: Default::default(),
+        margin: Default::default(),
     };
 
+    // Run layout regression tests instead of rendering:
+    if let Some(dir) = matches.opt_str("g") {
+        if !golden::check(&Path::new(dir), initial_containing_block) {
+            set_exit_status(1);
+        }
+        return;
+    }
+
     // Read input files:
     let read_source = |&: arg_filename: Option<String>, default_filename: &str| {
         let path = match arg_filename {
@@ -46,31 +54,31 @@ fn main() {
         };
         File::open(&Path::new(path)).read_to_string().unwrap()
     };
-    let html = read_source(matches.opt_str("h"), "examples/test.html");
+    let html_path = Path::new(matches.opt_str("h").unwrap_or("examples/test.html".to_string()));
+    let html = File::open(&html_path).read_to_string().unwrap();
     let css  = read_source(matches.opt_str("c"), "examples/test.css");
 
-    // Since we don't have an actual window, hard-code the "viewport" size.
-    let initial_containing_block = layout::Dimensions {
-        content: layout::Rect { x: 0.0, y: 0.0, width: 800.0, height: 600.0 },
-        padding: Default::default(),
-        border: Default::default(),
-        margin: Default::default(),
-    };
-
     // Parsing and rendering:
-    let root_node = html::parse(html);
-    let stylesheet = css::parse(css);
-    let style_root = style::style_tree(&root_node, &stylesheet);
-    let layout_root = layout::layout_tree(&style_root, initial_containing_block);
+    let (stylesheet, css_errors) = css::parse_with_errors(css);
+    let mut stderr = std::old_io::stderr();
+    for error in css_errors.iter() {
+        let _ = writeln!(&mut stderr, "CSS error: {} at {}:{}", error.message, error.line,
+                         error.column);
+    }
+    let mut document = document::Document::new(html::parse(html), stylesheet);
+    // Images are found relative to the HTML file.
+    document.set_base(html_path.dir_path());
+    let styled_document = document.style(initial_containing_block.content);
+    let layout_root = styled_document.layout(initial_containing_block);
 
     // Create the output file:
-    let default_filename = if png { "output.png" } else { "output.pdf" };
-    let filename = matches.opt_str("o").unwrap_or(default_filename.to_string());
+    let filename = matches.opt_str("o").unwrap_or(format!("output.{}", format));
     let mut file = File::create(&Path::new(&*filename)).unwrap();
 
     let result_ok;
-    if png {
-        let canvas = painting::paint(&layout_root, initial_containing_block.content);
+    if format == "png" {
+        let white = css::Color { r: 255, g: 255, b: 255, a: 255 };
+        let canvas = painting::paint(&layout_root, initial_containing_block.content, white);
 
         // Save an image:
         let (w, h) = (canvas.width as u32, canvas.height as u32);
@@ -78,8 +86,10 @@ fn main() {
         let img = image::ImageBuffer::from_fn(w, h, Box::new(|&: x: u32, y: u32| buffer[(y * w + x) as usize]));
 
         result_ok = image::ImageRgba8(img).save(file, image::PNG).is_ok();
-    } else {
+    } else if format == "pdf" {
         result_ok = pdf::render(&layout_root, initial_containing_block.content, &mut file).is_ok();
+    } else {
+        result_ok = file.write_str(&*svg::to_svg(&layout_root)).is_ok();
     }
 
     if result_ok {
@@ -1,7 +1,12 @@
-use layout::{AnonymousBlock, BlockNode, InlineNode, LayoutBox, Rect};
+use layout::{AnonymousBlock, BlockNode, InlineNode, LayoutBox, Rect, EdgeSizes, Dimensions};
 use css::{Value, Color};
+use css::Unit::{Px, Percent};
+use style::{TransformFn, TextDecoration, ObjectFit};
+use images::Image;
 use std::iter::repeat;
 use std::num::Float;
+use std::rc::Rc;
+use std::collections::HashMap;
 
 pub struct Canvas {
     pub pixels: Vec<Color>,
@@ -9,10 +14,13 @@ pub struct Canvas {
     pub height: usize,
 }
 
-/// Paint a tree of LayoutBoxes to an array of pixels.
-pub fn paint(layout_root: &LayoutBox, bounds: Rect) -> Canvas {
+/// Paint a tree of LayoutBoxes to an array of pixels, on a canvas filled with `background`.
+///
+/// The background may be transparent, in which case so is any part of the canvas that isn't
+/// painted over.
+pub fn paint(layout_root: &LayoutBox, bounds: Rect, background: Color) -> Canvas {
     let display_list = build_display_list(layout_root);
-    let mut canvas = Canvas::new(bounds.width as usize, bounds.height as usize);
+    let mut canvas = Canvas::new(bounds.width as usize, bounds.height as usize, background);
     for item in display_list.iter() {
         canvas.paint_item(item);
     }
@@ -22,73 +30,384 @@ pub fn paint(layout_root: &LayoutBox, bounds: Rect) -> Canvas {
 #[derive(Show)]
 pub enum DisplayCommand {
     SolidColor(Color, Rect),
+    /// An image stretched to fill the first rectangle, clipped to the second.
+    Image(Rc<Image>, Rect, Rect),
 }
 
 type DisplayList = Vec<DisplayCommand>;
 
+/// Paint each box in the order of `LayoutBox::paint_order`, and then their outlines in the same
+/// order.
+///
+/// Each box is painted with its own transform and those of its ancestors, and its text with its
+/// own text decorations and those of its ancestors.
+///
+/// TODO: Outlines should go in front of the rest of their own stacking context, not in front of
+/// everything.
 pub fn build_display_list(layout_root: &LayoutBox) -> DisplayList {
+    let mut contexts = HashMap::new();
+    let root_context = PaintContext { transforms: vec![], decorations: vec![] };
+    collect_paint_contexts(layout_root, &root_context, &mut contexts);
+
     let mut list = Vec::new();
-    render_layout_box(&mut list, layout_root);
+    let order = layout_root.paint_order();
+    for &layout_box in order.iter() {
+        let context = &contexts[layout_box as *const LayoutBox];
+        let mut items = Vec::new();
+        render_box(&mut items, layout_box);
+        for decoration in context.decorations.iter() {
+            render_text_decoration(&mut items, layout_box, decoration);
+        }
+        push_transformed(&mut list, items, &*context.transforms);
+    }
+    for &layout_box in order.iter() {
+        let context = &contexts[layout_box as *const LayoutBox];
+        let mut items = Vec::new();
+        render_outline(&mut items, layout_box);
+        push_transformed(&mut list, items, &*context.transforms);
+    }
     return list;
 }
 
-fn render_layout_box(list: &mut DisplayList, layout_box: &LayoutBox) {
+/// What a box gets from its ancestors when it is painted: the transforms that apply to it, from
+/// the innermost out, each with its origin; and the text decorations drawn over its text.
+#[derive(Clone)]
+struct PaintContext {
+    transforms: Vec<(Vec<TransformFn>, (f32, f32))>,
+    decorations: Vec<TextDecoration>,
+}
+
+/// Find the `PaintContext` of each box in a tree, given the context of its parent.
+fn collect_paint_contexts<'a>(layout_box: &LayoutBox<'a>, parent: &PaintContext,
+                              contexts: &mut HashMap<*const LayoutBox<'a>, PaintContext>) {
+    let mut context = parent.clone();
+    match layout_box.box_type {
+        BlockNode(style) | InlineNode(style) => {
+            // The transform is relative to the center of the border box.
+            let transform = style.transform();
+            if !transform.is_empty() {
+                let border_box = layout_box.dimensions.border_box();
+                let origin = (border_box.x + border_box.width / 2.0,
+                              border_box.y + border_box.height / 2.0);
+                context.transforms.insert(0, (transform, origin));
+            }
+            if let Some(decoration) = style.text_decoration() {
+                context.decorations.push(decoration);
+            }
+        }
+        AnonymousBlock => {}
+    }
+    for child in layout_box.children.iter() {
+        collect_paint_contexts(child, &context, contexts);
+    }
+    contexts.insert(layout_box as *const LayoutBox, context);
+}
+
+/// Add the commands painted for one box to the list, after applying `transforms` in order.
+fn push_transformed(list: &mut DisplayList, items: DisplayList,
+                    transforms: &[(Vec<TransformFn>, (f32, f32))]) {
+    for item in items.into_iter() {
+        list.push(transforms.iter().fold(item, |item, &(ref transform, origin)| match item {
+            DisplayCommand::SolidColor(color, rect) =>
+                DisplayCommand::SolidColor(color, transform_rect(rect, &**transform, origin)),
+            DisplayCommand::Image(image, rect, clip) =>
+                DisplayCommand::Image(image, transform_rect(rect, &**transform, origin),
+                                      transform_rect(clip, &**transform, origin)),
+        }));
+    }
+}
+
+/// Paint the parts of a box that go behind its content.
+fn render_box(list: &mut DisplayList, layout_box: &LayoutBox) {
+    render_box_shadow(list, layout_box);
     render_background(list, layout_box);
     render_borders(list, layout_box);
-    for child in layout_box.children.iter() {
-        render_layout_box(list, child);
+    render_replaced_image(list, layout_box);
+}
+
+/// Apply a list of transform functions to a rectangle, about the point `origin`.
+///
+/// As in CSS, the last function in the list is applied first. Rotations can't be drawn with
+/// axis-aligned rectangles, so they are ignored for now.
+fn transform_rect(rect: Rect, transform: &[TransformFn], origin: (f32, f32)) -> Rect {
+    let (origin_x, origin_y) = origin;
+    let mut rect = Rect { x: rect.x - origin_x, y: rect.y - origin_y, ..rect };
+    for function in transform.iter().rev() {
+        match *function {
+            TransformFn::Translate(dx, dy) => {
+                rect.x = rect.x + dx;
+                rect.y = rect.y + dy;
+            }
+            TransformFn::Scale(sx, sy) => {
+                rect = Rect {
+                    x: (rect.x * sx).min((rect.x + rect.width) * sx),
+                    y: (rect.y * sy).min((rect.y + rect.height) * sy),
+                    width: rect.width * sx.abs(),
+                    height: rect.height * sy.abs(),
+                };
+            }
+            TransformFn::Rotate(_) => {} // TODO
+        }
+    }
+    rect.x = rect.x + origin_x;
+    rect.y = rect.y + origin_y;
+    return rect;
+}
+
+fn render_box_shadow(list: &mut DisplayList, layout_box: &LayoutBox) {
+    let shadow = match layout_box.box_type {
+        BlockNode(style) | InlineNode(style) => match style.box_shadow() {
+            Some(shadow) => shadow,
+            None => return
+        },
+        AnonymousBlock => return
+    };
+
+    // The shadow is the border box, grown by the spread distance and moved by the offset.
+    // TODO: Blur the edges by `shadow.blur_radius`.
+    let border_box = layout_box.dimensions.border_box();
+    let spread = shadow.spread;
+    let mut rect = border_box.expanded_by(EdgeSizes {
+        left: spread, right: spread, top: spread, bottom: spread
+    });
+    rect.x = rect.x + shadow.offset_x;
+    rect.y = rect.y + shadow.offset_y;
+
+    // An outer shadow is only drawn outside the border box, so a transparent background
+    // doesn't show it.
+    for &part in subtract_rect(rect, border_box).iter() {
+        list.push(DisplayCommand::SolidColor(shadow.color, part));
+    }
+}
+
+/// The parts of `rect` outside of `hole`, as up to four non-overlapping rectangles: the bands
+/// above and below `hole`, and the parts to its left and right between them.
+fn subtract_rect(rect: Rect, hole: Rect) -> Vec<Rect> {
+    if !rect.intersects(hole) {
+        return vec![rect];
     }
+    let (top, bottom) = (rect.y.max(hole.y), (rect.y + rect.height).min(hole.y + hole.height));
+    let (left, right) = (rect.x.max(hole.x), (rect.x + rect.width).min(hole.x + hole.width));
+    let parts = vec![
+        Rect { x: rect.x, y: rect.y, width: rect.width, height: top - rect.y },
+        Rect { x: rect.x, y: bottom, width: rect.width, height: rect.y + rect.height - bottom },
+        Rect { x: rect.x, y: top, width: left - rect.x, height: bottom - top },
+        Rect { x: right, y: top, width: rect.x + rect.width - right, height: bottom - top },
+    ];
+    parts.into_iter().filter(|part| part.width > 0.0 && part.height > 0.0).collect()
 }
 
 fn render_background(list: &mut DisplayList, layout_box: &LayoutBox) {
-    get_color(layout_box, "background").map(|color|
-        list.push(DisplayCommand::SolidColor(color, layout_box.dimensions.border_box())));
+    if let Some(color) = get_color(layout_box, "background") {
+        for &rect in layout_box.background_rects().iter() {
+            list.push(DisplayCommand::SolidColor(color, rect));
+        }
+    }
+    render_background_image(list, layout_box);
+}
+
+/// Draw the `background-image` of a box once, at its natural size, placed within the padding box
+/// by `background-position` and clipped as `background-clip` says.
+///
+/// TODO: `background-repeat` and `background-size`.
+fn render_background_image(list: &mut DisplayList, layout_box: &LayoutBox) {
+    let style = match layout_box.box_type {
+        BlockNode(style) | InlineNode(style) => style,
+        AnonymousBlock => return
+    };
+    let image = match style.background_image().and_then(|url| layout_box.load_image(&*url)) {
+        Some(image) => image,
+        None => return
+    };
+    let area = layout_box.dimensions.padding_box();
+    let (width, height) = (image.width as f32, image.height as f32);
+    let (x, y) = style.background_position();
+    let rect = Rect {
+        x: area.x + background_offset(&x, area.width - width),
+        y: area.y + background_offset(&y, area.height - height),
+        width: width,
+        height: height,
+    };
+    list.push(DisplayCommand::Image(image, rect, layout_box.background_rect()));
+}
+
+/// Draw the image of an `<img>` element in its content box, scaled by `object-fit` and placed by
+/// `object-position`. Any part of the image outside the content box is clipped.
+fn render_replaced_image(list: &mut DisplayList, layout_box: &LayoutBox) {
+    let style = match layout_box.box_type {
+        BlockNode(style) | InlineNode(style) => style,
+        AnonymousBlock => return
+    };
+    let src = match style.element() {
+        Some(elem) if &*elem.tag_name == "img" => elem.attributes.get("src"),
+        _ => None
+    };
+    let image = match src.and_then(|src| layout_box.load_image(&**src)) {
+        Some(image) => image,
+        None => return
+    };
+    let area = layout_box.dimensions.content;
+    let (width, height) = (image.width as f32, image.height as f32);
+    let (width, height) = object_fit_size(style.object_fit(), area, width, height);
+    let (x, y) = style.object_position();
+    let rect = Rect {
+        x: area.x + background_offset(&x, area.width - width),
+        y: area.y + background_offset(&y, area.height - height),
+        width: width,
+        height: height,
+    };
+    list.push(DisplayCommand::Image(image, rect, area));
+}
+
+/// The size to draw an image `width` by `height` px at so that it fits in `area` as `object-fit`
+/// says.
+fn object_fit_size(fit: ObjectFit, area: Rect, width: f32, height: f32) -> (f32, f32) {
+    if width <= 0.0 || height <= 0.0 {
+        return (area.width, area.height);
+    }
+    let scale_x = area.width / width;
+    let scale_y = area.height / height;
+    let scale = match fit {
+        ObjectFit::Fill => return (area.width, area.height),
+        ObjectFit::Contain => scale_x.min(scale_y),
+        ObjectFit::Cover => scale_x.max(scale_y),
+        ObjectFit::None => 1.0,
+        ObjectFit::ScaleDown => scale_x.min(scale_y).min(1.0),
+    };
+    (width * scale, height * scale)
+}
+
+/// Resolve a `background-position` or `object-position` offset. Percentages are relative to
+/// `free_space`, the size of the area minus the size of the image, so `50%` centers the image.
+fn background_offset(value: &Value, free_space: f32) -> f32 {
+    match *value {
+        Value::Length(length, Px) => length,
+        Value::Length(percent, Percent) => free_space * percent / 100.0,
+        _ => 0.0
+    }
 }
 
 fn render_borders(list: &mut DisplayList, layout_box: &LayoutBox) {
-    let color = match get_color(layout_box, "border-color") {
+    let color = match get_border_color(layout_box) {
         Some(color) => color,
         _ => return
     };
+    for &rect in border_rects(&layout_box.dimensions).iter() {
+        list.push(DisplayCommand::SolidColor(color, rect));
+    }
+}
 
-    let d = &layout_box.dimensions;
+/// The left, right, top, and bottom borders of a box, in that order.
+pub fn border_rects(d: &Dimensions) -> [Rect; 4] {
     let border_box = d.border_box();
+    [
+        Rect {
+            x: border_box.x,
+            y: border_box.y,
+            width: d.border.left,
+            height: border_box.height,
+        },
+        Rect {
+            x: border_box.x + border_box.width - d.border.right,
+            y: border_box.y,
+            width: d.border.right,
+            height: border_box.height,
+        },
+        Rect {
+            x: border_box.x,
+            y: border_box.y,
+            width: border_box.width,
+            height: d.border.top,
+        },
+        Rect {
+            x: border_box.x,
+            y: border_box.y + border_box.height - d.border.bottom,
+            width: border_box.width,
+            height: d.border.bottom,
+        },
+    ]
+}
 
-    // Left border
-    list.push(DisplayCommand::SolidColor(color, Rect {
-        x: border_box.x,
-        y: border_box.y,
-        width: d.border.left,
-        height: border_box.height,
-    }));
+/// Height of the baseline above the bottom of a glyph box in the built-in font, as a fraction of
+/// the font size.
+const DESCENT: f32 = 0.2;
 
-    // Right border
-    list.push(DisplayCommand::SolidColor(color, Rect {
-        x: border_box.x + border_box.width - d.border.right,
-        y: border_box.y,
-        width: d.border.right,
-        height: border_box.height,
-    }));
+/// Draw the lines of `decoration`, set on a box or one of its ancestors, over the box's text.
+///
+/// TODO: Skip descendants that are floated, positioned, or atomic inlines.
+fn render_text_decoration(list: &mut DisplayList, layout_box: &LayoutBox,
+                          decoration: &TextDecoration) {
+    let style = match layout_box.box_type {
+        InlineNode(style) if style.text().is_some() => style,
+        _ => return
+    };
+    // The glyphs are centered vertically in the line. The underline sits just below the baseline,
+    // the overline along the top of the glyphs, and the line-through across their middle.
+    let font_size = style.font_size();
+    let thickness = (font_size / 16.0).max(1.0);
+    for fragment in layout_box.fragments.iter() {
+        let rect = fragment.rect;
+        let glyph_bottom = rect.y + (rect.height + font_size) / 2.0;
+        let mut positions = Vec::new();
+        if decoration.underline {
+            positions.push(glyph_bottom - font_size * DESCENT);
+        }
+        if decoration.overline {
+            positions.push(glyph_bottom - font_size);
+        }
+        if decoration.line_through {
+            positions.push(glyph_bottom - (font_size + thickness) / 2.0);
+        }
+        for &y in positions.iter() {
+            list.push(DisplayCommand::SolidColor(decoration.color, Rect {
+                x: rect.x, y: y, width: rect.width, height: thickness,
+            }));
+        }
+    }
+}
 
-    // Top border
-    list.push(DisplayCommand::SolidColor(color, Rect {
-        x: border_box.x,
-        y: border_box.y,
-        width: border_box.width,
-        height: d.border.top,
-    }));
+/// Draw the outline of a box just outside its border box. Outlines take up no space in layout,
+/// so they can overlap other boxes.
+fn render_outline(list: &mut DisplayList, layout_box: &LayoutBox) {
+    let outline = match layout_box.box_type {
+        BlockNode(style) | InlineNode(style) => match style.outline() {
+            Some(outline) => outline,
+            None => return
+        },
+        AnonymousBlock => return
+    };
+    let width = outline.width;
+    let border_box = layout_box.dimensions.border_box();
+    let outer = border_box.expanded_by(EdgeSizes {
+        left: width, right: width, top: width, bottom: width
+    });
 
-    // Bottom border
-    list.push(DisplayCommand::SolidColor(color, Rect {
-        x: border_box.x,
-        y: border_box.y + border_box.height - d.border.bottom,
-        width: border_box.width,
-        height: d.border.bottom,
+    // Top and bottom span the full outer width; left and right fill in the sides between them.
+    list.push(DisplayCommand::SolidColor(outline.color, Rect {
+        x: outer.x, y: outer.y, width: outer.width, height: width,
+    }));
+    list.push(DisplayCommand::SolidColor(outline.color, Rect {
+        x: outer.x, y: border_box.y + border_box.height, width: outer.width, height: width,
+    }));
+    list.push(DisplayCommand::SolidColor(outline.color, Rect {
+        x: outer.x, y: border_box.y, width: width, height: border_box.height,
     }));
+    list.push(DisplayCommand::SolidColor(outline.color, Rect {
+        x: border_box.x + border_box.width, y: border_box.y,
+        width: width, height: border_box.height,
+    }));
+}
+
+/// Return the color of a box's borders, or None if no border color was specified.
+pub fn get_border_color(layout_box: &LayoutBox) -> Option<Color> {
+    match layout_box.box_type {
+        BlockNode(style) | InlineNode(style) => style.border_color(),
+        AnonymousBlock => None
+    }
 }
 
 /// Return the specified color for CSS property `name`, or None if no color was specified.
-fn get_color(layout_box: &LayoutBox, name: &str) -> Option<Color> {
+pub fn get_color(layout_box: &LayoutBox, name: &str) -> Option<Color> {
     match layout_box.box_type {
         BlockNode(style) | InlineNode(style) => match style.value(name) {
             Some(Value::ColorValue(color)) => Some(color),
@@ -99,11 +418,10 @@ fn get_color(layout_box: &LayoutBox, name: &str) -> Option<Color> {
 }
 
 impl Canvas {
-    /// Create a blank canvas
-    fn new(width: usize, height: usize) -> Canvas {
-        let white = Color { r: 255, g: 255, b: 255, a: 255 };
+    /// Create a blank canvas filled with `background`.
+    fn new(width: usize, height: usize, background: Color) -> Canvas {
         return Canvas {
-            pixels: repeat(white).take(width * height).collect(),
+            pixels: repeat(background).take(width * height).collect(),
             width: width,
             height: height,
         }
@@ -120,8 +438,39 @@ impl Canvas {
 
                 for y in (y0 .. y1) {
                     for x in (x0 .. x1) {
-                        // TODO: alpha compositing with existing pixel
-                        self.pixels[y * self.width + x] = color;
+                        let index = y * self.width + x;
+                        self.pixels[index] = color.over(&self.pixels[index]);
+                    }
+                }
+            }
+            &DisplayCommand::Image(ref image, rect, clip) => {
+                if rect.width <= 0.0 || rect.height <= 0.0 {
+                    return;
+                }
+                // Clip the image to the clip rectangle and the canvas boundaries.
+                let x0 = rect.x.max(clip.x).clamp(0.0, self.width as f32) as usize;
+                let y0 = rect.y.max(clip.y).clamp(0.0, self.height as f32) as usize;
+                let x1 = (rect.x + rect.width).min(clip.x + clip.width)
+                                              .clamp(0.0, self.width as f32) as usize;
+                let y1 = (rect.y + rect.height).min(clip.y + clip.height)
+                                               .clamp(0.0, self.height as f32) as usize;
+
+                // Sample the nearest image pixel to the center of each canvas pixel.
+                let scale_x = image.width as f32 / rect.width;
+                let scale_y = image.height as f32 / rect.height;
+                for y in (y0 .. y1) {
+                    let image_y = ((y as f32 + 0.5 - rect.y) * scale_y) as usize;
+                    if image_y >= image.height {
+                        continue;
+                    }
+                    for x in (x0 .. x1) {
+                        let image_x = ((x as f32 + 0.5 - rect.x) * scale_x) as usize;
+                        if image_x >= image.width {
+                            continue;
+                        }
+                        let index = y * self.width + x;
+                        let pixel = image.pixel(image_x, image_y);
+                        self.pixels[index] = pixel.over(&self.pixels[index]);
                     }
                 }
             }
@@ -135,3 +484,193 @@ trait FloatClamp : Float {
     }
 }
 impl<T: Float> FloatClamp for T {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::object_fit_size;
+    use std::default::Default;
+    use css::{self, Stylesheet, Color};
+    use dom::Node;
+    use html;
+    use images::ImageCache;
+    use layout::{self, Dimensions, Rect, LayoutCache, BUILTIN_FONT};
+    use style::{style_tree, StyledNode, ObjectFit};
+
+    /// Style a tree with the default 16px root font size and 96px per inch, for an 800x600
+    /// viewport.
+    fn style<'a>(root: &'a Node, stylesheet: &'a Stylesheet) -> StyledNode<'a> {
+        style_tree(root, stylesheet, 16.0, (800.0, 600.0), 96.0)
+    }
+
+    fn viewport() -> Dimensions {
+        let mut viewport: Dimensions = Default::default();
+        viewport.content.width = 800.0;
+        viewport.content.height = 600.0;
+        viewport
+    }
+
+    /// The color and rect of each solid color command, as `(color, (x, y, width, height))`.
+    fn commands(list: &[DisplayCommand]) -> Vec<(Color, (f32, f32, f32, f32))> {
+        list.iter().filter_map(|command| match *command {
+            DisplayCommand::SolidColor(color, r) => Some((color, (r.x, r.y, r.width, r.height))),
+            DisplayCommand::Image(..) => None
+        }).collect()
+    }
+
+    #[test]
+    fn box_shadow_is_painted_behind_the_box() {
+        let root = html::parse("<div></div>".to_string());
+        let stylesheet = css::parse("div { display: block; width: 100px; height: 50px; \
+                                     background: #ffffff; \
+                                     box-shadow: 10px 10px rgba(0, 0, 0, 0.5); }".to_string());
+        let styled = style(&root, &stylesheet);
+        let layout_root = layout::layout_tree(&styled, viewport());
+
+        let shadow = Color { r: 0, g: 0, b: 0, a: 128 };
+        let white = Color { r: 255, g: 255, b: 255, a: 255 };
+        // Only the parts of the shadow outside the border box are drawn.
+        assert_eq!(commands(&*build_display_list(&layout_root)), vec![
+            (shadow, (10.0, 50.0, 100.0, 10.0)),
+            (shadow, (100.0, 10.0, 10.0, 40.0)),
+            (white, (0.0, 0.0, 100.0, 50.0)),
+        ]);
+    }
+
+    #[test]
+    fn outline_is_drawn_outside_the_border_box() {
+        let root = html::parse("<div></div>".to_string());
+        let stylesheet = css::parse("div { display: block; width: 100px; height: 50px; \
+                                     outline: 2px solid #ff0000; }".to_string());
+        let styled = style(&root, &stylesheet);
+        let layout_root = layout::layout_tree(&styled, viewport());
+
+        let red = Color { r: 255, g: 0, b: 0, a: 255 };
+        assert_eq!(commands(&*build_display_list(&layout_root)), vec![
+            (red, (-2.0, -2.0, 104.0, 2.0)),
+            (red, (-2.0, 50.0, 104.0, 2.0)),
+            (red, (-2.0, 0.0, 2.0, 50.0)),
+            (red, (100.0, 0.0, 2.0, 50.0)),
+        ]);
+    }
+
+    #[test]
+    fn outline_longhands_override_the_shorthand() {
+        let root = html::parse("<div></div>".to_string());
+        let stylesheet = css::parse("div { display: block; color: #0000ff; \
+                                     outline: 2px solid #ff0000; outline-width: thick; \
+                                     outline-color: currentcolor; }".to_string());
+        let styled = style(&root, &stylesheet);
+        let outline = styled.outline().unwrap();
+        assert_eq!(outline.width, 5.0);
+        assert_eq!(outline.color, Color { r: 0, g: 0, b: 255, a: 255 });
+    }
+
+    #[test]
+    fn no_outline_without_a_style() {
+        let root = html::parse("<div></div>".to_string());
+        let stylesheet = css::parse("div { display: block; outline: 2px #ff0000; }".to_string());
+        let styled = style(&root, &stylesheet);
+        let layout_root = layout::layout_tree(&styled, viewport());
+        assert!(build_display_list(&layout_root).is_empty());
+    }
+
+    /// The colors painted for `<div class="a"><div class="c"></div></div><div class="b"></div>`,
+    /// where `c` is red with `z-index: 100` and `b` is blue with `z-index: 1`.
+    fn stacked_colors(parent_css: &str) -> Vec<Color> {
+        let root = html::parse("<div><div class=\"a\"><div class=\"c\"></div></div>\
+                                <div class=\"b\"></div></div>".to_string());
+        let stylesheet = css::parse(format!("div {{ display: block; height: 10px; }} \
+            .a {{ {} }} \
+            .b {{ position: relative; z-index: 1; background: #0000ff; }} \
+            .c {{ position: relative; z-index: 100; background: #ff0000; }}", parent_css));
+        let styled = style(&root, &stylesheet);
+        let layout_root = layout::layout_tree(&styled, viewport());
+        commands(&*build_display_list(&layout_root)).into_iter().map(|(color, _)| color).collect()
+    }
+
+    #[test]
+    fn auto_z_index_does_not_contain_descendant_layers() {
+        let red = Color { r: 255, g: 0, b: 0, a: 255 };
+        let blue = Color { r: 0, g: 0, b: 255, a: 255 };
+        assert_eq!(stacked_colors("position: relative;"), vec![blue, red]);
+        assert_eq!(stacked_colors("position: relative; z-index: 0;"), vec![red, blue]);
+    }
+
+    #[test]
+    fn content_visibility_hidden_skips_painting_the_contents() {
+        let root = html::parse("<div><p></p><p class=\"layer\"></p></div>".to_string());
+        let stylesheet = css::parse("div { display: block; width: 100px; height: 20px; \
+                                           background: #ff0000; content-visibility: hidden; } \
+                                     p { display: block; height: 10px; background: #0000ff; } \
+                                     .layer { position: relative; z-index: 1; }".to_string());
+        let styled = style(&root, &stylesheet);
+        let layout_root = layout::layout_tree(&styled, viewport());
+        // The box itself is still painted, but none of its children are, even ones in layers.
+        let red = Color { r: 255, g: 0, b: 0, a: 255 };
+        assert_eq!(commands(&*build_display_list(&layout_root)),
+                   vec![(red, (0.0, 0.0, 100.0, 20.0))]);
+    }
+
+    fn rect_parts(r: Rect) -> (f32, f32, f32, f32) {
+        (r.x, r.y, r.width, r.height)
+    }
+
+    #[test]
+    fn object_fit_scales_the_image() {
+        // A 200x100 image in a 100x100 box.
+        let area = Rect { x: 10.0, y: 20.0, width: 100.0, height: 100.0 };
+        assert_eq!(object_fit_size(ObjectFit::Fill, area, 200.0, 100.0), (100.0, 100.0));
+        assert_eq!(object_fit_size(ObjectFit::Contain, area, 200.0, 100.0), (100.0, 50.0));
+        assert_eq!(object_fit_size(ObjectFit::Cover, area, 200.0, 100.0), (200.0, 100.0));
+        assert_eq!(object_fit_size(ObjectFit::None, area, 200.0, 100.0), (200.0, 100.0));
+        assert_eq!(object_fit_size(ObjectFit::ScaleDown, area, 200.0, 100.0), (100.0, 50.0));
+        // `scale-down` never makes an image bigger.
+        assert_eq!(object_fit_size(ObjectFit::ScaleDown, area, 20.0, 10.0), (20.0, 10.0));
+    }
+
+    /// Where the 200x100 image `wide.png` is drawn in a 100x100 `<img>` with the given style.
+    fn image_rect(img_css: &str) -> (f32, f32, f32, f32) {
+        let root = html::parse("<div><img src=\"wide.png\"></div>".to_string());
+        let stylesheet = css::parse(format!("div {{ display: block; }} \
+                                             img {{ display: block; width: 100px; height: 100px; \
+                                             {} }}", img_css));
+        let styled = style(&root, &stylesheet);
+        let images = ImageCache::new(Path::new("examples/images"));
+        let cache = LayoutCache::new();
+        let layout_root = layout::layout_document(&styled, viewport(), &BUILTIN_FONT, None,
+                                                  &images, &cache);
+        build_display_list(&layout_root).iter().filter_map(|command| match *command {
+            DisplayCommand::Image(_, r, _) => Some(rect_parts(r)),
+            _ => None
+        }).next().unwrap()
+    }
+
+    #[test]
+    fn object_position_places_the_image() {
+        // Centered by default.
+        assert_eq!(image_rect("object-fit: contain;"), (0.0, 25.0, 100.0, 50.0));
+        assert_eq!(image_rect("object-fit: contain; object-position: left top;"),
+                   (0.0, 0.0, 100.0, 50.0));
+        assert_eq!(image_rect("object-fit: contain; object-position: 10px 100%;"),
+                   (10.0, 50.0, 100.0, 50.0));
+        // The part of the image that overflows to the left is clipped.
+        assert_eq!(image_rect("object-fit: none; object-position: right;"),
+                   (-100.0, 0.0, 200.0, 100.0));
+    }
+
+    #[test]
+    fn unpainted_pixels_show_the_canvas_background() {
+        let root = html::parse("<div></div>".to_string());
+        let stylesheet = css::parse("div { display: block; width: 10px; height: 10px; \
+                                           background: #ff0000; }".to_string());
+        let styled = style(&root, &stylesheet);
+        let layout_root = layout::layout_tree(&styled, viewport());
+        let black = Color { r: 0, g: 0, b: 0, a: 255 };
+        let bounds = Rect { x: 0.0, y: 0.0, width: 20.0, height: 10.0 };
+        let canvas = paint(&layout_root, bounds, black);
+        assert_eq!(canvas.pixels[0], Color { r: 255, g: 0, b: 0, a: 255 });
+        assert_eq!(canvas.pixels[15], black);
+        assert_eq!(canvas.pixels[20 * 9 + 19], black);
+    }
+}
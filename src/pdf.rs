@@ -11,6 +11,10 @@ fn px_to_pt(value: f32) -> f32 {
 }
 
 
+/// Render a layout tree as a single-page PDF document.
+///
+/// Only solid colors are drawn for now. `DisplayCommand::Image` items, from `<img>` elements and
+/// background images, are left out of the PDF.
 pub fn render(layout_root: &LayoutBox, bounds: Rect, file: &mut File) -> IoResult<()> {
     let display_list = build_display_list(layout_root);
     let mut pdf = try!(Pdf::new(file));
@@ -33,6 +37,8 @@ fn render_item<W: Writer>(item: &DisplayCommand, output: &mut W) -> IoResult<()>
                    color.r, color.g, color.b,
                    rect.x, rect.y, rect.width, rect.height)
         }
+        // TODO: Embed images as XObjects.
+        DisplayCommand::Image(..) => Ok(())
     }
 }
 
@@ -4,110 +4,2228 @@
 //! complicated if I add support for compound selectors.
 
 use dom::{Node, NodeType, ElementData};
-use css::{Stylesheet, Rule, Selector, SimpleSelector, Value, Specificity};
+use css::{Stylesheet, Rule, Selector, SimpleSelector, Declaration, Value, Specificity, Color};
+use css::Value::{Keyword, Length, ColorValue};
+use css::Unit::{Px, Percent, Em, Rem, Vw, Vh, In, Cm, Mm, Pt, Pc, Fr, Deg, Rad};
+use std::f32::consts::PI;
+use std::ascii::AsciiExt; // for `to_ascii_lowercase`
+use std::cmp;
 use std::collections::HashMap;
 
 /// Map from CSS property names to values.
 pub type PropertyMap =  HashMap<String, Value>;
 
+/// Properties whose value is inherited from the parent node if not set on the node itself.
+///
+/// http://www.w3.org/TR/CSS2/propidx.html
+static INHERITED_PROPERTIES: &'static [&'static str] = &[
+    "color", "direction", "font-size", "font-style", "font-weight", "letter-spacing",
+    "line-height", "list-style-type", "overflow-wrap", "pointer-events", "quotes", "tab-size",
+    "text-align", "text-indent", "visibility", "white-space", "word-break", "word-spacing",
+];
+
+/// Every property this engine knows an initial value for. These and their shorthands are the
+/// properties it implements; see `supports`.
+static PROPERTIES: &'static [&'static str] = &[
+    "display", "position", "float", "overflow-x", "overflow-y", "box-sizing", "direction",
+    "width", "height", "top", "right", "bottom", "left",
+    "margin-top", "margin-right", "margin-bottom", "margin-left",
+    "padding-top", "padding-right", "padding-bottom", "padding-left",
+    "border-top-width", "border-right-width", "border-bottom-width", "border-left-width",
+    "border-color", "background", "color", "font-size", "line-height",
+    "outline-width", "outline-style", "outline-color", "row-gap", "column-gap",
+    "text-decoration-line", "text-decoration-style", "text-decoration-color",
+    "resize", "user-select", "font-style", "font-weight", "letter-spacing", "word-spacing",
+    "text-indent", "tab-size", "white-space", "vertical-align", "visibility", "z-index",
+    "pointer-events", "background-image", "background-position", "box-shadow", "transform",
+    "object-fit", "aspect-ratio", "flex-direction", "flex-wrap", "flex-grow", "order",
+    "justify-content", "align-items", "grid-template-columns", "content", "quotes",
+    "counter-reset", "counter-increment", "list-style-type", "word-break", "overflow-wrap",
+    "justify-items", "align-content", "object-position", "background-clip", "content-visibility",
+    "contain-intrinsic-size", "contain",
+];
+
+/// The keywords this engine understands for properties that take one of a fixed set, for
+/// `supports`. Other properties accept any value.
+static PROPERTY_KEYWORDS: &'static [(&'static str, &'static [&'static str])] = &[
+    ("display", &["inline", "block", "inline-block", "inline-flex", "list-item", "table",
+                  "table-row", "table-cell", "flex", "grid", "contents", "none"]),
+    ("position", &["static", "relative", "absolute", "fixed"]),
+    ("float", &["none", "left", "right"]),
+    ("overflow-x", &["visible", "hidden", "scroll", "auto"]),
+    ("overflow-y", &["visible", "hidden", "scroll", "auto"]),
+    ("box-sizing", &["content-box", "border-box"]),
+    ("direction", &["ltr", "rtl"]),
+    ("visibility", &["visible", "hidden", "collapse"]),
+    ("white-space", &["normal", "pre", "pre-wrap", "pre-line"]),
+    ("word-break", &["normal", "break-all"]),
+    ("overflow-wrap", &["normal", "break-word", "anywhere"]),
+    ("flex-direction", &["row", "column"]),
+    ("flex-wrap", &["nowrap", "wrap"]),
+    ("object-fit", &["fill", "contain", "cover", "none", "scale-down"]),
+    ("background-clip", &["border-box", "padding-box", "content-box"]),
+    ("content-visibility", &["visible", "hidden"]),
+];
+
+/// The initial value of a property, or `None` if the property is unknown.
+fn initial_value(name: &str) -> Option<Value> {
+    let keyword = |s: &str| Some(Keyword(s.to_string()));
+    match name {
+        "display" => keyword("inline"),
+        "position" => keyword("static"),
+        "float" => keyword("none"),
+        "overflow-x" | "overflow-y" => keyword("visible"),
+        "box-sizing" => keyword("content-box"),
+        "direction" => keyword("ltr"),
+        "width" | "height" | "top" | "right" | "bottom" | "left" => keyword("auto"),
+        "margin-top" | "margin-right" | "margin-bottom" | "margin-left" |
+        "padding-top" | "padding-right" | "padding-bottom" | "padding-left" |
+        "border-top-width" | "border-right-width" | "border-bottom-width" | "border-left-width"
+            => Some(Length(0.0, Px)),
+        "border-color" | "outline-color" => keyword("currentcolor"),
+        "outline-width" => keyword("medium"),
+        "outline-style" => keyword("none"),
+        "text-decoration-line" => keyword("none"),
+        "text-decoration-style" => keyword("solid"),
+        "text-decoration-color" => keyword("currentcolor"),
+        "row-gap" | "column-gap" => keyword("normal"),
+        "background" => keyword("transparent"),
+        "color" => Some(ColorValue(Color { r: 0, g: 0, b: 0, a: 255 })),
+        "font-size" => Some(Length(DEFAULT_FONT_SIZE, Px)),
+        "line-height" => keyword("normal"),
+        "resize" => keyword("none"),
+        "user-select" => keyword("auto"),
+        "font-style" | "font-weight" | "letter-spacing" | "word-spacing" => keyword("normal"),
+        "text-indent" => Some(Length(0.0, Px)),
+        "tab-size" => Some(Value::Number(8.0)),
+        "white-space" | "word-break" | "overflow-wrap" => keyword("normal"),
+        "vertical-align" => keyword("baseline"),
+        "visibility" => keyword("visible"),
+        "z-index" | "pointer-events" | "aspect-ratio" | "quotes" => keyword("auto"),
+        "background-image" | "box-shadow" | "transform" | "grid-template-columns" |
+        "counter-reset" | "counter-increment" => keyword("none"),
+        "background-position" => Some(Value::List(vec![Length(0.0, Percent),
+                                                       Length(0.0, Percent)])),
+        "object-fit" => keyword("fill"),
+        "object-position" => Some(Value::List(vec![Length(50.0, Percent),
+                                                   Length(50.0, Percent)])),
+        "flex-direction" => keyword("row"),
+        "flex-wrap" => keyword("nowrap"),
+        "flex-grow" | "order" => Some(Value::Number(0.0)),
+        "justify-content" | "align-items" | "align-content" | "content" => keyword("normal"),
+        "justify-items" => keyword("legacy"),
+        "background-clip" => keyword("border-box"),
+        "list-style-type" => keyword("disc"),
+        "content-visibility" => keyword("visible"),
+        "contain-intrinsic-size" | "contain" => keyword("none"),
+        _ => None
+    }
+}
+
+/// Does this engine implement property `name` with `value`? This is the test in `@supports`
+/// conditions.
+///
+/// The known properties are those in `PROPERTIES` and their shorthands. A keyword value must be
+/// one listed in `PROPERTY_KEYWORDS`, if the property (or for a shorthand, its longhands) is
+/// listed there. Custom properties are always supported.
+pub fn supports(name: &str, value: &Value) -> bool {
+    if name.starts_with("--") {
+        return true;
+    }
+    let longhand = match PROPERTIES.iter().find(|&&p| p == name || shorthand(p) == Some(name)) {
+        Some(&longhand) => longhand,
+        None => return false
+    };
+    match (PROPERTY_KEYWORDS.iter().find(|&&(p, _)| p == longhand), value) {
+        (Some(&(_, keywords)), &Keyword(ref keyword)) =>
+            is_css_wide_keyword(&**keyword) || keywords.iter().any(|&k| k == &**keyword),
+        _ => true
+    }
+}
+
+/// The shorthand property that sets `name`, if any.
+fn shorthand(name: &str) -> Option<&'static str> {
+    match name {
+        "margin-top" | "margin-right" | "margin-bottom" | "margin-left" => Some("margin"),
+        "padding-top" | "padding-right" | "padding-bottom" | "padding-left" => Some("padding"),
+        "border-top-width" | "border-right-width" | "border-bottom-width" | "border-left-width"
+            => Some("border-width"),
+        "outline-width" | "outline-style" | "outline-color" => Some("outline"),
+        "text-decoration-line" | "text-decoration-style" | "text-decoration-color"
+            => Some("text-decoration"),
+        "row-gap" | "column-gap" => Some("gap"),
+        "overflow-x" | "overflow-y" => Some("overflow"),
+        "top" | "right" | "bottom" | "left" => Some("inset"),
+        "align-items" | "justify-items" => Some("place-items"),
+        "align-content" | "justify-content" => Some("place-content"),
+        // `border` also sets the width of each side; see `expand_border`.
+        "border-color" => Some("border"),
+        _ => None
+    }
+}
+
 /// A node with associated style data.
 pub struct StyledNode<'a> {
+    /// The DOM node, or for generated content, its originating element.
     pub node: &'a Node,
     pub specified_values: PropertyMap,
     pub children: Vec<StyledNode<'a>>,
+    /// Set if this is a `::before`, `::after`, `::first-letter`, or `::marker` pseudo-element.
+    pub pseudo_element: Option<PseudoElement>,
+    /// The text of a text node inside generated content.
+    pub generated_text: Option<String>,
+}
+
+#[derive(PartialEq, Show)]
+pub enum PseudoElement {
+    Before,
+    After,
+    FirstLetter,
+    Marker,
+}
+
+impl Copy for PseudoElement {}
+
+impl PseudoElement {
+    /// The name used in selectors, like `before` in `p::before`.
+    pub fn name(self) -> &'static str {
+        match self {
+            PseudoElement::Before => "before",
+            PseudoElement::After => "after",
+            PseudoElement::FirstLetter => "first-letter",
+            PseudoElement::Marker => "marker",
+        }
+    }
+}
+
+/// The font size of the root element when no `font-size` is specified, in px.
+pub const DEFAULT_FONT_SIZE: f32 = 16.0;
+
+/// The height of a line with `line-height: normal`, as a multiple of the font size, for fonts
+/// that don't specify their own.
+pub const NORMAL_LINE_HEIGHT: f32 = 1.2;
+
+/// The number of px in an inch unless configured otherwise, as in CSS.
+pub const DEFAULT_PX_PER_INCH: f32 = 96.0;
+
+/// What relative lengths are measured against when converting them to px.
+#[derive(Show)]
+pub struct LengthContext {
+    /// The font size of the root element, for `rem`.
+    pub root_font_size: f32,
+    /// The font size of the element, for `em`.
+    pub font_size: f32,
+    /// The size of the viewport, for `vw` and `vh`.
+    pub viewport_width: f32,
+    pub viewport_height: f32,
+    /// The resolution, for `in`, `cm`, `mm`, `pt`, and `pc`.
+    pub px_per_inch: f32,
+}
+
+impl Copy for LengthContext {}
+
+impl LengthContext {
+    /// Convert relative and physical lengths in a value to px. Other values are unchanged.
+    ///
+    /// `min()`, `max()`, and `clamp()` are evaluated if this leaves all of their arguments in px.
+    pub fn resolve(&self, value: &Value) -> Value {
+        match *value {
+            Length(n, Em) => Length(n * self.font_size, Px),
+            Length(n, Rem) => Length(n * self.root_font_size, Px),
+            Length(n, Vw) => Length(n / 100.0 * self.viewport_width, Px),
+            Length(n, Vh) => Length(n / 100.0 * self.viewport_height, Px),
+            Length(n, In) => Length(n * self.px_per_inch, Px),
+            Length(n, Cm) => Length(n / 2.54 * self.px_per_inch, Px),
+            Length(n, Mm) => Length(n / 25.4 * self.px_per_inch, Px),
+            Length(n, Pt) => Length(n / 72.0 * self.px_per_inch, Px),
+            Length(n, Pc) => Length(n / 6.0 * self.px_per_inch, Px),
+            Value::List(ref values) =>
+                Value::List(values.iter().map(|v| self.resolve(v)).collect()),
+            Value::Function(ref name, ref args) => {
+                let function = Value::Function(name.clone(),
+                                               args.iter().map(|v| self.resolve(v)).collect());
+                if function.is_px() { Length(function.to_px(), Px) } else { function }
+            }
+            _ => value.clone()
+        }
+    }
+
+    /// The used value of `font-size` for the specified `value`, where `self.font_size` is the
+    /// parent's font size. Percentages are relative to the parent's font size.
+    ///
+    /// Returns `None` if `value` isn't a length.
+    pub fn resolve_font_size(&self, value: &Value) -> Option<f32> {
+        match *value {
+            Length(percent, Percent) => Some(self.font_size * percent / 100.0),
+            _ => match self.resolve(value) {
+                Length(size, Px) => Some(size),
+                _ => None
+            }
+        }
+    }
+}
+
+/// The CSS counters in scope, innermost last, for `counter()`.
+type Counters = Vec<(String, i32)>;
+
+#[derive(PartialEq)]
+pub enum Display {
+    Inline,
+    Block,
+    InlineBlock,
+    /// A flex container that is laid out as a single unit in inline flow, like `InlineBlock`.
+    InlineFlex,
+    Table,
+    TableRow,
+    TableCell,
+    Flex,
+    Grid,
+    /// The element generates no box, and its children are laid out in its place.
+    Contents,
+    None,
+}
+
+impl Copy for Display {}
+
+#[derive(PartialEq)]
+pub enum Position {
+    Static,
+    Relative,
+    Absolute,
+    Fixed,
+}
+
+#[derive(PartialEq)]
+pub enum Float {
+    None,
+    Left,
+    Right,
+}
+
+#[derive(PartialEq)]
+pub enum Overflow {
+    Visible,
+    Hidden,
+    Scroll,
+    Auto,
+}
+
+#[derive(PartialEq)]
+pub enum BoxSizing {
+    ContentBox,
+    BorderBox,
+}
+
+/// The box that a box's background is painted in.
+#[derive(PartialEq, Show)]
+pub enum BackgroundClip {
+    BorderBox,
+    PaddingBox,
+    ContentBox,
+}
+
+impl Copy for BackgroundClip {}
+
+#[derive(PartialEq)]
+pub enum Direction {
+    Ltr,
+    Rtl,
+}
+
+impl Copy for Direction {}
+
+#[derive(PartialEq)]
+pub enum Visibility {
+    Visible,
+    Hidden,
+    Collapse,
+}
+
+/// Whether a box's contents are laid out and painted.
+#[derive(PartialEq, Show)]
+pub enum ContentVisibility {
+    Visible,
+    /// Skip the contents, as if the box were empty.
+    Hidden,
+}
+
+impl Copy for ContentVisibility {}
+
+/// The kinds of containment set by the `contain` property.
+#[derive(PartialEq, Show)]
+pub struct Contain {
+    /// The box is sized as if it were empty.
+    pub size: bool,
+    /// The box's contents are laid out independently of the rest of the document, and it is the
+    /// containing block of its absolutely positioned descendants.
+    pub layout: bool,
+    /// The box's contents are clipped to its padding box.
+    pub paint: bool,
+}
+
+impl Copy for Contain {}
+
+#[derive(PartialEq)]
+pub enum WhiteSpace {
+    /// Collapse whitespace, and wrap lines to fit.
+    Normal,
+    /// Keep whitespace, and only break lines at newlines.
+    Pre,
+    /// Keep whitespace, and wrap lines to fit as well as breaking them at newlines.
+    PreWrap,
+    /// Collapse spaces but keep newlines, and wrap lines to fit.
+    PreLine,
+}
+
+impl Copy for WhiteSpace {}
+
+/// Where lines may break within words.
+#[derive(PartialEq, Show)]
+pub enum WordBreak {
+    /// Only between words.
+    Normal,
+    /// Between any two characters.
+    BreakAll,
+}
+
+impl Copy for WordBreak {}
+
+/// Whether a word too long to fit on a line of its own may be broken to avoid overflow.
+#[derive(PartialEq, Show)]
+pub enum OverflowWrap {
+    /// No; the word overflows.
+    Normal,
+    /// Yes, but the min-content width still counts whole words.
+    BreakWord,
+    /// Yes, and the min-content width counts single characters.
+    Anywhere,
+}
+
+impl Copy for OverflowWrap {}
+
+#[derive(PartialEq)]
+pub enum VerticalAlign {
+    Baseline,
+    Top,
+    Middle,
+    Bottom,
+}
+
+impl Copy for VerticalAlign {}
+
+#[derive(PartialEq)]
+pub enum PointerEvents {
+    Auto,
+    None,
+}
+
+/// Which ways the user may resize a box. This engine doesn't act on it; it's for embedders.
+#[derive(PartialEq, Show)]
+pub enum Resize {
+    None,
+    Both,
+    Horizontal,
+    Vertical,
+}
+
+impl Copy for Resize {}
+
+/// Whether the user may select text in a box. This engine doesn't act on it; it's for embedders.
+#[derive(PartialEq, Show)]
+pub enum UserSelect {
+    Auto,
+    None,
+    Text,
+}
+
+impl Copy for UserSelect {}
+
+#[derive(PartialEq)]
+pub enum FontStyle {
+    Normal,
+    Italic,
+    Oblique,
+}
+
+impl Copy for FontStyle {}
+
+/// The font used for a run of text, and the spacing between its glyphs.
+pub struct Font {
+    /// Size in px.
+    pub size: f32,
+    /// Weight from 1 to 1000, where 400 is normal and 700 is bold.
+    pub weight: u32,
+    pub style: FontStyle,
+    /// Extra space after each character in px, from `letter-spacing`.
+    pub letter_spacing: f32,
+    /// Extra space after each space character in px, from `word-spacing`.
+    pub word_spacing: f32,
+}
+
+impl Copy for Font {}
+
+impl Font {
+    /// Should this font be drawn with bold glyphs?
+    pub fn is_bold(&self) -> bool {
+        self.weight >= 600
+    }
+}
+
+/// The lines drawn over text by `text-decoration`.
+#[derive(Show)]
+pub struct TextDecoration {
+    pub underline: bool,
+    pub overline: bool,
+    pub line_through: bool,
+    pub color: Color,
+}
+
+impl Copy for TextDecoration {}
+
+/// How a replaced element's image is scaled to fit its content box.
+#[derive(PartialEq)]
+pub enum ObjectFit {
+    /// Stretch the image to fill the box.
+    Fill,
+    /// Scale the image to fit inside the box, keeping its aspect ratio.
+    Contain,
+    /// Scale the image to cover the whole box, keeping its aspect ratio. The rest is clipped.
+    Cover,
+    /// Draw the image at its natural size. Any part outside the box is clipped.
+    None,
+    /// Like `None`, or like `Contain` if that makes the image smaller.
+    ScaleDown,
+}
+
+impl Copy for ObjectFit {}
+
+#[derive(PartialEq)]
+pub enum JustifyContent {
+    FlexStart,
+    FlexEnd,
+    Center,
+    SpaceBetween,
+    SpaceAround,
+    SpaceEvenly,
+}
+
+#[derive(PartialEq)]
+pub enum FlexDirection {
+    Row,
+    Column,
+}
+
+#[derive(PartialEq)]
+pub enum FlexWrap {
+    NoWrap,
+    Wrap,
+}
+
+#[derive(PartialEq)]
+pub enum AlignItems {
+    Stretch,
+    FlexStart,
+    FlexEnd,
+    Center,
+}
+
+impl Copy for AlignItems {}
+
+/// The stroke drawn around the border box by `outline`.
+#[derive(Show)]
+pub struct Outline {
+    pub width: f32,
+    pub color: Color,
+}
+
+/// The size of a track in `grid-template-columns`.
+#[derive(Show, PartialEq)]
+pub enum TrackSize {
+    /// A width in px.
+    Fixed(f32),
+    /// A share of the space left over by fixed tracks, from the `fr` unit.
+    Fraction(f32),
+}
+
+impl Copy for TrackSize {}
+
+/// One function in a `transform` list.
+#[derive(Show, PartialEq)]
+pub enum TransformFn {
+    /// Move by x and y, in px.
+    Translate(f32, f32),
+    /// Scale by a factor in each direction.
+    Scale(f32, f32),
+    /// Rotate clockwise, by an angle in degrees.
+    Rotate(f32),
+}
+
+impl Copy for TransformFn {}
+
+/// A drop shadow set by `box-shadow: <offset-x> <offset-y> [<blur-radius> [<spread>]] [<color>]`.
+#[derive(Show)]
+pub struct BoxShadow {
+    pub offset_x: f32,
+    pub offset_y: f32,
+    pub blur_radius: f32,
+    pub spread: f32,
+    pub color: Color,
+}
+
+impl<'a> StyledNode<'a> {
+    /// The text of this node, if it is a text node or text inside generated content.
+    pub fn text(&self) -> Option<&str> {
+        if let Some(ref text) = self.generated_text {
+            return Some(&**text);
+        }
+        match self.node.node_type {
+            NodeType::Text(ref text) if self.pseudo_element.is_none() => Some(&**text),
+            _ => None
+        }
+    }
+
+    /// The element this node was created for, or `None` for text and generated content.
+    pub fn element(&self) -> Option<&ElementData> {
+        match self.node.node_type {
+            NodeType::Element(ref elem)
+                if self.pseudo_element.is_none() && self.generated_text.is_none() => Some(elem),
+            _ => None
+        }
+    }
+
+    /// Return the specified value of a property if it exists, otherwise `None`.
+    pub fn value(&self, name: &str) -> Option<Value> {
+        self.specified_values.get(name).map(|v| v.clone())
+    }
+
+    /// Return the specified value of property `name`, or property `fallback_name` if that doesn't
+    /// exist. or value `default` if neither does.
+    pub fn lookup(&self, name: &str, fallback_name: &str, default: &Value) -> Value {
+        self.value(name).unwrap_or_else(|| self.value(fallback_name)
+                        .unwrap_or_else(|| default.clone()))
+    }
+
+    /// Return the computed value of every known property, like `getComputedStyle`.
+    ///
+    /// Unlike `specified_values`, this includes longhands set by a shorthand and the initial
+    /// values of properties that were never set.
+    pub fn computed_values(&self) -> PropertyMap {
+        let mut values = self.specified_values.clone();
+        for &name in PROPERTIES.iter() {
+            if values.contains_key(name) {
+                continue;
+            }
+            let value = match shorthand(name).and_then(|s| self.value(s)) {
+                Some(value) => value,
+                None => initial_value(name).unwrap()
+            };
+            values.insert(name.to_string(), value);
+        }
+        return values;
+    }
+
+    /// The value of the `vertical-align` property (defaults to baseline).
+    pub fn vertical_align(&self) -> VerticalAlign {
+        match self.keyword("vertical-align") {
+            Some(s) => match &*s {
+                "top" => VerticalAlign::Top,
+                "middle" => VerticalAlign::Middle,
+                "bottom" => VerticalAlign::Bottom,
+                _ => VerticalAlign::Baseline
+            },
+            _ => VerticalAlign::Baseline
+        }
+    }
+
+    /// The lines set by `text-decoration-line` and `text-decoration-color` or the
+    /// `text-decoration` shorthand, or `None` if there are no lines.
+    ///
+    /// The color defaults to `color`. All values of `text-decoration-style` are drawn as `solid`.
+    pub fn text_decoration(&self) -> Option<TextDecoration> {
+        let current_color = self.color();
+        let mut decoration = TextDecoration {
+            underline: false,
+            overline: false,
+            line_through: false,
+            color: current_color,
+        };
+        for value in value_list(self.value("text-decoration")).iter() {
+            apply_text_decoration(&mut decoration, value, current_color);
+        }
+        // The longhands are only set if they override the shorthand.
+        if let Some(line) = self.value("text-decoration-line") {
+            decoration.underline = false;
+            decoration.overline = false;
+            decoration.line_through = false;
+            for value in value_list(Some(line)).iter() {
+                apply_text_decoration(&mut decoration, value, current_color);
+            }
+        }
+        if let Some(color) = self.value("text-decoration-color") {
+            apply_text_decoration(&mut decoration, &color, current_color);
+        }
+        if decoration.underline || decoration.overline || decoration.line_through {
+            Some(decoration)
+        } else {
+            None
+        }
+    }
+
+    /// The value of the `object-fit` property (defaults to fill).
+    pub fn object_fit(&self) -> ObjectFit {
+        match self.keyword("object-fit") {
+            Some(s) => match &*s {
+                "contain" => ObjectFit::Contain,
+                "cover" => ObjectFit::Cover,
+                "none" => ObjectFit::None,
+                "scale-down" => ObjectFit::ScaleDown,
+                _ => ObjectFit::Fill
+            },
+            _ => ObjectFit::Fill
+        }
+    }
+
+    /// The font size in px (defaults to 16px).
+    pub fn font_size(&self) -> f32 {
+        match self.value("font-size") {
+            Some(Length(size, Px)) => size,
+            _ => DEFAULT_FONT_SIZE
+        }
+    }
+
+    /// The value of the `font-weight` property, as a number (defaults to 400).
+    ///
+    /// TODO: `bolder` and `lighter` should be relative to the parent's weight.
+    pub fn font_weight(&self) -> u32 {
+        match self.value("font-weight") {
+            Some(Value::Number(n)) if n >= 1.0 && n <= 1000.0 => n as u32,
+            Some(Keyword(ref s)) => match &**s {
+                "bold" | "bolder" => 700,
+                "lighter" => 100,
+                _ => 400
+            },
+            _ => 400
+        }
+    }
+
+    /// The value of the `font-style` property (defaults to normal).
+    pub fn font_style(&self) -> FontStyle {
+        match self.keyword("font-style") {
+            Some(s) => match &*s {
+                "italic" => FontStyle::Italic,
+                "oblique" => FontStyle::Oblique,
+                _ => FontStyle::Normal
+            },
+            _ => FontStyle::Normal
+        }
+    }
+
+    /// The font for text in this node.
+    pub fn font(&self) -> Font {
+        Font {
+            size: self.font_size(),
+            weight: self.font_weight(),
+            style: self.font_style(),
+            letter_spacing: self.spacing("letter-spacing"),
+            word_spacing: self.spacing("word-spacing"),
+        }
+    }
+
+    /// The value of `letter-spacing` or `word-spacing` (named by `property`) in px. `normal`
+    /// (the default) is zero.
+    fn spacing(&self, property: &str) -> f32 {
+        match self.value(property) {
+            Some(Length(spacing, Px)) => spacing,
+            _ => 0.0
+        }
+    }
+
+    /// The value of the `white-space` property (defaults to normal).
+    ///
+    /// TODO: `nowrap`.
+    pub fn white_space(&self) -> WhiteSpace {
+        match self.keyword("white-space") {
+            Some(s) => match &*s {
+                "pre" => WhiteSpace::Pre,
+                "pre-wrap" => WhiteSpace::PreWrap,
+                "pre-line" => WhiteSpace::PreLine,
+                _ => WhiteSpace::Normal
+            },
+            _ => WhiteSpace::Normal
+        }
+    }
+
+    /// The value of the `word-break` property (defaults to normal).
+    pub fn word_break(&self) -> WordBreak {
+        match self.keyword("word-break") {
+            Some(s) => match &*s {
+                "break-all" => WordBreak::BreakAll,
+                _ => WordBreak::Normal
+            },
+            _ => WordBreak::Normal
+        }
+    }
+
+    /// The value of the `overflow-wrap` property (defaults to normal).
+    pub fn overflow_wrap(&self) -> OverflowWrap {
+        match self.keyword("overflow-wrap") {
+            Some(s) => match &*s {
+                "break-word" => OverflowWrap::BreakWord,
+                "anywhere" => OverflowWrap::Anywhere,
+                _ => OverflowWrap::Normal
+            },
+            _ => OverflowWrap::Normal
+        }
+    }
+
+    /// The distance between tab stops in preformatted text, as a number of spaces (defaults
+    /// to 8).
+    ///
+    /// TODO: `tab-size` lengths.
+    pub fn tab_size(&self) -> usize {
+        match self.value("tab-size") {
+            Some(Value::Number(n)) if n >= 0.0 => n as usize,
+            _ => 8
+        }
+    }
+
+    /// The width divided by the height, from `aspect-ratio: <width> [/ <height>]`.
+    ///
+    /// Returns `None` for `aspect-ratio: auto` (the default) or an invalid ratio.
+    pub fn aspect_ratio(&self) -> Option<f32> {
+        let (width, height) = match self.value("aspect-ratio") {
+            Some(Value::Number(width)) => (width, 1.0),
+            Some(Value::List(values)) => {
+                if values.len() != 3 || values[1] != Keyword("/".to_string()) {
+                    return None;
+                }
+                match (&values[0], &values[2]) {
+                    (&Value::Number(width), &Value::Number(height)) => (width, height),
+                    _ => return None
+                }
+            }
+            _ => return None
+        };
+        if width > 0.0 && height > 0.0 { Some(width / height) } else { None }
+    }
+
+    /// The value of the `justify-content` property (defaults to flex-start).
+    pub fn justify_content(&self) -> JustifyContent {
+        match self.keyword("justify-content") {
+            Some(s) => match &*s {
+                "flex-end" | "end" => JustifyContent::FlexEnd,
+                "center" => JustifyContent::Center,
+                "space-between" => JustifyContent::SpaceBetween,
+                "space-around" => JustifyContent::SpaceAround,
+                "space-evenly" => JustifyContent::SpaceEvenly,
+                _ => JustifyContent::FlexStart
+            },
+            _ => JustifyContent::FlexStart
+        }
+    }
+
+    /// The value of the `align-items` property (defaults to stretch).
+    ///
+    /// `baseline` is treated as flex-start.
+    pub fn align_items(&self) -> AlignItems {
+        match self.keyword("align-items") {
+            Some(s) => match &*s {
+                "flex-start" | "start" | "baseline" => AlignItems::FlexStart,
+                "flex-end" | "end" => AlignItems::FlexEnd,
+                "center" => AlignItems::Center,
+                _ => AlignItems::Stretch
+            },
+            _ => AlignItems::Stretch
+        }
+    }
+
+    /// The value of the `flex-direction` property (defaults to row).
+    ///
+    /// TODO: `row-reverse` and `column-reverse`.
+    pub fn flex_direction(&self) -> FlexDirection {
+        match self.keyword("flex-direction") {
+            Some(ref s) if *s == "column" => FlexDirection::Column,
+            _ => FlexDirection::Row
+        }
+    }
+
+    /// The value of the `flex-wrap` property (defaults to nowrap).
+    ///
+    /// TODO: `wrap-reverse`.
+    pub fn flex_wrap(&self) -> FlexWrap {
+        match self.keyword("flex-wrap") {
+            Some(ref s) if *s == "wrap" => FlexWrap::Wrap,
+            _ => FlexWrap::NoWrap
+        }
+    }
+
+    /// The value of the `order` property, used to rearrange flex items (defaults to 0).
+    pub fn order(&self) -> i32 {
+        match self.value("order") {
+            Some(Value::Number(n)) => n as i32,
+            _ => 0
+        }
+    }
+
+    /// The `flex-grow` factor of a flex item (defaults to 0).
+    pub fn flex_grow(&self) -> f32 {
+        match self.value("flex-grow") {
+            Some(Value::Number(n)) if n >= 0.0 => n,
+            _ => 0.0
+        }
+    }
+
+    /// The sizes of the columns in `grid-template-columns`.
+    ///
+    /// Returns an empty list for `grid-template-columns: none` (the default), or if any track
+    /// size is not a length in px or `fr`.
+    pub fn grid_template_columns(&self) -> Vec<TrackSize> {
+        let values = match self.value("grid-template-columns") {
+            Some(Value::List(values)) => values,
+            Some(value) => vec![value],
+            None => return vec![]
+        };
+        let mut tracks = Vec::new();
+        for value in values.iter() {
+            match *value {
+                Length(width, Px) => tracks.push(TrackSize::Fixed(width)),
+                Length(share, Fr) if share >= 0.0 => tracks.push(TrackSize::Fraction(share)),
+                _ => return vec![]
+            }
+        }
+        return tracks;
+    }
+
+    /// The functions in the `transform` property, in the order they were written.
+    ///
+    /// Returns an empty list for `transform: none` (the default), or if any function is invalid.
+    pub fn transform(&self) -> Vec<TransformFn> {
+        let values = match self.value("transform") {
+            Some(Value::List(values)) => values,
+            Some(value) => vec![value],
+            None => return vec![]
+        };
+        let mut functions = Vec::new();
+        for value in values.iter() {
+            match transform_function(value) {
+                Some(function) => functions.push(function),
+                None => return vec![]
+            }
+        }
+        return functions;
+    }
+
+    /// The value of the `color` property (defaults to black).
+    pub fn color(&self) -> Color {
+        match self.value("color") {
+            Some(ColorValue(color)) => color,
+            _ => Color { r: 0, g: 0, b: 0, a: 255 }
+        }
+    }
+
+    /// The value of the `border-color` property, with `currentcolor` resolved to `color`, or
+    /// `None` if it isn't set.
+    pub fn border_color(&self) -> Option<Color> {
+        match self.value("border-color") {
+            Some(ColorValue(color)) => Some(color),
+            Some(Keyword(ref k)) if k.eq_ignore_ascii_case("currentcolor") => Some(self.color()),
+            _ => None
+        }
+    }
+
+    /// The value of the `box-shadow` property, or `None` if there is no shadow.
+    ///
+    /// Only a single outer shadow is supported. The color defaults to `color`.
+    pub fn box_shadow(&self) -> Option<BoxShadow> {
+        let values = match self.value("box-shadow") {
+            Some(Value::List(values)) => values,
+            _ => return None
+        };
+        let mut lengths = Vec::new();
+        let mut color = None;
+        for value in values.iter() {
+            match *value {
+                Length(length, Px) => lengths.push(length),
+                Value::Number(n) if n == 0.0 => lengths.push(0.0),
+                ColorValue(c) => color = Some(c),
+                _ => return None
+            }
+        }
+        if lengths.len() < 2 || lengths.len() > 4 {
+            return None;
+        }
+        Some(BoxShadow {
+            offset_x: lengths[0],
+            offset_y: lengths[1],
+            blur_radius: lengths.get(2).map_or(0.0, |&l| l),
+            spread: lengths.get(3).map_or(0.0, |&l| l),
+            color: color.unwrap_or(self.color()),
+        })
+    }
+
+    /// The outline set by `outline-width`, `outline-style`, and `outline-color` or the `outline`
+    /// shorthand, or `None` if the outline style is `none`.
+    ///
+    /// All visible styles are drawn as `solid`.
+    pub fn outline(&self) -> Option<Outline> {
+        let (mut width, mut style, mut color) = (None, None, None);
+        let parts = match self.value("outline") {
+            Some(Value::List(values)) => values,
+            Some(value) => vec![value],
+            None => vec![]
+        };
+        let longhands = [self.value("outline-width"), self.value("outline-style"),
+                         self.value("outline-color")];
+        // The longhands are only set if they override the shorthand.
+        for value in parts.iter().chain(longhands.iter().filter_map(|v| v.as_ref())) {
+            match *value {
+                Length(length, Px) => width = Some(length),
+                Value::Number(n) if n == 0.0 => width = Some(0.0),
+                ColorValue(c) => color = Some(c),
+                Keyword(ref k) => match &**k {
+                    "thin" => width = Some(1.0),
+                    "medium" => width = Some(3.0),
+                    "thick" => width = Some(5.0),
+                    "currentcolor" => color = None,
+                    _ => style = Some(k.clone())
+                },
+                _ => {}
+            }
+        }
+        match style {
+            Some(ref s) if *s != "none" && *s != "hidden" => {}
+            _ => return None
+        }
+        Some(Outline {
+            width: width.unwrap_or(3.0),
+            color: color.unwrap_or(self.color()),
+        })
+    }
+
+    /// The URL of the `background-image` property, or `None` if there is no image.
+    pub fn background_image(&self) -> Option<String> {
+        match self.value("background-image") {
+            Some(Value::Function(ref name, ref args)) if *name == "url" && args.len() == 1 => {
+                match args[0] {
+                    Value::Str(ref url) => Some(url.clone()),
+                    _ => None
+                }
+            }
+            _ => None
+        }
+    }
+
+    /// The horizontal and vertical offsets of the `background-position` property, as either
+    /// pixel lengths or percentages (defaults to `0% 0%`).
+    ///
+    /// Keywords are converted to percentages. If only one value is given, the other is `center`.
+    pub fn background_position(&self) -> (Value, Value) {
+        self.position("background-position", 0.0)
+    }
+
+    /// The horizontal and vertical offsets of the `object-position` property, like
+    /// `background_position` (defaults to `50% 50%`).
+    pub fn object_position(&self) -> (Value, Value) {
+        self.position("object-position", 50.0)
+    }
+
+    /// The offsets of a position property like `background-position`, or `default` percent in
+    /// both directions if it isn't set.
+    fn position(&self, name: &str, default: f32) -> (Value, Value) {
+        let values = match self.value(name) {
+            Some(Value::List(values)) => values,
+            Some(value) => vec![value],
+            None => vec![]
+        };
+        let is_vertical = |&: value: &Value| match *value {
+            Keyword(ref k) => *k == "top" || *k == "bottom",
+            _ => false
+        };
+        let is_horizontal = |&: value: &Value| match *value {
+            Keyword(ref k) => *k == "left" || *k == "right",
+            _ => false
+        };
+        let center = Keyword("center".to_string());
+        let (x, y) = match values.len() {
+            0 => return (Length(default, Percent), Length(default, Percent)),
+            1 if is_vertical(&values[0]) => (center, values[0].clone()),
+            1 => (values[0].clone(), center),
+            // Keywords may be given in either order, like `top left`.
+            _ if is_vertical(&values[0]) || is_horizontal(&values[1]) =>
+                (values[1].clone(), values[0].clone()),
+            _ => (values[0].clone(), values[1].clone()),
+        };
+        (background_offset(x), background_offset(y))
+    }
+
+    /// Return the keyword value of a property, if it is set to a keyword.
+    fn keyword(&self, name: &str) -> Option<String> {
+        match self.value(name) {
+            Some(Value::Keyword(s)) => Some(s),
+            _ => None
+        }
+    }
+
+    /// The value of the `display` property (defaults to inline).
+    pub fn display(&self) -> Display {
+        match self.keyword("display") {
+            Some(s) => match &*s {
+                // List items are blocks that start with a marker; see `list_marker`.
+                "block" | "list-item" => Display::Block,
+                "inline-block" => Display::InlineBlock,
+                "inline-flex" => Display::InlineFlex,
+                "table" => Display::Table,
+                "table-row" => Display::TableRow,
+                "table-cell" => Display::TableCell,
+                "flex" => Display::Flex,
+                "grid" => Display::Grid,
+                "contents" => Display::Contents,
+                "none" => Display::None,
+                _ => Display::Inline
+            },
+            _ => Display::Inline
+        }
+    }
+
+    /// The value of the `position` property (defaults to static).
+    pub fn position(&self) -> Position {
+        match self.keyword("position") {
+            Some(s) => match &*s {
+                "relative" => Position::Relative,
+                "absolute" => Position::Absolute,
+                "fixed" => Position::Fixed,
+                _ => Position::Static
+            },
+            _ => Position::Static
+        }
+    }
+
+    /// The value of the `pointer-events` property (defaults to auto).
+    pub fn pointer_events(&self) -> PointerEvents {
+        match self.keyword("pointer-events") {
+            Some(ref s) if *s == "none" => PointerEvents::None,
+            _ => PointerEvents::Auto
+        }
+    }
+
+    /// The value of the `resize` property (defaults to none).
+    pub fn resize(&self) -> Resize {
+        match self.keyword("resize") {
+            Some(s) => match &*s {
+                "both" => Resize::Both,
+                "horizontal" => Resize::Horizontal,
+                "vertical" => Resize::Vertical,
+                _ => Resize::None
+            },
+            _ => Resize::None
+        }
+    }
+
+    /// The value of the `user-select` property (defaults to auto).
+    pub fn user_select(&self) -> UserSelect {
+        match self.keyword("user-select") {
+            Some(s) => match &*s {
+                "none" => UserSelect::None,
+                "text" => UserSelect::Text,
+                _ => UserSelect::Auto
+            },
+            _ => UserSelect::Auto
+        }
+    }
+
+    /// The value of the `visibility` property (defaults to visible).
+    pub fn visibility(&self) -> Visibility {
+        match self.keyword("visibility") {
+            Some(s) => match &*s {
+                "hidden" => Visibility::Hidden,
+                "collapse" => Visibility::Collapse,
+                _ => Visibility::Visible
+            },
+            _ => Visibility::Visible
+        }
+    }
+
+    /// The value of the `content-visibility` property (defaults to visible).
+    ///
+    /// TODO: `auto`, which is treated like `visible`.
+    pub fn content_visibility(&self) -> ContentVisibility {
+        match self.keyword("content-visibility") {
+            Some(s) => match &*s {
+                "hidden" => ContentVisibility::Hidden,
+                _ => ContentVisibility::Visible
+            },
+            _ => ContentVisibility::Visible
+        }
+    }
+
+    /// The value of the `contain` property (defaults to none). `strict` means `size layout paint`,
+    /// and `content` means `layout paint`. Style containment is ignored.
+    pub fn contain(&self) -> Contain {
+        let mut contain = Contain { size: false, layout: false, paint: false };
+        for value in value_list(self.value("contain")).iter() {
+            if let Keyword(ref k) = *value {
+                match &**k {
+                    "strict" => {
+                        contain.size = true;
+                        contain.layout = true;
+                        contain.paint = true;
+                    }
+                    "content" => {
+                        contain.layout = true;
+                        contain.paint = true;
+                    }
+                    "size" => contain.size = true,
+                    "layout" => contain.layout = true,
+                    "paint" => contain.paint = true,
+                    _ => {}
+                }
+            }
+        }
+        contain
+    }
+
+    /// The width and height in px set by the `contain-intrinsic-size` property, or `None` if it
+    /// is `none` (the default). A single length sets both. The `auto` keyword is ignored.
+    pub fn contain_intrinsic_size(&self) -> Option<(f32, f32)> {
+        let lengths: Vec<f32> = value_list(self.value("contain-intrinsic-size")).iter()
+                                    .filter_map(|value| match *value {
+            Length(length, Px) => Some(length),
+            Value::Number(n) if n == 0.0 => Some(0.0),
+            _ => None
+        }).collect();
+        match lengths.len() {
+            1 => Some((lengths[0], lengths[0])),
+            2 => Some((lengths[0], lengths[1])),
+            _ => None
+        }
+    }
+
+    /// The value of the `z-index` property, or `None` for `auto` (the default).
+    pub fn z_index(&self) -> Option<i32> {
+        match self.value("z-index") {
+            Some(Value::Number(n)) => Some(n as i32),
+            _ => None
+        }
+    }
+
+    /// The value of the `float` property (defaults to none).
+    pub fn float(&self) -> Float {
+        match self.keyword("float") {
+            Some(s) => match &*s {
+                "left" => Float::Left,
+                "right" => Float::Right,
+                _ => Float::None
+            },
+            _ => Float::None
+        }
+    }
+
+    /// The computed value of the `overflow-x` property, or of the `overflow` shorthand (defaults
+    /// to visible).
+    pub fn overflow_x(&self) -> Overflow {
+        self.overflow_axis("overflow-x", "overflow-y")
+    }
+
+    /// The computed value of the `overflow-y` property, or of the `overflow` shorthand (defaults
+    /// to visible).
+    pub fn overflow_y(&self) -> Overflow {
+        self.overflow_axis("overflow-y", "overflow-x")
+    }
+
+    /// The computed overflow on one axis. `visible` computes to `auto` if the other axis is not
+    /// `visible`, since content can't spill out of a box that clips on the other axis.
+    fn overflow_axis(&self, name: &str, other_name: &str) -> Overflow {
+        match (self.specified_overflow(name), self.specified_overflow(other_name)) {
+            (Overflow::Visible, Overflow::Visible) => Overflow::Visible,
+            (Overflow::Visible, _) => Overflow::Auto,
+            (overflow, _) => overflow
+        }
+    }
+
+    fn specified_overflow(&self, name: &str) -> Overflow {
+        match self.lookup(name, "overflow", &Keyword("visible".to_string())) {
+            Keyword(s) => match &*s {
+                "hidden" => Overflow::Hidden,
+                "scroll" => Overflow::Scroll,
+                "auto" => Overflow::Auto,
+                _ => Overflow::Visible
+            },
+            _ => Overflow::Visible
+        }
+    }
+
+    /// The value of the `box-sizing` property (defaults to content-box).
+    pub fn box_sizing(&self) -> BoxSizing {
+        match self.keyword("box-sizing") {
+            Some(s) => match &*s {
+                "border-box" => BoxSizing::BorderBox,
+                _ => BoxSizing::ContentBox
+            },
+            _ => BoxSizing::ContentBox
+        }
+    }
+
+    /// The value of the `background-clip` property (defaults to border-box).
+    pub fn background_clip(&self) -> BackgroundClip {
+        match self.keyword("background-clip") {
+            Some(s) => match &*s {
+                "padding-box" => BackgroundClip::PaddingBox,
+                "content-box" => BackgroundClip::ContentBox,
+                _ => BackgroundClip::BorderBox
+            },
+            _ => BackgroundClip::BorderBox
+        }
+    }
+
+    /// The value of the `direction` property (defaults to ltr).
+    pub fn direction(&self) -> Direction {
+        match self.keyword("direction") {
+            Some(s) => match &*s {
+                "rtl" => Direction::Rtl,
+                _ => Direction::Ltr
+            },
+            _ => Direction::Ltr
+        }
+    }
+}
+
+/// Apply a stylesheet to an entire DOM tree, returning a StyledNode tree.
+///
+/// This finds the specified values, including inherited values. Initial values are left out so
+/// that longhand lookups can still fall back to shorthands; see `StyledNode::computed_values`.
+///
+/// `root_font_size` is the font size of the root element if the stylesheet doesn't set one, like
+/// a user's preferred text size. It is also the base for `em` and `rem` in the root's styles.
+/// `viewport` is the width and height of the viewport, for `vw` and `vh`. `px_per_inch` converts
+/// physical units like `in` and `pt` to px.
+pub fn style_tree<'a>(root: &'a Node, stylesheet: &'a Stylesheet, root_font_size: f32,
+                      viewport: (f32, f32), px_per_inch: f32) -> StyledNode<'a> {
+    let (viewport_width, viewport_height) = viewport;
+    let context = LengthContext {
+        root_font_size: root_font_size,
+        font_size: root_font_size,
+        viewport_width: viewport_width,
+        viewport_height: viewport_height,
+        px_per_inch: px_per_inch,
+    };
+    style_subtree(root, stylesheet, None, context, &mut Vec::new(), &mut 0)
+}
+
+/// Style a node and its descendants, in document order so that `counters` and `quote_depth` (the
+/// number of open quotes generated so far but not yet closed) can be kept up to date.
+///
+/// `rem`, `vw`, and `vh` lengths are resolved using `context`.
+fn style_subtree<'a>(node: &'a Node, stylesheet: &'a Stylesheet,
+                     parent_values: Option<&PropertyMap>, context: LengthContext,
+                     counters: &mut Counters, quote_depth: &mut usize) -> StyledNode<'a> {
+    let mut values = match node.node_type {
+        NodeType::Element(ref elem) => specified_values(elem, stylesheet, None),
+        NodeType::Text(_) => HashMap::new()
+    };
+    cascade_from_parent(&mut values, parent_values, context);
+
+    // Below the root, `rem` is relative to the root element's font size.
+    let context = match parent_values {
+        None => LengthContext {
+            root_font_size: values.get("font-size").map_or(context.root_font_size,
+                                                           |size| size.to_px()),
+            ..context
+        },
+        Some(_) => context
+    };
+
+    let mut children = Vec::new();
+    if let NodeType::Element(ref elem) = node.node_type {
+        update_counters(&values, counters);
+        // Counters created inside this element go out of scope at its end.
+        let scope = counters.len();
+        let marker = list_marker(node, elem, stylesheet, &values, context, counters);
+        children.extend(marker.into_iter());
+        let before = generated_content(node, elem, stylesheet, &values, PseudoElement::Before,
+                                       context, counters, quote_depth);
+        children.extend(before.into_iter());
+        for child in node.children.iter() {
+            children.push(style_subtree(child, stylesheet, Some(&values), context, counters,
+                                        quote_depth));
+        }
+        split_first_letter(&mut children, node, elem, stylesheet, &values, context);
+        let after = generated_content(node, elem, stylesheet, &values, PseudoElement::After,
+                                      context, counters, quote_depth);
+        children.extend(after.into_iter());
+        counters.truncate(scope);
+    }
+
+    StyledNode {
+        node: node,
+        specified_values: values,
+        children: children,
+        pseudo_element: None,
+        generated_text: None,
+    }
+}
+
+/// Finish the specified values of a node, given the values of its parent.
+fn cascade_from_parent(values: &mut PropertyMap, parent_values: Option<&PropertyMap>,
+                       context: LengthContext) {
+    resolve_variables(values, parent_values);
+    resolve_css_wide_keywords(values, parent_values);
+    if let Some(parent_values) = parent_values {
+        inherit(values, parent_values);
+    }
+    resolve_lengths(values, parent_values, context);
+}
+
+/// Convert relative lengths to px, and set `font-size` to a length in px.
+///
+/// Relative lengths in `font-size` itself are relative to the parent's font size, or to
+/// `context.root_font_size` for the root.
+fn resolve_lengths(values: &mut PropertyMap, parent_values: Option<&PropertyMap>,
+                   context: LengthContext) {
+    let parent_font_size = parent_values.and_then(|parent| parent.get("font-size"))
+                                        .map_or(context.root_font_size, |size| size.to_px());
+    let parent_context = LengthContext { font_size: parent_font_size, ..context };
+    let font_size = match values.get("font-size") {
+        Some(value) => parent_context.resolve_font_size(value).unwrap_or(parent_font_size),
+        None => parent_font_size
+    };
+    values.insert("font-size".to_string(), Length(font_size, Px));
+
+    let context = LengthContext { font_size: font_size, ..context };
+    let resolved: Vec<(String, Value)> = values.iter()
+        // Custom properties are resolved where they are used, by `var()`.
+        .filter(|&(name, _)| !is_custom_property(&**name))
+        .map(|(name, value)| (name.clone(), context.resolve(value)))
+        .collect();
+    for (name, value) in resolved.into_iter() {
+        values.insert(name, value);
+    }
 }
 
-#[derive(PartialEq)]
-pub enum Display {
-    Inline,
-    Block,
-    None,
+/// Style the `::before` or `::after` pseudo-element of `elem`, if its `content` property
+/// generates any text.
+///
+/// http://www.w3.org/TR/CSS2/generate.html
+///
+/// The pseudo-element holds a single text node with the generated text.
+fn generated_content<'a>(node: &'a Node, elem: &ElementData, stylesheet: &'a Stylesheet,
+                         parent_values: &PropertyMap, pseudo: PseudoElement,
+                         context: LengthContext, counters: &mut Counters,
+                         quote_depth: &mut usize) -> Option<StyledNode<'a>> {
+    let mut values = specified_values(elem, stylesheet, Some(pseudo));
+    if !values.contains_key("content") {
+        return None;
+    }
+    cascade_from_parent(&mut values, Some(parent_values), context);
+    update_counters(&values, counters);
+    let text = match values.get("content") {
+        Some(content) => match content_text(content, elem, values.get("quotes"), counters,
+                                            quote_depth) {
+            Some(text) => text,
+            None => return None
+        },
+        None => return None
+    };
+
+    let mut text_values = HashMap::new();
+    cascade_from_parent(&mut text_values, Some(&values), context);
+    let text_node = StyledNode {
+        node: node,
+        specified_values: text_values,
+        children: vec![],
+        pseudo_element: None,
+        generated_text: Some(text),
+    };
+    Some(StyledNode {
+        node: node,
+        specified_values: values,
+        children: vec![text_node],
+        pseudo_element: Some(pseudo),
+        generated_text: None,
+    })
 }
 
-impl<'a> StyledNode<'a> {
-    /// Return the specified value of a property if it exists, otherwise `None`.
-    pub fn value(&self, name: &str) -> Option<Value> {
-        self.specified_values.get(name).map(|v| v.clone())
+/// Style the `::marker` pseudo-element of `elem`, if it is a list item with a `list-style-type`
+/// other than `none`.
+///
+/// http://www.w3.org/TR/CSS2/generate.html#lists
+///
+/// The marker holds a single text node, with a bullet or the value of the `list-item` counter
+/// in the style given by `list-style-type`, followed by a space.
+///
+/// TODO: `list-style-position: outside` (the default), which puts the marker outside the
+/// principal box instead of at the start of its first line. Also `list-style-image`.
+fn list_marker<'a>(node: &'a Node, elem: &ElementData, stylesheet: &'a Stylesheet,
+                   parent_values: &PropertyMap, context: LengthContext, counters: &Counters)
+                   -> Option<StyledNode<'a>> {
+    if !is_list_item(parent_values) {
+        return None;
     }
+    let style_type = match parent_values.get("list-style-type") {
+        Some(&Keyword(ref keyword)) => keyword.clone(),
+        _ => "disc".to_string()
+    };
+    let text = match &*style_type {
+        "none" => return None,
+        "disc" | "circle" | "square" => format!("{} ", counter_text(0, &*style_type)),
+        _ => format!("{}. ", counter_text(counter_value(counters, "list-item"), &*style_type))
+    };
 
-    /// Return the specified value of property `name`, or property `fallback_name` if that doesn't
-    /// exist. or value `default` if neither does.
-    pub fn lookup(&self, name: &str, fallback_name: &str, default: &Value) -> Value {
-        self.value(name).unwrap_or_else(|| self.value(fallback_name)
-                        .unwrap_or_else(|| default.clone()))
+    let mut values = specified_values(elem, stylesheet, Some(PseudoElement::Marker));
+    cascade_from_parent(&mut values, Some(parent_values), context);
+    let mut text_values = HashMap::new();
+    cascade_from_parent(&mut text_values, Some(&values), context);
+    let text_node = StyledNode {
+        node: node,
+        specified_values: text_values,
+        children: vec![],
+        pseudo_element: None,
+        generated_text: Some(text),
+    };
+    Some(StyledNode {
+        node: node,
+        specified_values: values,
+        children: vec![text_node],
+        pseudo_element: Some(PseudoElement::Marker),
+        generated_text: None,
+    })
+}
+
+/// Does an element with these specified values have `display: list-item`?
+fn is_list_item(values: &PropertyMap) -> bool {
+    match values.get("display") {
+        Some(&Keyword(ref display)) => &**display == "list-item",
+        _ => false
     }
+}
 
-    /// The value of the `display` property (defaults to inline).
-    pub fn display(&self) -> Display {
-        match self.value("display") {
-            Some(Value::Keyword(s)) => match &*s {
-                "block" => Display::Block,
-                "none" => Display::None,
-                _ => Display::Inline
-            },
-            _ => Display::Inline
+/// Counter value `n` written in a `list-style-type` style, like `iv` for `lower-roman`. The
+/// bullet styles ignore `n`.
+///
+/// Alphabetic styles go `a` to `z`, then `aa`, `ab`, and so on. Values these styles can't show,
+/// like zero, and unknown styles are written in decimal.
+fn counter_text(n: i32, style_type: &str) -> String {
+    match style_type {
+        "disc" => "\u{2022}".to_string(),
+        "circle" => "\u{25E6}".to_string(),
+        "square" => "\u{25AA}".to_string(),
+        "lower-alpha" | "lower-latin" if n > 0 => alphabetic(n, 'a'),
+        "upper-alpha" | "upper-latin" if n > 0 => alphabetic(n, 'A'),
+        "lower-roman" if n > 0 && n < 4000 => roman(n).to_ascii_lowercase(),
+        "upper-roman" if n > 0 && n < 4000 => roman(n),
+        _ => n.to_string()
+    }
+}
+
+/// Write a positive number in bijective base 26, using the letters from `first`.
+fn alphabetic(mut n: i32, first: char) -> String {
+    let mut letters = Vec::new();
+    while n > 0 {
+        n = n - 1;
+        letters.push((first as u8 + (n % 26) as u8) as char);
+        n = n / 26;
+    }
+    letters.into_iter().rev().collect()
+}
+
+/// Write a number from 1 to 3999 in upper-case Roman numerals.
+fn roman(mut n: i32) -> String {
+    let numerals = [(1000, "M"), (900, "CM"), (500, "D"), (400, "CD"), (100, "C"), (90, "XC"),
+                    (50, "L"), (40, "XL"), (10, "X"), (9, "IX"), (5, "V"), (4, "IV"), (1, "I")];
+    let mut text = String::new();
+    for &(value, numeral) in numerals.iter() {
+        while n >= value {
+            text.push_str(numeral);
+            n = n - value;
         }
     }
+    text
 }
 
-/// Apply a stylesheet to an entire DOM tree, returning a StyledNode tree.
+/// Move the first letter of an element's text into a `::first-letter` pseudo-element, if any
+/// rules apply to it. `children` are the styled children of `elem`.
 ///
-/// This finds only the specified values at the moment. Eventually it should be extended to find the
-/// computed values too, including inherited values.
-pub fn style_tree<'a>(root: &'a Node, stylesheet: &'a Stylesheet) -> StyledNode<'a> {
-    StyledNode {
-        node: root,
-        specified_values: match root.node_type {
-            NodeType::Element(ref elem) => specified_values(elem, stylesheet),
-            NodeType::Text(_) => HashMap::new()
+/// http://www.w3.org/TR/CSS2/selector.html#first-letter
+///
+/// Like generated content, the pseudo-element holds a single text node with the letter, and
+/// any whitespace before it. The rest of the text stays in the original text node.
+///
+/// TODO: `::first-line`, punctuation before the letter, and first letters inside descendants
+/// or generated content.
+fn split_first_letter<'a>(children: &mut Vec<StyledNode<'a>>, node: &'a Node,
+                          elem: &ElementData, stylesheet: &'a Stylesheet,
+                          parent_values: &PropertyMap, context: LengthContext) {
+    // Only use text that comes before any other content of the element.
+    let first_content = children.iter().position(|child| {
+        child.text().map_or(true, |text| !text.trim().is_empty())
+    });
+    let index = match first_content {
+        Some(i) if children[i].pseudo_element.is_none() && children[i].text().is_some() => i,
+        _ => return
+    };
+    let mut values = specified_values(elem, stylesheet, Some(PseudoElement::FirstLetter));
+    if values.is_empty() {
+        return;
+    }
+    cascade_from_parent(&mut values, Some(parent_values), context);
+
+    let text = children[index].text().unwrap().to_string();
+    let split = text.char_indices().find(|&(_, c)| !c.is_whitespace())
+                                   .map(|(i, c)| i + c.len_utf8()).unwrap();
+    let mut letter_values = HashMap::new();
+    cascade_from_parent(&mut letter_values, Some(&values), context);
+    let letter_node = StyledNode {
+        node: node,
+        specified_values: letter_values,
+        children: vec![],
+        pseudo_element: None,
+        generated_text: Some(text[.. split].to_string()),
+    };
+    let first_letter = StyledNode {
+        node: node,
+        specified_values: values,
+        children: vec![letter_node],
+        pseudo_element: Some(PseudoElement::FirstLetter),
+        generated_text: None,
+    };
+
+    let mut rest = children.remove(index);
+    children.insert(index, first_letter);
+    if split < text.len() {
+        rest.generated_text = Some(text[split ..].to_string());
+        children.insert(index + 1, rest);
+    }
+}
+
+/// The text generated by a `content` value on a pseudo-element of `elem`, or `None` if it
+/// generates nothing.
+///
+/// Supports strings, `counter(<name>)` and `counter(<name>, <list-style-type>)`, `attr(<name>)`,
+/// quotes, and lists of them.
+///
+/// `open-quote` and `close-quote` use the pair in `quotes` for the current `quote_depth`, and
+/// move it up or down a level.
+fn content_text(content: &Value, elem: &ElementData, quotes: Option<&Value>,
+                counters: &Counters, quote_depth: &mut usize) -> Option<String> {
+    let items = match *content {
+        Value::List(ref items) => items.clone(),
+        _ => vec![content.clone()]
+    };
+    let mut text = String::new();
+    for item in items.iter() {
+        match *item {
+            Value::Str(ref s) => text.push_str(&**s),
+            Value::Function(ref function, ref args) if &**function == "counter" => {
+                let name = match args.first() {
+                    Some(&Keyword(ref name)) => name,
+                    _ => return None
+                };
+                let style_type = match args.get(1) {
+                    Some(&Keyword(ref style_type)) => &**style_type,
+                    _ => "decimal"
+                };
+                text.push_str(&*counter_text(counter_value(counters, &**name), style_type));
+            }
+            Value::Function(ref function, ref args) if &**function == "attr" => {
+                // A missing attribute is the same as an empty one.
+                let name = match args.first() {
+                    Some(&Keyword(ref name)) => name.to_ascii_lowercase(),
+                    _ => return None
+                };
+                if let Some(value) = elem.attributes.get(&name) {
+                    text.push_str(&**value);
+                }
+            }
+            Keyword(ref keyword) if &**keyword == "open-quote" => {
+                if let Some((open, _)) = quote_pair(quotes, *quote_depth) {
+                    text.push_str(&*open);
+                }
+                *quote_depth = *quote_depth + 1;
+            }
+            Keyword(ref keyword) if &**keyword == "close-quote" => {
+                // A close quote with no open quote to match generates nothing.
+                if *quote_depth > 0 {
+                    *quote_depth = *quote_depth - 1;
+                    if let Some((_, close)) = quote_pair(quotes, *quote_depth) {
+                        text.push_str(&*close);
+                    }
+                }
+            }
+            Keyword(ref keyword) if &**keyword == "no-open-quote" => {
+                *quote_depth = *quote_depth + 1;
+            }
+            Keyword(ref keyword) if &**keyword == "no-close-quote" => {
+                if *quote_depth > 0 {
+                    *quote_depth = *quote_depth - 1;
+                }
+            }
+            // This includes `normal` and `none`.
+            _ => return None
+        }
+    }
+    Some(text)
+}
+
+/// The open and close quotes at nesting level `depth`, from a `quotes` value like
+/// `"«" "»" "‹" "›"`. Levels deeper than the list use its last pair. Returns `None` for
+/// `quotes: none`.
+///
+/// If `quotes` isn't set, the pairs are `"` `"` and then `'` `'`.
+fn quote_pair(quotes: Option<&Value>, depth: usize) -> Option<(String, String)> {
+    let strings: Vec<String> = match quotes {
+        Some(&Value::List(ref items)) => items.iter().filter_map(|item| match *item {
+            Value::Str(ref s) => Some(s.clone()),
+            _ => None
+        }).collect(),
+        Some(&Keyword(ref keyword)) if &**keyword == "none" => return None,
+        _ => vec!["\"".to_string(), "\"".to_string(), "'".to_string(), "'".to_string()]
+    };
+    if strings.len() < 2 {
+        return None;
+    }
+    let i = 2 * cmp::min(depth, strings.len() / 2 - 1);
+    Some((strings[i].clone(), strings[i + 1].clone()))
+}
+
+/// The items of a space-separated list, or a list of the single value if it isn't one.
+fn value_list(value: Option<Value>) -> Vec<Value> {
+    match value {
+        Some(Value::List(values)) => values,
+        Some(value) => vec![value],
+        None => vec![]
+    }
+}
+
+/// Apply one part of a `text-decoration` value: a line, a color, or `none`. Styles like `dotted`
+/// are ignored.
+fn apply_text_decoration(decoration: &mut TextDecoration, value: &Value, current_color: Color) {
+    match *value {
+        ColorValue(color) => decoration.color = color,
+        Keyword(ref k) => match &**k {
+            "underline" => decoration.underline = true,
+            "overline" => decoration.overline = true,
+            "line-through" => decoration.line_through = true,
+            "currentcolor" => decoration.color = current_color,
+            "none" => {
+                decoration.underline = false;
+                decoration.overline = false;
+                decoration.line_through = false;
+            }
+            _ => {}
+        },
+        _ => {}
+    }
+}
+
+/// Convert one `background-position` or `object-position` component to a px length or a
+/// percentage.
+fn background_offset(value: Value) -> Value {
+    match value {
+        Keyword(ref k) => Length(match &**k {
+            "right" | "bottom" => 100.0,
+            "center" => 50.0,
+            _ => 0.0
+        }, Percent),
+        Value::Number(_) => Length(0.0, Px),
+        Length(length, Px) => Length(length, Px),
+        Length(percent, Percent) => Length(percent, Percent),
+        _ => Length(0.0, Percent)
+    }
+}
+
+/// Parse one function like `translate(10px, 0)` from a `transform` list.
+fn transform_function(value: &Value) -> Option<TransformFn> {
+    let (name, args) = match *value {
+        Value::Function(ref name, ref args) => (&**name, args),
+        _ => return None
+    };
+    // Translations can't use percentages yet, so lengths must be in px. Zero may be unitless.
+    let length = |&: value: &Value| match *value {
+        Length(l, Px) => Some(l),
+        Value::Number(n) if n == 0.0 => Some(0.0),
+        _ => None
+    };
+    let number = |&: value: &Value| match *value {
+        Value::Number(n) => Some(n),
+        _ => None
+    };
+    let angle = |&: value: &Value| match *value {
+        Length(a, Deg) => Some(a),
+        Length(a, Rad) => Some(a * 180.0 / PI),
+        Value::Number(n) if n == 0.0 => Some(0.0),
+        _ => None
+    };
+    match (name, args.len()) {
+        ("translate", 1) => length(&args[0]).map(|x| TransformFn::Translate(x, 0.0)),
+        ("translate", 2) => match (length(&args[0]), length(&args[1])) {
+            (Some(x), Some(y)) => Some(TransformFn::Translate(x, y)),
+            _ => None
+        },
+        ("translatex", 1) => length(&args[0]).map(|x| TransformFn::Translate(x, 0.0)),
+        ("translatey", 1) => length(&args[0]).map(|y| TransformFn::Translate(0.0, y)),
+        ("scale", 1) => number(&args[0]).map(|s| TransformFn::Scale(s, s)),
+        ("scale", 2) => match (number(&args[0]), number(&args[1])) {
+            (Some(x), Some(y)) => Some(TransformFn::Scale(x, y)),
+            _ => None
         },
-        children: root.children.iter().map(|child| style_tree(child, stylesheet)).collect(),
+        ("scalex", 1) => number(&args[0]).map(|x| TransformFn::Scale(x, 1.0)),
+        ("scaley", 1) => number(&args[0]).map(|y| TransformFn::Scale(1.0, y)),
+        ("rotate", 1) => angle(&args[0]).map(|a| TransformFn::Rotate(a)),
+        _ => None
+    }
+}
+
+/// The value of the innermost counter called `name`. A counter that was never reset is zero.
+fn counter_value(counters: &Counters, name: &str) -> i32 {
+    counters.iter().rev().find(|&&(ref counter, _)| &**counter == name).map_or(0, |&(_, n)| n)
+}
+
+/// Apply the `counter-reset` and `counter-increment` properties of an element.
+///
+/// http://www.w3.org/TR/CSS2/generate.html#counters
+///
+/// A reset creates a new counter, which stays in scope until the end of the element's parent.
+/// Incrementing a counter that isn't in scope resets it first.
+///
+/// List items also increment the `list-item` counter, unless `counter-increment` mentions it.
+fn update_counters(values: &PropertyMap, counters: &mut Counters) {
+    for (name, n) in counter_list(values.get("counter-reset"), 0).into_iter() {
+        counters.push((name, n));
+    }
+    let mut increments = counter_list(values.get("counter-increment"), 1);
+    if is_list_item(values) && !increments.iter().any(|&(ref name, _)| &**name == "list-item") {
+        increments.push(("list-item".to_string(), 1));
+    }
+    for (name, n) in increments.into_iter() {
+        let innermost = counters.iter().rposition(|&(ref counter, _)| *counter == name);
+        match innermost {
+            Some(i) => counters[i].1 = counters[i].1 + n,
+            None => counters.push((name, n))
+        }
+    }
+}
+
+/// Parse a value like `item 2 other`: counter names, each followed by an optional integer.
+fn counter_list(value: Option<&Value>, default: i32) -> Vec<(String, i32)> {
+    let items = match value {
+        Some(&Value::List(ref items)) => items.clone(),
+        Some(value) => vec![value.clone()],
+        None => vec![]
+    };
+    let mut counters: Vec<(String, i32)> = Vec::new();
+    for item in items.iter() {
+        match *item {
+            Keyword(ref name) if &**name != "none" => counters.push((name.clone(), default)),
+            Value::Number(n) => match counters.last_mut() {
+                Some(counter) => counter.1 = n as i32,
+                None => {}
+            },
+            _ => {}
+        }
+    }
+    return counters;
+}
+
+/// How deeply `var()` references may nest, so that cycles like `--a: var(--a)` end.
+const MAX_VAR_DEPTH: usize = 32;
+
+/// Is `name` a custom property, like `--main-color`?
+fn is_custom_property(name: &str) -> bool {
+    name.starts_with("--")
+}
+
+/// Replace each `var()` reference with the value of the custom property it names.
+///
+/// http://www.w3.org/TR/css-variables-1/
+///
+/// Custom properties are always inherited. If a property is undefined, the fallback given as the
+/// second argument of `var()` is used instead. A declaration that still can't be resolved is
+/// dropped, as if it were never set.
+fn resolve_variables(values: &mut PropertyMap, parent_values: Option<&PropertyMap>) {
+    if let Some(parent_values) = parent_values {
+        // The parent's values have already been resolved.
+        for (name, value) in parent_values.iter() {
+            if is_custom_property(&**name) && !values.contains_key(name) {
+                values.insert(name.clone(), value.clone());
+            }
+        }
+    }
+
+    let resolved: Vec<(String, Option<Value>)> = values.iter()
+        .map(|(name, value)| (name.clone(), substitute_variables(value, values, 0)))
+        .collect();
+    for (name, value) in resolved.into_iter() {
+        match value {
+            Some(value) => { values.insert(name, value); }
+            None => { values.remove(&name); }
+        }
+    }
+}
+
+/// `value` with any `var()` references in it replaced, or `None` if one can't be resolved.
+fn substitute_variables(value: &Value, values: &PropertyMap, depth: usize) -> Option<Value> {
+    match *value {
+        Value::Function(ref function, ref args) if &**function == "var" => {
+            if depth > MAX_VAR_DEPTH {
+                return None;
+            }
+            let name = match args.first() {
+                Some(&Keyword(ref name)) if is_custom_property(&**name) => name,
+                _ => return None
+            };
+            match values.get(name) {
+                Some(value) => substitute_variables(value, values, depth + 1),
+                None => args.get(1).and_then(|fallback|
+                    substitute_variables(fallback, values, depth + 1))
+            }
+        }
+        Value::Function(ref function, ref args) => {
+            let mut substituted = Vec::new();
+            for arg in args.iter() {
+                match substitute_variables(arg, values, depth) {
+                    Some(arg) => substituted.push(arg),
+                    None => return None
+                }
+            }
+            Some(Value::Function(function.clone(), substituted))
+        }
+        Value::List(ref items) => {
+            // A variable that holds a list is spliced into the surrounding list.
+            let mut substituted = Vec::new();
+            for item in items.iter() {
+                match substitute_variables(item, values, depth) {
+                    Some(Value::List(inner)) => substituted.extend(inner.into_iter()),
+                    Some(item) => substituted.push(item),
+                    None => return None
+                }
+            }
+            Some(Value::List(substituted))
+        }
+        _ => Some(value.clone())
+    }
+}
+
+/// Copy inherited properties from the parent's values, unless they are already set.
+fn inherit(values: &mut PropertyMap, parent_values: &PropertyMap) {
+    for &name in INHERITED_PROPERTIES.iter() {
+        if values.contains_key(name) {
+            continue;
+        }
+        if let Some(value) = parent_values.get(name) {
+            values.insert(name.to_string(), value.clone());
+        }
     }
 }
 
 /// Apply styles to a single element, returning the specified styles.
 ///
+/// `!important` declarations are applied after all normal declarations, so they win regardless
+/// of specificity.
+///
+/// Cascade layers take precedence over specificity. Normal declarations in later layers win,
+/// and those outside any layer win over all layers. For `!important` declarations this is
+/// reversed.
+///
+/// If `pseudo` is set, this finds the styles of that pseudo-element of `elem` instead.
+///
 /// To do: Allow multiple UA/author/user stylesheets, and implement the cascade.
-fn specified_values(elem: &ElementData, stylesheet: &Stylesheet) -> PropertyMap {
+fn specified_values(elem: &ElementData, stylesheet: &Stylesheet,
+                    pseudo: Option<PseudoElement>) -> PropertyMap {
     let mut values = HashMap::new();
-    let mut rules = matching_rules(elem, stylesheet);
+    let mut rules = matching_rules(elem, stylesheet, pseudo);
+    let layer_count = stylesheet.layers.len();
 
-    // Go through the rules from lowest to highest specificity.
-    rules.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
-    for &(_, rule) in rules.iter() {
-        for declaration in rule.declarations.iter() {
-            values.insert(declaration.name.clone(), declaration.value.clone());
+    for &important in [false, true].iter() {
+        // Go through the rules from lowest to highest precedence. The sort is stable, so rules
+        // that are otherwise equal stay in stylesheet order.
+        let rank = |&: layer: Option<usize>| match (layer, important) {
+            (Some(i), false) => i,
+            (None, false) => layer_count,
+            (Some(i), true) => layer_count - i,
+            (None, true) => 0,
+        };
+        rules.sort_by(|&(a, a_rule), &(b, b_rule)| {
+            (rank(a_rule.layer), a).cmp(&(rank(b_rule.layer), b))
+        });
+        for &(_, rule) in rules.iter() {
+            for declaration in rule.declarations.iter().filter(|d| d.important == important) {
+                apply_declaration(&mut values, declaration);
+            }
         }
     }
     return values;
 }
 
+/// Add a declaration to the cascaded values of an element, replacing any earlier value.
+fn apply_declaration(values: &mut PropertyMap, declaration: &Declaration) {
+    let name = &*declaration.name;
+    if name == "all" {
+        return reset_all(values, &declaration.value);
+    }
+    if name == "gap" {
+        return expand_gap(values, &declaration.value);
+    }
+    if name == "border" {
+        return expand_border(values, &declaration.value);
+    }
+    if name == "inset" {
+        return expand_inset(values, &declaration.value);
+    }
+    if name == "place-items" {
+        return expand_place(values, &declaration.value, "align-items", "justify-items");
+    }
+    if name == "place-content" {
+        return expand_place(values, &declaration.value, "align-content", "justify-content");
+    }
+    // A CSS-wide keyword on a shorthand applies to each of its longhands, so it can be overridden
+    // by later longhand declarations and each longhand is resolved separately.
+    if let Keyword(ref k) = declaration.value {
+        if is_css_wide_keyword(&**k) && PROPERTIES.iter().any(|&l| shorthand(l) == Some(name)) {
+            values.remove(name);
+            for &longhand in PROPERTIES.iter().filter(|&&l| shorthand(l) == Some(name)) {
+                values.insert(longhand.to_string(), declaration.value.clone());
+            }
+            return;
+        }
+    }
+    if let Value::List(ref parts) = declaration.value {
+        if name == "margin" || name == "padding" || name == "border-width" {
+            return expand_edges(values, name, &**parts);
+        }
+    }
+    // A shorthand replaces any of its longhands set by earlier declarations.
+    for &longhand in PROPERTIES.iter() {
+        if shorthand(longhand) == Some(name) {
+            values.remove(longhand);
+        }
+    }
+    values.insert(declaration.name.clone(), declaration.value.clone());
+}
+
+/// Apply the `gap` shorthand, `gap: <row-gap> [<column-gap>]`. A single value sets both gaps.
+fn expand_gap(values: &mut PropertyMap, value: &Value) {
+    let (row, column) = match *value {
+        Value::List(ref parts) if parts.len() == 2 => (parts[0].clone(), parts[1].clone()),
+        Value::List(_) => return, // Invalid.
+        _ => (value.clone(), value.clone())
+    };
+    values.insert("row-gap".to_string(), row);
+    values.insert("column-gap".to_string(), column);
+}
+
+/// Apply the `inset` shorthand to `top`, `right`, `bottom`, and `left`. Like `margin`, it takes
+/// `<all>`, `<vertical> <horizontal>`, `<top> <horizontal> <bottom>`, or
+/// `<top> <right> <bottom> <left>`.
+fn expand_inset(values: &mut PropertyMap, value: &Value) {
+    let parts = value_list(Some(value.clone()));
+    let (top, right, bottom, left) = match parts.len() {
+        1 => (0, 0, 0, 0),
+        2 => (0, 1, 0, 1),
+        3 => (0, 1, 2, 1),
+        4 => (0, 1, 2, 3),
+        _ => return // Invalid.
+    };
+    for &(name, i) in [("top", top), ("right", right), ("bottom", bottom), ("left", left)].iter() {
+        values.insert(name.to_string(), parts[i].clone());
+    }
+}
+
+/// Apply the `place-items` or `place-content` shorthand, `<align> [<justify>]`, to the `align`
+/// and `justify` longhands. A single value sets both.
+fn expand_place(values: &mut PropertyMap, value: &Value, align: &str, justify: &str) {
+    let (align_value, justify_value) = match *value {
+        Value::List(ref parts) if parts.len() == 2 => (parts[0].clone(), parts[1].clone()),
+        Value::List(_) => return, // Invalid.
+        _ => (value.clone(), value.clone())
+    };
+    values.insert(align.to_string(), align_value);
+    values.insert(justify.to_string(), justify_value);
+}
+
+/// Apply the `border` shorthand, `border: <width> <style> <color>` in any order, to the width of
+/// each side and to `border-color`. An omitted color is `currentcolor`, and an omitted width is
+/// `medium`.
+///
+/// Border styles aren't drawn, but without a style (or with `none` or `hidden`) the border has
+/// no width, as in CSS.
+fn expand_border(values: &mut PropertyMap, value: &Value) {
+    values.remove("border-width");
+    let sides = ["top", "right", "bottom", "left"];
+    // A CSS-wide keyword is copied to each longhand, for `resolve_css_wide_keywords`.
+    if let Keyword(ref k) = *value {
+        if is_css_wide_keyword(&**k) {
+            for &side in sides.iter() {
+                values.insert(format!("border-{}-width", side), value.clone());
+            }
+            values.insert("border-color".to_string(), value.clone());
+            return;
+        }
+    }
+    let mut width = Length(3.0, Px);
+    let mut color = Keyword("currentcolor".to_string());
+    let mut visible = false;
+    for part in value_list(Some(value.clone())).iter() {
+        match *part {
+            Length(..) | Value::Function(..) => width = part.clone(),
+            Value::Number(n) if n == 0.0 => width = Length(0.0, Px),
+            ColorValue(_) => color = part.clone(),
+            Keyword(ref k) => match &*k.to_ascii_lowercase() {
+                "thin" => width = Length(1.0, Px),
+                "medium" => width = Length(3.0, Px),
+                "thick" => width = Length(5.0, Px),
+                "currentcolor" => color = part.clone(),
+                "none" | "hidden" => visible = false,
+                _ => visible = true
+            },
+            _ => {}
+        }
+    }
+    if !visible {
+        width = Length(0.0, Px);
+    }
+    for &side in sides.iter() {
+        values.insert(format!("border-{}-width", side), width.clone());
+    }
+    values.insert("border-color".to_string(), color);
+}
+
+/// Apply the `all` shorthand, which resets every property except `direction` and custom
+/// properties.
+///
+/// Its value is one of the CSS-wide keywords. It is copied to every known property, for
+/// `resolve_css_wide_keywords` to resolve later.
+fn reset_all(values: &mut PropertyMap, value: &Value) {
+    match *value {
+        Keyword(ref k) if is_css_wide_keyword(&**k) => {}
+        _ => return
+    }
+
+    let reset: Vec<String> = values.keys().filter(|name| {
+        &***name != "direction" && !name.starts_with("--")
+    }).map(|name| name.clone()).collect();
+    for name in reset.iter() {
+        values.remove(&**name);
+    }
+
+    for &name in PROPERTIES.iter() {
+        if name != "direction" {
+            values.insert(name.to_string(), value.clone());
+        }
+    }
+}
+
+/// Apply a `margin`, `padding`, or `border-width` shorthand with more than one value to the
+/// longhand for each side. It takes `<vertical> <horizontal>`, `<top> <horizontal> <bottom>`, or
+/// `<top> <right> <bottom> <left>`.
+///
+/// A single value is kept on the shorthand, where `StyledNode::lookup` finds it.
+fn expand_edges(values: &mut PropertyMap, name: &str, parts: &[Value]) {
+    let (top, right, bottom, left) = match parts.len() {
+        2 => (0, 1, 0, 1),
+        3 => (0, 1, 2, 1),
+        4 => (0, 1, 2, 3),
+        _ => return // Invalid.
+    };
+    values.remove(name);
+    for &(side, i) in [("top", top), ("right", right), ("bottom", bottom), ("left", left)].iter() {
+        let longhand = match name {
+            "border-width" => format!("border-{}-width", side),
+            _ => format!("{}-{}", name, side)
+        };
+        values.insert(longhand, parts[i].clone());
+    }
+}
+
+fn is_css_wide_keyword(keyword: &str) -> bool {
+    match keyword {
+        "initial" | "inherit" | "unset" => true,
+        _ => false
+    }
+}
+
+fn is_inherited(name: &str) -> bool {
+    INHERITED_PROPERTIES.iter().any(|&inherited| inherited == name)
+}
+
+/// Replace the keywords `initial`, `inherit`, and `unset` with the values they stand for.
+///
+/// `unset` acts as `inherit` for inherited properties, and as `initial` for all others. A longhand
+/// inherits the parent's value of the longhand, or of its shorthand if only that was set.
+///
+/// Shorthands set to one of these keywords were already expanded by `apply_declaration`.
+fn resolve_css_wide_keywords(values: &mut PropertyMap, parent_values: Option<&PropertyMap>) {
+    let names: Vec<String> = values.keys().map(|name| name.clone()).collect();
+    for name in names.iter() {
+        let inherit = match values.get(&**name) {
+            Some(&Keyword(ref k)) => match &**k {
+                "inherit" => true,
+                "initial" => false,
+                "unset" => is_inherited(&**name),
+                _ => continue
+            },
+            _ => continue
+        };
+        values.remove(&**name);
+
+        if inherit {
+            let parent_value = parent_values.and_then(|parent| {
+                parent.get(&**name).or_else(|| shorthand(&**name).and_then(|s| parent.get(s)))
+            });
+            if let Some(value) = parent_value {
+                values.insert(name.clone(), value.clone());
+                continue;
+            }
+            // If the parent doesn't have a value either, it has the initial value.
+        }
+
+        if let Some(value) = initial_value(&**name) {
+            values.insert(name.clone(), value);
+        }
+    }
+}
+
 /// A single CSS rule and the specificity of its most specific matching selector.
 type MatchedRule<'a> = (Specificity, &'a Rule);
 
 /// Find all CSS rules that match the given element.
-fn matching_rules<'a>(elem: &ElementData, stylesheet: &'a Stylesheet) -> Vec<MatchedRule<'a>> {
+fn matching_rules<'a>(elem: &ElementData, stylesheet: &'a Stylesheet,
+                      pseudo: Option<PseudoElement>) -> Vec<MatchedRule<'a>> {
     // For now, we just do a linear scan of all the rules.  For large
     // documents, it would be more efficient to store the rules in hash tables
     // based on tag name, id, class, etc.
-    stylesheet.rules.iter().filter_map(|rule| match_rule(elem, rule)).collect()
+    stylesheet.rules.iter().filter_map(|rule| match_rule(elem, rule, pseudo)).collect()
+}
+
+/// The selectors that match `elem`, in stylesheet order: the most specific matching selector of
+/// each rule that applies to it.
+pub fn matched_selectors<'a>(elem: &ElementData, stylesheet: &'a Stylesheet) -> Vec<&'a Selector> {
+    stylesheet.rules.iter().filter_map(|rule| {
+        rule.selectors.iter().find(|selector| matches(elem, *selector, None))
+    }).collect()
 }
 
 /// If `rule` matches `elem`, return a `MatchedRule`. Otherwise return `None`.
-fn match_rule<'a>(elem: &ElementData, rule: &'a Rule) -> Option<MatchedRule<'a>> {
+fn match_rule<'a>(elem: &ElementData, rule: &'a Rule,
+                  pseudo: Option<PseudoElement>) -> Option<MatchedRule<'a>> {
     // Find the first (most specific) matching selector.
-    rule.selectors.iter().find(|selector| matches(elem, *selector))
+    rule.selectors.iter().find(|selector| matches(elem, *selector, pseudo))
         .map(|selector| (selector.specificity(), rule))
 }
 
 /// Selector matching:
-fn matches(elem: &ElementData, selector: &Selector) -> bool {
+fn matches(elem: &ElementData, selector: &Selector, pseudo: Option<PseudoElement>) -> bool {
     match *selector {
-        Selector::Simple(ref simple_selector) => matches_simple_selector(elem, simple_selector)
+        Selector::Simple(ref simple_selector) =>
+            matches_simple_selector(elem, simple_selector, pseudo)
     }
 }
 
-fn matches_simple_selector(elem: &ElementData, selector: &SimpleSelector) -> bool {
+fn matches_simple_selector(elem: &ElementData, selector: &SimpleSelector,
+                           pseudo: Option<PseudoElement>) -> bool {
+    // Check pseudo-element
+    if selector.pseudo_element.as_ref().map(|name| &**name) != pseudo.map(|p| p.name()) {
+        return false;
+    }
+
     // Check type selector
     if selector.tag_name.iter().any(|name| elem.tag_name != *name) {
         return false;
@@ -127,3 +2245,386 @@ fn matches_simple_selector(elem: &ElementData, selector: &SimpleSelector) -> boo
     // We didn't find any non-matching selector components.
     return true;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::counter_text;
+    use css::{self, Stylesheet, Value, Color};
+    use css::Value::{Keyword, Length, ColorValue};
+    use css::Unit::Px;
+    use dom::Node;
+    use html;
+
+    /// Style a tree with the default 16px root font size and 96px per inch, for an 800x600
+    /// viewport.
+    fn style<'a>(root: &'a Node, stylesheet: &'a Stylesheet) -> StyledNode<'a> {
+        style_tree(root, stylesheet, 16.0, (800.0, 600.0), 96.0)
+    }
+
+    #[test]
+    fn accessors_default_for_unstyled_elements() {
+        let root = html::parse("<div></div>".to_string());
+        let stylesheet = css::parse(String::new());
+        let styled = style(&root, &stylesheet);
+
+        assert!(styled.position() == Position::Static);
+        assert!(styled.float() == Float::None);
+        assert!(styled.overflow() == Overflow::Visible);
+        assert!(styled.box_sizing() == BoxSizing::ContentBox);
+    }
+
+    #[test]
+    fn accessors_read_keywords() {
+        let root = html::parse("<div></div>".to_string());
+        let stylesheet = css::parse("div { position: absolute; float: right; overflow: hidden; \
+                                     box-sizing: border-box; }".to_string());
+        let styled = style(&root, &stylesheet);
+
+        assert!(styled.position() == Position::Absolute);
+        assert!(styled.float() == Float::Right);
+        assert!(styled.overflow() == Overflow::Hidden);
+        assert!(styled.box_sizing() == BoxSizing::BorderBox);
+    }
+
+    #[test]
+    fn accessors_ignore_unknown_keywords() {
+        let root = html::parse("<div></div>".to_string());
+        let stylesheet = css::parse("div { position: sticky; float: center; }".to_string());
+        let styled = style(&root, &stylesheet);
+
+        assert!(styled.position() == Position::Static);
+        assert!(styled.float() == Float::None);
+    }
+
+    #[test]
+    fn resize_and_user_select_accessors() {
+        let root = html::parse("<div><p></p><span></span></div>".to_string());
+        let stylesheet = css::parse("p { resize: vertical; user-select: none; } \
+                                     span { resize: diagonal; user-select: all; }".to_string());
+        let styled = style(&root, &stylesheet);
+        assert_eq!(styled.resize(), Resize::None);
+        assert_eq!(styled.user_select(), UserSelect::Auto);
+        assert_eq!(styled.children[0].resize(), Resize::Vertical);
+        assert_eq!(styled.children[0].user_select(), UserSelect::None);
+        // Unknown keywords fall back to the defaults.
+        assert_eq!(styled.children[1].resize(), Resize::None);
+        assert_eq!(styled.children[1].user_select(), UserSelect::Auto);
+    }
+
+    fn red() -> Value {
+        ColorValue(Color { r: 255, g: 0, b: 0, a: 255 })
+    }
+
+    #[test]
+    fn all_initial_resets_earlier_declarations() {
+        let root = html::parse("<div><p></p></div>".to_string());
+        let stylesheet = css::parse("div { color: #ff0000; } \
+                                     p { width: 10px; color: #0000ff; direction: rtl; \
+                                         all: initial; }".to_string());
+        let styled = style(&root, &stylesheet);
+        let p = &styled.children[0];
+
+        assert_eq!(p.value("width"), Some(Keyword("auto".to_string())));
+        // Inherited properties are reset too, instead of inheriting.
+        assert_eq!(p.value("color"), Some(ColorValue(Color { r: 0, g: 0, b: 0, a: 255 })));
+        assert_eq!(p.value("font-size"), Some(Length(16.0, Px)));
+        // `all` never resets `direction`.
+        assert_eq!(p.value("direction"), Some(Keyword("rtl".to_string())));
+    }
+
+    #[test]
+    fn all_unset_inherits_inherited_properties() {
+        let root = html::parse("<div><p></p></div>".to_string());
+        let stylesheet = css::parse("div { color: #ff0000; } \
+                                     p { width: 10px; color: #0000ff; all: unset; }".to_string());
+        let styled = style(&root, &stylesheet);
+        let p = &styled.children[0];
+
+        assert_eq!(p.value("width"), Some(Keyword("auto".to_string())));
+        assert_eq!(p.value("color"), Some(red()));
+    }
+
+    #[test]
+    fn declarations_after_all_still_apply() {
+        let root = html::parse("<p></p>".to_string());
+        let stylesheet = css::parse("p { all: initial; width: 10px; }".to_string());
+        let styled = style(&root, &stylesheet);
+
+        assert_eq!(styled.value("width"), Some(Length(10.0, Px)));
+        assert_eq!(styled.value("display"), Some(Keyword("inline".to_string())));
+    }
+
+    #[test]
+    fn css_wide_keywords_resolve_per_property() {
+        let root = html::parse("<div><p></p></div>".to_string());
+        let stylesheet = css::parse("div { color: #ff0000; width: 10px; height: 20px; } \
+                                     p { color: initial; width: inherit; height: unset; \
+                                         font-size: unset; }".to_string());
+        let styled = style(&root, &stylesheet);
+        let p = &styled.children[0];
+
+        assert_eq!(p.value("color"), Some(ColorValue(Color { r: 0, g: 0, b: 0, a: 255 })));
+        assert_eq!(p.value("width"), Some(Length(10.0, Px)));
+        // `unset` is `initial` for height, and `inherit` for font-size.
+        assert_eq!(p.value("height"), Some(Keyword("auto".to_string())));
+        assert_eq!(p.value("font-size"), Some(Length(16.0, Px)));
+    }
+
+    #[test]
+    fn css_wide_keyword_on_shorthand_sets_each_longhand() {
+        let root = html::parse("<div><p></p></div>".to_string());
+        let stylesheet = css::parse("div { margin: 5px; } \
+                                     p { margin: inherit; margin-left: 1px; }".to_string());
+        let styled = style(&root, &stylesheet);
+        let p = &styled.children[0];
+
+        // The parent only set the shorthand, so each longhand inherits from it.
+        assert_eq!(p.value("margin-top"), Some(Length(5.0, Px)));
+        assert_eq!(p.value("margin-left"), Some(Length(1.0, Px)));
+        assert_eq!(p.value("margin"), None);
+    }
+
+    fn layered_color(css: &str) -> Option<Value> {
+        let root = html::parse("<div id=\"x\"></div>".to_string());
+        let stylesheet = css::parse(css.to_string());
+        style(&root, &stylesheet).value("color")
+    }
+
+    #[test]
+    fn later_layers_beat_specificity() {
+        let blue = ColorValue(Color { r: 0, g: 0, b: 255, a: 255 });
+        assert_eq!(layered_color("@layer base, theme; \
+                                  @layer theme { div { color: #ff0000; } } \
+                                  @layer base { #x { color: #0000ff; } }"), Some(red()));
+        // Rules outside any layer win over all layers.
+        assert_eq!(layered_color("@layer theme { #x { color: #ff0000; } } \
+                                  div { color: #0000ff; }"), Some(blue));
+        // For `!important` declarations the order is reversed.
+        assert_eq!(layered_color("@layer base, theme; \
+                                  @layer base { div { color: #ff0000 !important; } } \
+                                  @layer theme { div { color: #0000ff !important; } } \
+                                  div { color: #00ff00 !important; }"), Some(red()));
+    }
+
+    #[test]
+    fn important_beats_specificity() {
+        let root = html::parse("<p id=\"x\"></p>".to_string());
+        let stylesheet = css::parse("p { display: none !important; width: 1px; } \
+                                     #x { display: block; width: 2px; }".to_string());
+        let styled = style(&root, &stylesheet);
+
+        assert_eq!(styled.value("display"), Some(Keyword("none".to_string())));
+        assert_eq!(styled.value("width"), Some(Length(2.0, Px)));
+    }
+
+    #[test]
+    fn edge_shorthands_expand_to_each_side() {
+        let root = html::parse("<p></p>".to_string());
+        let stylesheet = css::parse("p { margin: 5px; margin: 1px 2px 3px; padding: 0 auto; \
+                                     border-width: 1px 2px 3px 4px; }".to_string());
+        let styled = style(&root, &stylesheet);
+
+        assert_eq!(styled.value("margin"), None);
+        assert_eq!(styled.value("margin-top"), Some(Length(1.0, Px)));
+        assert_eq!(styled.value("margin-right"), Some(Length(2.0, Px)));
+        assert_eq!(styled.value("margin-bottom"), Some(Length(3.0, Px)));
+        assert_eq!(styled.value("margin-left"), Some(Length(2.0, Px)));
+        assert_eq!(styled.value("padding-top"), Some(Value::Number(0.0)));
+        assert_eq!(styled.value("padding-left"), Some(Keyword("auto".to_string())));
+        assert_eq!(styled.value("border-left-width"), Some(Length(4.0, Px)));
+    }
+
+    #[test]
+    fn border_shorthand_defaults_to_the_current_color() {
+        let root = html::parse("<div><p></p><span></span></div>".to_string());
+        let stylesheet = css::parse("div { color: #ff0000; border: 2px solid; } \
+                                     p { border: dotted #0000ff; } span { border: 4px; }"
+                                    .to_string());
+        let styled = style(&root, &stylesheet);
+        assert_eq!(styled.value("border-left-width"), Some(Length(2.0, Px)));
+        assert_eq!(styled.border_color(), Some(Color { r: 255, g: 0, b: 0, a: 255 }));
+
+        let p = &styled.children[0];
+        assert_eq!(p.value("border-top-width"), Some(Length(3.0, Px)));
+        assert_eq!(p.border_color(), Some(Color { r: 0, g: 0, b: 255, a: 255 }));
+        // Without a style there is no border.
+        assert_eq!(styled.children[1].value("border-top-width"), Some(Length(0.0, Px)));
+    }
+
+    #[test]
+    fn inset_expands_like_margin() {
+        let root = html::parse("<div><p></p><span></span></div>".to_string());
+        let stylesheet = css::parse("div { inset: 1px 2px 3px; } p { inset: 5px; left: auto; } \
+                                     span { inset: 1px 2px 3px 4px 5px; }".to_string());
+        let styled = style(&root, &stylesheet);
+        assert_eq!(styled.value("top"), Some(Length(1.0, Px)));
+        assert_eq!(styled.value("right"), Some(Length(2.0, Px)));
+        assert_eq!(styled.value("bottom"), Some(Length(3.0, Px)));
+        assert_eq!(styled.value("left"), Some(Length(2.0, Px)));
+
+        let p = &styled.children[0];
+        assert_eq!(p.value("top"), Some(Length(5.0, Px)));
+        assert_eq!(p.value("bottom"), Some(Length(5.0, Px)));
+        assert_eq!(p.value("left"), Some(Keyword("auto".to_string())));
+        // Five values are invalid.
+        assert_eq!(styled.children[1].value("top"), Some(Keyword("auto".to_string())));
+    }
+
+    #[test]
+    fn place_shorthands_set_align_then_justify() {
+        let root = html::parse("<div><p></p><span></span></div>".to_string());
+        let stylesheet = css::parse("div { place-items: center start; place-content: end; } \
+                                     p { place-items: start end center; } \
+                                     span { place-items: inherit; justify-items: end; }"
+                                    .to_string());
+        let styled = style(&root, &stylesheet);
+        let keyword = |s: &str| Some(Keyword(s.to_string()));
+        assert_eq!(styled.value("align-items"), keyword("center"));
+        assert_eq!(styled.value("justify-items"), keyword("start"));
+        // A single value sets both.
+        assert_eq!(styled.value("align-content"), keyword("end"));
+        assert_eq!(styled.value("justify-content"), keyword("end"));
+        // Three values are invalid, so the longhands keep their initial values.
+        let p = &styled.children[0];
+        assert_eq!(p.value("align-items"), keyword("normal"));
+        assert_eq!(p.value("justify-items"), keyword("legacy"));
+        // A CSS-wide keyword applies to each longhand, so later longhands override it.
+        let span = &styled.children[1];
+        assert_eq!(span.value("align-items"), keyword("center"));
+        assert_eq!(span.value("justify-items"), keyword("end"));
+    }
+
+    #[test]
+    fn later_longhand_overrides_expanded_shorthand() {
+        let root = html::parse("<p></p>".to_string());
+        let stylesheet = css::parse("p { margin: 1px 2px; margin-left: 7px; }".to_string());
+        let styled = style(&root, &stylesheet);
+
+        assert_eq!(styled.value("margin-left"), Some(Length(7.0, Px)));
+        assert_eq!(styled.value("margin-right"), Some(Length(2.0, Px)));
+    }
+
+    #[test]
+    fn em_and_rem_resolve_against_font_sizes() {
+        let root = html::parse("<div><p></p></div>".to_string());
+        let stylesheet = css::parse("div { font-size: 2em; } \
+                                     p { font-size: 0.5em; width: 2em; margin-left: 0.25rem; }"
+                                    .to_string());
+        let styled = style_tree(&root, &stylesheet, 20.0, (800.0, 600.0), 96.0);
+        // The root's `em` is relative to the given root font size.
+        assert_eq!(styled.font_size(), 40.0);
+        let p = &styled.children[0];
+        assert_eq!(p.font_size(), 20.0);
+        assert_eq!(p.value("width"), Some(Length(40.0, Px)));
+        // Below the root, `rem` is relative to the root element's font size.
+        assert_eq!(p.value("margin-left"), Some(Length(10.0, Px)));
+    }
+
+    #[test]
+    fn root_font_size_applies_without_a_font_size() {
+        let root = html::parse("<div><p></p></div>".to_string());
+        let stylesheet = css::parse("div { width: 2rem; } p { width: 1em; }".to_string());
+        let styled = style_tree(&root, &stylesheet, 20.0, (800.0, 600.0), 96.0);
+        assert_eq!(styled.font_size(), 20.0);
+        assert_eq!(styled.value("width"), Some(Length(40.0, Px)));
+        assert_eq!(styled.children[0].value("width"), Some(Length(20.0, Px)));
+    }
+
+    /// The computed `width` of a `<p>` with the given value, in a viewport of the given size.
+    fn width_in_viewport(width: &str, viewport: (f32, f32)) -> Option<Value> {
+        let root = html::parse("<p></p>".to_string());
+        let stylesheet = css::parse(format!("p {{ width: {}; }}", width));
+        let styled = style_tree(&root, &stylesheet, 16.0, viewport, 96.0);
+        styled.value("width")
+    }
+
+    #[test]
+    fn viewport_units_resolve_to_px() {
+        assert_eq!(width_in_viewport("50vw", (800.0, 600.0)), Some(Length(400.0, Px)));
+        assert_eq!(width_in_viewport("10vh", (800.0, 600.0)), Some(Length(60.0, Px)));
+    }
+
+    #[test]
+    fn physical_units_resolve_at_the_given_resolution() {
+        let viewport = (800.0, 600.0);
+        assert_eq!(width_in_viewport("1in", viewport), Some(Length(96.0, Px)));
+        assert_eq!(width_in_viewport("72pt", viewport), Some(Length(96.0, Px)));
+        assert_eq!(width_in_viewport("6pc", viewport), Some(Length(96.0, Px)));
+
+        let root = html::parse("<p></p>".to_string());
+        let stylesheet = css::parse("p { width: 1in; height: 36pt; }".to_string());
+        let styled = style_tree(&root, &stylesheet, 16.0, viewport, 300.0);
+        assert_eq!(styled.value("width"), Some(Length(300.0, Px)));
+        assert_eq!(styled.value("height"), Some(Length(150.0, Px)));
+    }
+
+    #[test]
+    fn clamp_keeps_the_preferred_value_between_the_bounds() {
+        let clamp = "clamp(100px, 50vw, 300px)";
+        assert_eq!(width_in_viewport(clamp, (400.0, 600.0)), Some(Length(200.0, Px)));
+        assert_eq!(width_in_viewport(clamp, (800.0, 600.0)), Some(Length(300.0, Px)));
+        assert_eq!(width_in_viewport(clamp, (100.0, 600.0)), Some(Length(100.0, Px)));
+        // The bounds may be relative lengths too.
+        assert_eq!(width_in_viewport("clamp(1em, 1px, 2rem)", (800.0, 600.0)),
+                   Some(Length(16.0, Px)));
+    }
+
+    /// The text generated for pseudo-elements in and under `styled`, in document order.
+    fn generated_texts(styled: &StyledNode) -> Vec<String> {
+        let mut texts = Vec::new();
+        if let Some(ref text) = styled.generated_text {
+            texts.push(text.clone());
+        }
+        for child in styled.children.iter() {
+            texts.extend(generated_texts(child).into_iter());
+        }
+        texts
+    }
+
+    fn quote_texts(extra_css: &str) -> Vec<String> {
+        let root = html::parse("<div><q><q></q></q><q></q></div>".to_string());
+        let stylesheet = css::parse(format!("q::before {{ content: open-quote; }} \
+                                             q::after {{ content: close-quote; }} {}", extra_css));
+        generated_texts(&style(&root, &stylesheet))
+    }
+
+    #[test]
+    fn quotes_nest_by_depth() {
+        assert_eq!(quote_texts(""), vec!["\"", "'", "'", "\"", "\"", "\""]);
+        assert_eq!(quote_texts("div { quotes: \"<<\" \">>\" \"<\" \">\"; }"),
+                   vec!["<<", "<", ">", ">>", "<<", ">>"]);
+        // Levels deeper than the list reuse its last pair.
+        assert_eq!(quote_texts("div { quotes: \"[\" \"]\"; }"),
+                   vec!["[", "[", "]", "]", "[", "]"]);
+    }
+
+    #[test]
+    fn unmatched_close_quotes_generate_nothing() {
+        let root = html::parse("<div><p></p></div>".to_string());
+        let stylesheet = css::parse("p::before { content: close-quote \"x\"; }".to_string());
+        assert_eq!(generated_texts(&style(&root, &stylesheet)), vec!["x"]);
+    }
+
+    #[test]
+    fn counters_are_written_in_each_list_style() {
+        assert_eq!(&*counter_text(4, "lower-roman"), "iv");
+        assert_eq!(&*counter_text(1994, "upper-roman"), "MCMXCIV");
+        assert_eq!(&*counter_text(1, "lower-alpha"), "a");
+        assert_eq!(&*counter_text(26, "lower-alpha"), "z");
+        assert_eq!(&*counter_text(27, "lower-latin"), "aa");
+        assert_eq!(&*counter_text(28, "upper-alpha"), "AB");
+        // Values the style can't show, and unknown styles, fall back to decimal.
+        assert_eq!(&*counter_text(0, "lower-alpha"), "0");
+        assert_eq!(&*counter_text(4000, "upper-roman"), "4000");
+        assert_eq!(&*counter_text(3, "hebrew"), "3");
+    }
+
+    #[test]
+    fn list_items_get_numbered_markers() {
+        let root = html::parse("<ol><li></li><li></li><li></li></ol>".to_string());
+        let stylesheet = css::parse("li { display: list-item; list-style-type: upper-roman; }"
+                                        .to_string());
+        assert_eq!(generated_texts(&style(&root, &stylesheet)), vec!["I. ", "II. ", "III. "]);
+    }
+}
@@ -0,0 +1,91 @@
+//! Export layout trees as SVG documents, with a rectangle for each box's background and borders.
+//!
+//! Unlike `painting`, this keeps the structure of the layout tree: each box becomes a `<g>`
+//! group containing its own rectangles and the groups of its children.
+//!
+//! TODO: Text, images, outlines, and transforms.
+
+use css::Color;
+use layout::{LayoutBox, Rect};
+use painting::{get_color, get_border_color, border_rects};
+
+/// Serialize a layout tree as an SVG document the size of the root's margin box.
+pub fn to_svg(layout_root: &LayoutBox) -> String {
+    let bounds = layout_root.dimensions.margin_box();
+    let mut output = format!(
+        concat!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" ",
+                "viewBox=\"{} {} {} {}\">\n"),
+        bounds.width, bounds.height, bounds.x, bounds.y, bounds.width, bounds.height);
+    write_layout_box(&mut output, layout_root, 1);
+    output.push_str("</svg>\n");
+    return output;
+}
+
+fn write_layout_box(output: &mut String, layout_box: &LayoutBox, depth: usize) {
+    let indent: String = (0 .. depth).map(|_| "  ").collect();
+    output.push_str(&*format!("{}<g>\n", indent));
+
+    if let Some(color) = get_color(layout_box, "background") {
+        for &rect in layout_box.background_rects().iter() {
+            write_rect(output, &*indent, color, rect);
+        }
+    }
+    if let Some(color) = get_border_color(layout_box) {
+        for &rect in border_rects(&layout_box.dimensions).iter() {
+            if rect.width > 0.0 && rect.height > 0.0 {
+                write_rect(output, &*indent, color, rect);
+            }
+        }
+    }
+    if !layout_box.skips_contents() {
+        for child in layout_box.children.iter() {
+            write_layout_box(output, child, depth + 1);
+        }
+    }
+
+    output.push_str(&*format!("{}</g>\n", indent));
+}
+
+fn write_rect(output: &mut String, indent: &str, color: Color, rect: Rect) {
+    output.push_str(&*format!(
+        "{}  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"rgb({},{},{})\"",
+        indent, rect.x, rect.y, rect.width, rect.height, color.r, color.g, color.b));
+    if color.a < 255 {
+        output.push_str(&*format!(" fill-opacity=\"{}\"", color.a as f32 / 255.0));
+    }
+    output.push_str("/>\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::default::Default;
+    use css;
+    use html;
+    use layout::{layout_tree, Dimensions};
+    use style::style_tree;
+
+    #[test]
+    fn boxes_become_nested_groups_of_rects() {
+        let root = html::parse("<div><p></p></div>".to_string());
+        let stylesheet = css::parse(
+            "div { display: block; width: 100px; height: 50px; background: #ff0000; } \
+             p { display: block; height: 10px; background: rgba(0, 0, 255, 0.2); }".to_string());
+        let styled = style_tree(&root, &stylesheet, 16.0, (800.0, 600.0), 96.0);
+        let mut viewport: Dimensions = Default::default();
+        viewport.content.width = 800.0;
+        viewport.content.height = 600.0;
+
+        assert_eq!(&*to_svg(&layout_tree(&styled, viewport)), concat!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"800\" height=\"50\" ",
+            "viewBox=\"0 0 800 50\">\n",
+            "  <g>\n",
+            "    <rect x=\"0\" y=\"0\" width=\"100\" height=\"50\" fill=\"rgb(255,0,0)\"/>\n",
+            "    <g>\n",
+            "      <rect x=\"0\" y=\"0\" width=\"100\" height=\"10\" fill=\"rgb(0,0,255)\" ",
+            "fill-opacity=\"0.2\"/>\n",
+            "    </g>\n",
+            "  </g>\n",
+            "</svg>\n"));
+    }
+}